@@ -0,0 +1,170 @@
+//! Recording of the rendered frames, either as a numbered sequence of PNG
+//! images or as a single uncompressed Y4M video.
+
+use std::fs::{self, File};
+use std::io;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use drawing::Framebuffer;
+use files::png;
+use files::y4m::Y4mWriter;
+
+pub const DEFAULT_CAPTURE_FPS: u32 = 30;
+
+enum Target {
+    /// Directory the numbered images are written to.
+    Images(PathBuf),
+    Video(Y4mWriter<BufWriter<File>>),
+}
+
+/// Writes every presented frame to disk. Captured frames are meant to be
+/// played back at a fixed rate, so the host should advance its clock by
+/// `frame_duration` per frame while capturing, no matter how long rendering
+/// actually takes.
+pub struct Capture {
+    target: Target,
+    fps: u32,
+    frames: u64,
+}
+
+impl Capture {
+    /// Captures to `dir/frame00000.png`, `dir/frame00001.png`, ... The
+    /// images are palettized, using the framebuffer's palette.
+    pub fn png_sequence<P>(dir: P, fps: u32) -> io::Result<Capture>
+        where P: AsRef<Path>
+    {
+        try!(check_fps(fps));
+        try!(fs::create_dir_all(dir.as_ref()));
+        Ok(Capture {
+            target: Target::Images(dir.as_ref().to_path_buf()),
+            fps: fps,
+            frames: 0,
+        })
+    }
+
+    /// Captures to a Y4M video file of the given frame size.
+    pub fn y4m<P>(path: P, width: usize, height: usize, fps: u32) -> io::Result<Capture>
+        where P: AsRef<Path>
+    {
+        try!(check_fps(fps));
+        let file = BufWriter::new(try!(File::create(path)));
+        let writer = try!(Y4mWriter::new(file, width, height, fps));
+        Ok(Capture {
+            target: Target::Video(writer),
+            fps: fps,
+            frames: 0,
+        })
+    }
+
+    /// Simulated time between two captured frames.
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_nanos(1_000_000_000 / self.fps as u64)
+    }
+
+    /// Number of frames written so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Writes the current contents of the framebuffer. Must be called after
+    /// `Framebuffer::swap_buffers`, since videos use the color buffer.
+    pub fn write_frame(&mut self, framebuffer: &Framebuffer) -> io::Result<()> {
+        match self.target {
+            Target::Images(ref dir) => {
+                let path = dir.join(format!("frame{:05}.png", self.frames));
+                let mut file = BufWriter::new(try!(File::create(path)));
+                try!(png::write_indexed(&mut file,
+                                        framebuffer.width() as u32,
+                                        framebuffer.height() as u32,
+                                        &framebuffer.palette().to_bytes(),
+                                        framebuffer.pixels()));
+            }
+            Target::Video(ref mut writer) => {
+                try!(writer.write_bgrx(framebuffer.color_buffer()));
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self.target {
+            Target::Video(ref mut writer) => writer.flush(),
+            Target::Images(_) => Ok(()),
+        }
+    }
+}
+
+/// Frame rates of 0 have no frame duration.
+fn check_fps(fps: u32) -> io::Result<()> {
+    if fps == 0 {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "capture frame rate must not be 0"))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io;
+    use std::io::prelude::*;
+    use std::time::Duration;
+    use drawing::{Framebuffer, Palette};
+    use files::testing::temp_path;
+    use super::Capture;
+
+    fn framebuffer() -> Framebuffer {
+        let mut fb = Framebuffer::with_palette(4, 2, Palette::from_bytes(&[0; 768]));
+        fb.fill(1);
+        fb.swap_buffers();
+        fb
+    }
+
+    #[test]
+    fn png_sequence() {
+        let dir = temp_path("capture-png");
+        let mut capture = Capture::png_sequence(&dir, 25).unwrap();
+        assert_eq!(capture.frame_duration(), Duration::from_millis(40));
+        let fb = framebuffer();
+        capture.write_frame(&fb).unwrap();
+        capture.write_frame(&fb).unwrap();
+        assert_eq!(capture.frames(), 2);
+        assert!(dir.join("frame00000.png").exists());
+        assert!(dir.join("frame00001.png").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn zero_fps() {
+        let path = temp_path("capture-zero-fps");
+        for result in &[Capture::png_sequence(&path, 0), Capture::y4m(&path, 4, 2, 0)] {
+            match *result {
+                Err(ref e) if e.kind() == io::ErrorKind::InvalidInput => {}
+                _ => panic!("a frame rate of 0 was accepted"),
+            }
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn y4m_video() {
+        let path = temp_path("capture.y4m");
+        {
+            let mut capture = Capture::y4m(&path, 4, 2, 30).unwrap();
+            let fb = framebuffer();
+            for _ in 0..3 {
+                capture.write_frame(&fb).unwrap();
+            }
+            capture.finish().unwrap();
+        }
+        let mut bytes = vec![];
+        File::open(&path).unwrap().read_to_end(&mut bytes).unwrap();
+        let header = "YUV4MPEG2 W4 H2 F30:1 Ip A1:1 C420jpeg\n".len();
+        let frame = "FRAME\n".len() + 8 + 2 + 2;
+        assert_eq!(bytes.len(), header + 3 * frame);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,254 @@
+//! Translucency for the palettized framebuffer. Since pixels are palette
+//! indices, two colors can't be mixed directly. Instead, a 256x256 lookup
+//! table is precomputed for every alpha value in use, mapping a pair of
+//! (source, destination) indices to the palette entry closest to their mix.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use drawing::{Framebuffer, Palette, TRANSPARENT};
+use files::LmpImage;
+use hprof;
+
+/// Palette entries from this index upwards are fullbright colors, which are
+/// never chosen as a blend result.
+const FIRST_FULLBRIGHT: usize = 224;
+
+pub struct BlendTable {
+    alpha: f32,
+    /// Indexed by `source * 256 + destination`.
+    table: Vec<u8>,
+}
+
+impl BlendTable {
+    /// Computes the table for `alpha` (the opacity of the source color,
+    /// clamped to `0.0..1.0`) by searching the nearest palette entry for every
+    /// color pair.
+    pub fn new(palette: &Palette, alpha: f32) -> BlendTable {
+        let _guard = hprof::enter("BlendTable::new");
+        let alpha = alpha.clamp(0.0, 1.0);
+        let colors: Vec<[f32; 3]> = (0..256)
+                                        .map(|i| {
+                                            let c = palette.get(i as u8);
+                                            [c.r as f32, c.g as f32, c.b as f32]
+                                        })
+                                        .collect();
+        // Many pairs mix to the same color, so remember the searches.
+        let mut nearest = HashMap::new();
+        let mut table = vec![0; 256 * 256];
+
+        for (src, s) in colors.iter().enumerate() {
+            for (dst, d) in colors.iter().enumerate() {
+                let mix = |i: usize| (s[i] * alpha + d[i] * (1.0 - alpha)).round() as u8;
+                let mixed = (mix(0), mix(1), mix(2));
+                let index = *nearest.entry(mixed).or_insert_with(|| nearest_color(&colors, mixed));
+                table[src * 256 + dst] = index;
+            }
+        }
+
+        BlendTable {
+            alpha: alpha,
+            table: table,
+        }
+    }
+
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+
+    /// Returns the palette index closest to `source` drawn over `destination`.
+    #[inline]
+    pub fn blend(&self, source: u8, destination: u8) -> u8 {
+        self.table[source as usize * 256 + destination as usize]
+    }
+}
+
+impl fmt::Debug for BlendTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BlendTable {{ alpha: {} }}", self.alpha)
+    }
+}
+
+fn nearest_color(colors: &[[f32; 3]], c: (u8, u8, u8)) -> u8 {
+    let c = [c.0 as f32, c.1 as f32, c.2 as f32];
+    let mut best = 0;
+    let mut best_distance = f32::MAX;
+    for (i, p) in colors.iter().take(FIRST_FULLBRIGHT).enumerate() {
+        let (dr, dg, db) = (p[0] - c[0], p[1] - c[1], p[2] - c[2]);
+        let distance = dr * dr + dg * dg + db * db;
+        if distance < best_distance {
+            best = i;
+            best_distance = distance;
+        }
+    }
+    best as u8
+}
+
+/// In-memory cache of blend tables, keyed by alpha in percent. Building a
+/// table takes a noticeable amount of time, so it should only happen once per
+/// alpha value.
+#[derive(Default)]
+pub struct BlendTableCache {
+    tables: HashMap<u8, Rc<BlendTable>>,
+}
+
+impl BlendTableCache {
+    pub fn new() -> BlendTableCache {
+        BlendTableCache { tables: HashMap::new() }
+    }
+
+    /// Returns the table for `alpha`, rounded to the nearest percent.
+    pub fn get(&mut self, palette: &Palette, alpha: f32) -> Rc<BlendTable> {
+        let percent = (alpha.clamp(0.0, 1.0) * 100.0).round() as u8;
+        self.tables
+            .entry(percent)
+            .or_insert_with(|| Rc::new(BlendTable::new(palette, percent as f32 / 100.0)))
+            .clone()
+    }
+
+    /// Drops all tables, e.g. after the palette changed.
+    pub fn clear(&mut self) {
+        self.tables.clear();
+    }
+}
+
+impl Framebuffer {
+    /// Blends `color` over the whole framebuffer.
+    pub fn blend_fill(&mut self, color: u8, table: &BlendTable) {
+        for v in self.pixels_mut() {
+            *v = table.blend(color, *v);
+        }
+    }
+
+    /// Blends `color` over a rectangle, clipped to the framebuffer.
+    pub fn blend_rect(&mut self,
+                      x: usize,
+                      y: usize,
+                      width: usize,
+                      height: usize,
+                      color: u8,
+                      table: &BlendTable) {
+        let x_end = (x + width).min(self.width());
+        let y_end = (y + height).min(self.height());
+        for row in y..y_end {
+            for col in x..x_end {
+                let dst = self.get(col, row);
+                self.set(col, row, table.blend(color, dst));
+            }
+        }
+    }
+
+    /// Blends a row of source pixels into row `y`, starting at column `x`.
+    /// Pixels outside the framebuffer are skipped.
+    pub fn blend_span(&mut self, x: usize, y: usize, source: &[u8], table: &BlendTable) {
+        if y >= self.height() || x >= self.width() {
+            return;
+        }
+        let len = source.len().min(self.width() - x);
+        let start = y * self.width() + x;
+        let row = &mut self.pixels_mut()[start..start + len];
+        for (dst, &src) in row.iter_mut().zip(source) {
+            *dst = table.blend(src, *dst);
+        }
+    }
+
+    /// Draws an image translucently. Pixels with the transparent index (255)
+    /// are skipped, like in `Draw_TransPic`.
+    pub fn draw_pic_blended(&mut self,
+                            x_pos: usize,
+                            y_pos: usize,
+                            image: &LmpImage,
+                            table: &BlendTable) {
+        for j in 0..image.height() as usize {
+            let y = j + y_pos;
+            if y >= self.height() {
+                break;
+            }
+            for i in 0..image.width() as usize {
+                let x = i + x_pos;
+                if x >= self.width() {
+                    break;
+                }
+                let src = image.get(i as u32, j as u32);
+                if src != TRANSPARENT {
+                    let dst = self.get(x, y);
+                    self.set(x, y, table.blend(src, dst));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use drawing::{Framebuffer, Palette};
+    use files::LmpImage;
+    use super::{BlendTable, BlendTableCache};
+
+    /// Palette where entry i is the grey (i, i, i).
+    fn grey_palette() -> Palette {
+        let bytes: Vec<u8> = (0..256).flat_map(|i| vec![i as u8; 3]).collect();
+        Palette::from_bytes(&bytes)
+    }
+
+    #[test]
+    fn opaque_and_invisible() {
+        let palette = grey_palette();
+        let opaque = BlendTable::new(&palette, 1.0);
+        let invisible = BlendTable::new(&palette, 0.0);
+        for &(s, d) in &[(0, 10), (100, 3), (223, 0)] {
+            assert_eq!(opaque.blend(s, d), s);
+            assert_eq!(invisible.blend(s, d), d);
+        }
+    }
+
+    #[test]
+    fn half_alpha_mixes() {
+        let table = BlendTable::new(&grey_palette(), 0.5);
+        assert_eq!(table.blend(200, 0), 100);
+        assert_eq!(table.blend(10, 20), 15);
+        // Fullbrights are never picked
+        assert_eq!(table.blend(255, 255), 223);
+    }
+
+    #[test]
+    fn cache_reuses_tables() {
+        let palette = grey_palette();
+        let mut cache = BlendTableCache::new();
+        let a = cache.get(&palette, 0.5);
+        let b = cache.get(&palette, 0.501);
+        assert!(Rc::ptr_eq(&a, &b));
+        assert_eq!(a.alpha(), 0.5);
+    }
+
+    #[test]
+    fn palette_change_drops_tables() {
+        let mut fb = Framebuffer::with_palette(1, 1, grey_palette());
+        let grey = fb.blend_table(0.5);
+        assert!(Rc::ptr_eq(&grey, &fb.blend_table(0.5)));
+        fb.set_palette(Palette::from_bytes(&[0; 768]));
+        assert!(!Rc::ptr_eq(&grey, &fb.blend_table(0.5)));
+    }
+
+    #[test]
+    fn blended_operations() {
+        let mut fb = Framebuffer::with_palette(4, 2, grey_palette());
+        fb.fill(100);
+        let table = fb.blend_table(0.5);
+        fb.blend_rect(1, 0, 2, 1, 200, &table);
+        assert_eq!(fb.pixels(), &[100, 150, 150, 100, 100, 100, 100, 100]);
+
+        fb.blend_span(2, 1, &[0, 0, 0, 0], &table);
+        assert_eq!(&fb.pixels()[4..], &[100, 100, 50, 50]);
+
+        let bytes = [2, 0, 0, 0, 1, 0, 0, 0, 0, 255];
+        let image = LmpImage::from_bytes(&bytes).unwrap();
+        fb.draw_pic_blended(0, 1, &image, &table);
+        assert_eq!(&fb.pixels()[4..], &[50, 100, 50, 50]);
+
+        fb.blend_fill(200, &table);
+        assert_eq!(fb.get(0, 0), 150);
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+
+use files::{PackContainer, PackResult};
+
+/// Number of light levels (rows) in the colormap.
+pub const LIGHT_LEVELS: usize = 64;
+
+/// The lighting table of `gfx/colormap.lmp`. Every row maps the 256 palette
+/// entries to their shade at one light level, from the brightest (row 0) to
+/// black (row 63). Fullbright colors stay the same in every row.
+pub struct Colormap {
+    table: Vec<u8>,
+}
+
+impl fmt::Debug for Colormap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Colormap")
+    }
+}
+
+impl Colormap {
+    pub fn new(pack: &mut PackContainer) -> PackResult<Colormap> {
+        let bytes = try!(pack.read("gfx/colormap.lmp"));
+        Ok(Colormap::from_bytes(&bytes))
+    }
+
+    /// Builds a colormap from the rows of a `colormap.lmp` file. Missing
+    /// rows are black.
+    pub fn from_bytes(bytes: &[u8]) -> Colormap {
+        let mut table = vec![0; LIGHT_LEVELS * 256];
+        let len = bytes.len().min(table.len());
+        table[..len].copy_from_slice(&bytes[..len]);
+        Colormap { table: table }
+    }
+
+    /// The row for light level `level` (0 to 63, clamped).
+    pub fn row(&self, level: usize) -> &[u8] {
+        let start = level.min(LIGHT_LEVELS - 1) * 256;
+        &self.table[start..start + 256]
+    }
+
+    #[inline]
+    pub fn shade(&self, color: u8, level: usize) -> u8 {
+        self.row(level)[color as usize]
+    }
+
+    /// Converts a lightmap value (0 is dark, 255 the brightest) into a light
+    /// level.
+    #[inline]
+    pub fn light_level(light: u8) -> usize {
+        (255 - light as usize) >> 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Colormap, LIGHT_LEVELS};
+
+    #[test]
+    fn rows_and_levels() {
+        let bytes: Vec<u8> = (0..LIGHT_LEVELS * 256).map(|i| (i % 256 + i / 256) as u8).collect();
+        let colormap = Colormap::from_bytes(&bytes);
+        assert_eq!(colormap.shade(7, 0), 7);
+        assert_eq!(colormap.shade(7, 13), 20);
+        assert_eq!(colormap.row(100)[0], 63);
+        assert_eq!(Colormap::light_level(255), 0);
+        assert_eq!(Colormap::light_level(200), 13);
+        assert_eq!(Colormap::light_level(0), 63);
+
+        let short = Colormap::from_bytes(&[1, 2, 3]);
+        assert_eq!(short.shade(1, 0), 2);
+        assert_eq!(short.shade(1, 1), 0);
+    }
+}
@@ -1,7 +1,12 @@
 use drawing::bezier::BezierCurve;
+use drawing::blend::{BlendTable, BlendTableCache};
 use util::Color;
 use files::*;
 use hprof;
+use std::rc::Rc;
+
+/// The palette index of transparent pixels in pics, sprites and skins.
+pub const TRANSPARENT: u8 = 255;
 
 pub struct Palette {
     colors: [Color; 256],
@@ -10,18 +15,28 @@ pub struct Palette {
 impl Palette {
     pub fn new(pack: &mut PackContainer) -> PackResult<Palette> {
         let bytes = try!(pack.read("gfx/palette.lmp"));
+        Ok(Palette::from_bytes(&bytes))
+    }
+
+    /// Builds a palette from the 768 bytes (256 RGB triples) of a
+    /// `palette.lmp` file.
+    pub fn from_bytes(bytes: &[u8]) -> Palette {
         let mut buf = [Color::default(); 256];
-        for (i, b) in bytes.chunks(3).enumerate() {
-            let (r, g, b) = (b[2], b[1], b[0]);
-            buf[i] = Color::new(r, g, b);
+        for (i, b) in bytes.chunks(3).take(256).enumerate() {
+            buf[i] = Color::new(b[0], b[1], b[2]);
         }
 
-        Ok(Palette { colors: buf })
+        Palette { colors: buf }
     }
 
     pub fn get(&self, c: u8) -> Color {
         self.colors[c as usize]
     }
+
+    /// Returns the palette as 256 RGB triples, the layout of `palette.lmp`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.colors.iter().flat_map(|c| vec![c.r, c.g, c.b]).collect()
+    }
 }
 
 pub struct Framebuffer {
@@ -32,18 +47,32 @@ pub struct Framebuffer {
     height: usize,
     /// Buffer of colors as they will be rendered to the screen.
     /// Size is width * height * 4 (32 bpp), also treated like a fixed-size array.
+    /// Every pixel is stored as the bytes blue, green, red, unused (in that
+    /// order), regardless of the platform's endianness.
     color_buffer: Vec<u8>,
-    pub palette: Palette,
+    /// Optional depth buffer with the same dimensions as `pixels`. Stores
+    /// `0x8000 / z`, so larger values are closer to the viewer and a cleared
+    /// buffer (all zeroes) is infinitely far away.
+    zbuffer: Option<Vec<u16>>,
+    palette: Palette,
+    /// Translucency tables built from `palette`, see `blend_table`.
+    blend_tables: BlendTableCache,
 }
 
 impl Framebuffer {
     pub fn new(width: usize, height: usize, pack: &mut PackContainer) -> Framebuffer {
+        Framebuffer::with_palette(width, height, Palette::new(pack).unwrap())
+    }
+
+    pub fn with_palette(width: usize, height: usize, palette: Palette) -> Framebuffer {
         Framebuffer {
             pixels: vec![0; height * width],
             width: width as usize,
             height: height as usize,
             color_buffer: vec![0; height * width * 4],
-            palette: Palette::new(pack).unwrap(),
+            zbuffer: None,
+            palette: palette,
+            blend_tables: BlendTableCache::new(),
         }
     }
 
@@ -84,9 +113,9 @@ impl Framebuffer {
         let mut i = 0;
         for px in &self.pixels {
             let color = self.palette.get(*px);
-            self.color_buffer[i] = color.r;
+            self.color_buffer[i] = color.b;
             self.color_buffer[i + 1] = color.g;
-            self.color_buffer[i + 2] = color.b;
+            self.color_buffer[i + 2] = color.r;
             self.color_buffer[i + 3] = 0;
 
             i += 4;
@@ -97,6 +126,58 @@ impl Framebuffer {
         &self.pixels
     }
 
+    pub fn pixels_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+
+    /// Allocates the z-buffer if it doesn't exist yet. Drawing operations
+    /// only depth test while a z-buffer is present.
+    pub fn enable_zbuffer(&mut self) {
+        if self.zbuffer.is_none() {
+            self.zbuffer = Some(vec![0; self.width * self.height]);
+        }
+    }
+
+    pub fn disable_zbuffer(&mut self) {
+        self.zbuffer = None;
+    }
+
+    /// Resets every depth value to "infinitely far away".
+    pub fn clear_zbuffer(&mut self) {
+        if let Some(ref mut zbuffer) = self.zbuffer {
+            for z in zbuffer.iter_mut() {
+                *z = 0;
+            }
+        }
+    }
+
+    pub fn zbuffer(&self) -> Option<&[u16]> {
+        self.zbuffer.as_ref().map(|z| &z[..])
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Replaces the palette, and drops the translucency tables built from
+    /// the old one.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.blend_tables.clear();
+    }
+
+    /// Returns the (cached) translucency table for `alpha`, built from this
+    /// framebuffer's palette.
+    pub fn blend_table(&mut self, alpha: f32) -> Rc<BlendTable> {
+        self.blend_tables.get(&self.palette, alpha)
+    }
+
+    /// Borrows the pixel buffer and the z-buffer (if enabled) at the same time,
+    /// for the rasterizers that need to write to both.
+    pub fn buffers_mut(&mut self) -> (&mut [u8], Option<&mut [u16]>) {
+        (&mut self.pixels, self.zbuffer.as_mut().map(|z| &mut z[..]))
+    }
+
     pub fn color_buffer(&self) -> &[u8] {
         &self.color_buffer
     }
@@ -204,9 +285,9 @@ mod tests {
         let bytes = fb.color_buffer();
         assert_eq!(bytes.len(), sz);
         for b in bytes.chunks(4) {
-            assert_eq!(b[0], p.r);
+            assert_eq!(b[0], p.b);
             assert_eq!(b[1], p.g);
-            assert_eq!(b[2], p.b);
+            assert_eq!(b[2], p.r);
         }
     }
 
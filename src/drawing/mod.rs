@@ -1,5 +1,15 @@
 pub mod bezier;
+pub mod blend;
+pub mod colormap;
 pub mod framebuffer;
+pub mod raster;
+pub mod texture;
+pub mod translation;
 
 pub use self::bezier::BezierCurve;
-pub use self::framebuffer::Framebuffer;
+pub use self::blend::BlendTable;
+pub use self::colormap::Colormap;
+pub use self::framebuffer::{Framebuffer, Palette, TRANSPARENT};
+pub use self::raster::{Shading, Vertex};
+pub use self::texture::Texture;
+pub use self::translation::Translation;
@@ -0,0 +1,502 @@
+//! Scanline rasterization of filled triangles and convex polygons.
+//!
+//! Vertices are given in screen space (pixel coordinates, with pixel centers
+//! at `x + 0.5`) together with their view space depth `z`. Attributes are
+//! interpolated with plane gradients like Quake's `d_polyse.c`: every span
+//! starts at the exact value for its first pixel center and steps by the
+//! per-pixel x gradient, so adjacent polygons never overlap or leave gaps.
+
+use std::fmt;
+
+use drawing::{BlendTable, Colormap, Framebuffer, TRANSPARENT, Texture};
+use hprof;
+
+/// Scale of the values stored in the z-buffer: `ZBUFFER_SCALE / z`.
+pub const ZBUFFER_SCALE: f32 = 0x8000 as f32;
+
+/// Triangles with a smaller (absolute) doubled area are considered degenerate.
+const DEGENERATE_EPSILON: f32 = 1e-6;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Vertex {
+    /// Screen x coordinate in pixels.
+    pub x: f32,
+    /// Screen y coordinate in pixels.
+    pub y: f32,
+    /// View space depth, must be positive.
+    pub z: f32,
+    /// Texture coordinates in texels.
+    pub s: f32,
+    pub t: f32,
+    /// Light level (a colormap row) for `Shading::Gouraud`.
+    pub light: f32,
+}
+
+impl Vertex {
+    pub fn new(x: f32, y: f32, z: f32, s: f32, t: f32) -> Vertex {
+        Vertex {
+            x: x,
+            y: y,
+            z: z,
+            s: s,
+            t: t,
+            light: 0.0,
+        }
+    }
+
+    /// The vertex with a light level for `Shading::Gouraud`.
+    pub fn with_light(self, light: f32) -> Vertex {
+        Vertex { light: light, ..self }
+    }
+}
+
+/// How the pixels of a polygon are colored.
+#[derive(Debug, Copy, Clone)]
+pub enum Shading<'a> {
+    /// A single palette index.
+    Flat(u8),
+    /// Texture coordinates are interpolated linearly in screen space. Cheap,
+    /// but warps visibly on large polygons seen at an angle.
+    Affine(&'a Texture),
+    /// Texture coordinates are divided by `z` and interpolated together with
+    /// `1 / z`, which is correct under perspective projection.
+    Perspective(&'a Texture),
+    /// Like `Perspective`, with every texel remapped through a 256 entry
+    /// table, usually a row of the colormap.
+    Lit(&'a Texture, &'a [u8]),
+    /// Like `Perspective`, for a surface from the surface cache that covers
+    /// exactly one polygon: the coordinates are clamped instead of wrapped.
+    Surface(&'a Texture),
+    /// Like `Perspective`, with the texture warped by the turbulence at a
+    /// time in seconds.
+    Turbulent(&'a Texture, f32),
+    /// Like `Turbulent`, blended over the framebuffer for translucent water.
+    TurbulentBlended(&'a Texture, f32, &'a BlendTable),
+    /// Colors come from the pixel position alone.
+    Screen(&'a ScreenShader),
+    /// Like `Affine`, shaded through the colormap with the light level
+    /// interpolated between the vertices, like alias models in
+    /// `d_polyse.c`.
+    Gouraud(&'a Texture, &'a Colormap),
+    /// Like `Surface`, but pixels of `TRANSPARENT` are left alone,
+    /// for sprites.
+    Sprite(&'a Texture),
+}
+
+/// Colors pixels by their position on the screen, for surfaces like the sky
+/// that aren't mapped to their polygons.
+pub trait ScreenShader: fmt::Debug {
+    fn color(&self, x: usize, y: usize) -> u8;
+}
+
+/// Interpolated quantities at a point of the polygon.
+#[derive(Debug, Copy, Clone, Default)]
+struct Attributes {
+    /// 1 / z
+    izi: f32,
+    /// s / z (perspective) or s (affine)
+    s: f32,
+    /// t / z (perspective) or t (affine)
+    t: f32,
+    /// Light level, always affine
+    light: f32,
+}
+
+impl Attributes {
+    fn of(v: &Vertex, perspective: bool) -> Attributes {
+        let izi = 1.0 / v.z;
+        if perspective {
+            Attributes {
+                izi: izi,
+                s: v.s * izi,
+                t: v.t * izi,
+                light: v.light,
+            }
+        } else {
+            Attributes {
+                izi: izi,
+                s: v.s,
+                t: v.t,
+                light: v.light,
+            }
+        }
+    }
+
+    #[inline]
+    fn add_scaled(&self, d: &Attributes, t: f32) -> Attributes {
+        Attributes {
+            izi: self.izi + d.izi * t,
+            s: self.s + d.s * t,
+            t: self.t + d.t * t,
+            light: self.light + d.light * t,
+        }
+    }
+}
+
+/// Converts an interpolated `1 / z` into a z-buffer value.
+#[inline]
+pub fn depth_value(izi: f32) -> u16 {
+    let z = izi * ZBUFFER_SCALE;
+    if z >= 65535.0 {
+        0xFFFF
+    } else if z <= 0.0 {
+        0
+    } else {
+        z as u16
+    }
+}
+
+impl Framebuffer {
+    /// Draws a filled triangle. If the z-buffer is enabled, pixels are depth
+    /// tested and the z-buffer is updated.
+    pub fn triangle(&mut self, vertices: &[Vertex; 3], shading: Shading) {
+        let _guard = hprof::enter("Framebuffer::triangle");
+        let mut v = *vertices;
+        v.sort_by(|a, b| a.y.partial_cmp(&b.y).unwrap_or(::std::cmp::Ordering::Equal));
+        let (v0, v1, v2) = (v[0], v[1], v[2]);
+
+        let denom = (v1.x - v0.x) * (v2.y - v0.y) - (v2.x - v0.x) * (v1.y - v0.y);
+        if denom.abs() < DEGENERATE_EPSILON {
+            return;
+        }
+
+        let perspective = match shading {
+            Shading::Perspective(_) |
+            Shading::Lit(..) |
+            Shading::Surface(_) |
+            Shading::Turbulent(..) |
+            Shading::TurbulentBlended(..) |
+            Shading::Sprite(_) => true,
+            Shading::Flat(_) |
+            Shading::Affine(_) |
+            Shading::Screen(_) |
+            Shading::Gouraud(..) => false,
+        };
+        let a0 = Attributes::of(&v0, perspective);
+        let a1 = Attributes::of(&v1, perspective);
+        let a2 = Attributes::of(&v2, perspective);
+
+        // Plane gradients of each attribute.
+        let gradient = |f: &Fn(&Attributes) -> f32| {
+            let (d1, d2) = (f(&a1) - f(&a0), f(&a2) - f(&a0));
+            let ddx = (d1 * (v2.y - v0.y) - d2 * (v1.y - v0.y)) / denom;
+            let ddy = (d2 * (v1.x - v0.x) - d1 * (v2.x - v0.x)) / denom;
+            (ddx, ddy)
+        };
+        let (izi_dx, izi_dy) = gradient(&|a| a.izi);
+        let (s_dx, s_dy) = gradient(&|a| a.s);
+        let (t_dx, t_dy) = gradient(&|a| a.t);
+        let (light_dx, light_dy) = gradient(&|a| a.light);
+        let ddx = Attributes {
+            izi: izi_dx,
+            s: s_dx,
+            t: t_dx,
+            light: light_dx,
+        };
+        let ddy = Attributes {
+            izi: izi_dy,
+            s: s_dy,
+            t: t_dy,
+            light: light_dy,
+        };
+
+        let height = self.height() as i32;
+        let y_start = ((v0.y - 0.5).ceil() as i32).max(0);
+        let y_end = ((v2.y - 0.5).ceil() as i32).min(height);
+
+        for y in y_start..y_end {
+            let yc = y as f32 + 0.5;
+            let x_long = edge_x(&v0, &v2, yc);
+            let x_short = if yc < v1.y {
+                edge_x(&v0, &v1, yc)
+            } else {
+                edge_x(&v1, &v2, yc)
+            };
+            let (xl, xr) = if x_long < x_short {
+                (x_long, x_short)
+            } else {
+                (x_short, x_long)
+            };
+
+            let x_start = (xl - 0.5).ceil() as i32;
+            let x_end = (xr - 0.5).ceil() as i32;
+            let start = a0.add_scaled(&ddx, x_start as f32 + 0.5 - v0.x)
+                          .add_scaled(&ddy, yc - v0.y);
+            self.span(x_start, x_end, y as usize, start, &ddx, shading);
+        }
+    }
+
+    /// Draws a filled convex polygon given by its vertices in order (either
+    /// winding). The polygon is split into a triangle fan.
+    pub fn polygon(&mut self, vertices: &[Vertex], shading: Shading) {
+        if vertices.len() < 3 {
+            return;
+        }
+
+        for i in 1..vertices.len() - 1 {
+            self.triangle(&[vertices[0], vertices[i], vertices[i + 1]], shading);
+        }
+    }
+
+    /// Fills the pixels `x_start..x_end` of row `y`, starting with the
+    /// attributes of the first pixel and stepping by `step` per pixel.
+    fn span(&mut self,
+            x_start: i32,
+            x_end: i32,
+            y: usize,
+            start: Attributes,
+            step: &Attributes,
+            shading: Shading) {
+        let width = self.width() as i32;
+        let x0 = x_start.max(0);
+        let x1 = x_end.min(width);
+        if x0 >= x1 {
+            return;
+        }
+
+        let mut attr = start.add_scaled(step, (x0 - x_start) as f32);
+        let row = y * self.width();
+        let (pixels, mut zbuffer) = self.buffers_mut();
+
+        for x in x0..x1 {
+            let i = row + x as usize;
+            let depth = depth_value(attr.izi);
+            let visible = zbuffer.as_ref().map_or(true, |z| depth >= z[i]);
+
+            if visible {
+                let color = match shading {
+                    Shading::Flat(color) => color,
+                    Shading::Affine(texture) => texture.sample(attr.s, attr.t),
+                    Shading::Perspective(texture) => {
+                        let z = 1.0 / attr.izi;
+                        texture.sample(attr.s * z, attr.t * z)
+                    }
+                    Shading::Lit(texture, light) => {
+                        let z = 1.0 / attr.izi;
+                        light[texture.sample(attr.s * z, attr.t * z) as usize]
+                    }
+                    Shading::Surface(texture) => {
+                        let z = 1.0 / attr.izi;
+                        texture.sample_clamped(attr.s * z, attr.t * z)
+                    }
+                    Shading::Turbulent(texture, time) |
+                    Shading::TurbulentBlended(texture, time, _) => {
+                        let z = 1.0 / attr.izi;
+                        texture.sample_turbulent(attr.s * z, attr.t * z, time)
+                    }
+                    Shading::Screen(shader) => shader.color(x as usize, y),
+                    Shading::Gouraud(texture, colormap) => {
+                        let level = attr.light.max(0.0) as usize;
+                        colormap.shade(texture.sample(attr.s, attr.t), level)
+                    }
+                    Shading::Sprite(texture) => {
+                        let z = 1.0 / attr.izi;
+                        texture.sample_clamped(attr.s * z, attr.t * z)
+                    }
+                };
+                let color = match shading {
+                    Shading::Sprite(_) if color == TRANSPARENT => None,
+                    Shading::TurbulentBlended(_, _, table) => Some(table.blend(color, pixels[i])),
+                    _ => Some(color),
+                };
+                if let Some(color) = color {
+                    pixels[i] = color;
+                    if let Some(ref mut z) = zbuffer {
+                        z[i] = depth;
+                    }
+                }
+            }
+
+            attr = attr.add_scaled(step, 1.0);
+        }
+    }
+}
+
+/// X coordinate of the edge `a -> b` at height `y`.
+#[inline]
+fn edge_x(a: &Vertex, b: &Vertex, y: f32) -> f32 {
+    let dy = b.y - a.y;
+    if dy.abs() < DEGENERATE_EPSILON {
+        a.x
+    } else {
+        a.x + (y - a.y) * (b.x - a.x) / dy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drawing::{BlendTable, Colormap, Framebuffer, Palette, Texture};
+    use super::{ScreenShader, Shading, Vertex, depth_value};
+
+    fn framebuffer(w: usize, h: usize) -> Framebuffer {
+        Framebuffer::with_palette(w, h, Palette::from_bytes(&[0; 768]))
+    }
+
+    fn quad(x0: f32, y0: f32, x1: f32, y1: f32, z: f32) -> [Vertex; 4] {
+        [Vertex::new(x0, y0, z, 0.0, 0.0),
+         Vertex::new(x1, y0, z, x1 - x0, 0.0),
+         Vertex::new(x1, y1, z, x1 - x0, y1 - y0),
+         Vertex::new(x0, y1, z, 0.0, y1 - y0)]
+    }
+
+    #[test]
+    fn fills_exactly_the_covered_pixels() {
+        let mut fb = framebuffer(16, 16);
+        fb.polygon(&quad(2.0, 3.0, 10.0, 7.0, 1.0), Shading::Flat(5));
+        for y in 0..16 {
+            for x in 0..16 {
+                let inside = (2..10).contains(&x) && (3..7).contains(&y);
+                assert_eq!(fb.get(x, y) == 5, inside, "pixel {} {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn clips_to_the_framebuffer() {
+        let mut fb = framebuffer(8, 8);
+        fb.polygon(&quad(-20.0, -20.0, 20.0, 20.0, 1.0), Shading::Flat(1));
+        assert!(fb.pixels().iter().all(|&p| p == 1));
+    }
+
+    #[test]
+    fn depth_test() {
+        let mut fb = framebuffer(8, 8);
+        fb.enable_zbuffer();
+        fb.polygon(&quad(0.0, 0.0, 8.0, 8.0, 2.0), Shading::Flat(1));
+        fb.polygon(&quad(0.0, 0.0, 4.0, 8.0, 4.0), Shading::Flat(2));
+        fb.polygon(&quad(4.0, 0.0, 8.0, 8.0, 1.0), Shading::Flat(3));
+        assert_eq!(fb.get(1, 1), 1);
+        assert_eq!(fb.get(6, 6), 3);
+        assert_eq!(fb.zbuffer().unwrap()[0], depth_value(0.5));
+
+        fb.clear_zbuffer();
+        fb.polygon(&quad(0.0, 0.0, 4.0, 8.0, 4.0), Shading::Flat(2));
+        assert_eq!(fb.get(1, 1), 2);
+    }
+
+    #[test]
+    fn textured_modes_agree_at_constant_depth() {
+        let texture = Texture::new(4, 4, (0..16).collect());
+        let mut affine = framebuffer(8, 8);
+        let mut perspective = framebuffer(8, 8);
+        affine.polygon(&quad(0.0, 0.0, 8.0, 8.0, 3.0), Shading::Affine(&texture));
+        perspective.polygon(&quad(0.0, 0.0, 8.0, 8.0, 3.0),
+                            Shading::Perspective(&texture));
+        assert_eq!(affine.pixels(), perspective.pixels());
+
+        let light: Vec<u8> = (0..256).map(|i| 255 - i as u8).collect();
+        let mut lit = framebuffer(8, 8);
+        lit.polygon(&quad(0.0, 0.0, 8.0, 8.0, 3.0), Shading::Lit(&texture, &light));
+        assert_eq!(lit.get(5, 1), 250);
+        let mut surface = framebuffer(8, 8);
+        surface.polygon(&quad(0.0, 0.0, 8.0, 8.0, 3.0), Shading::Surface(&texture));
+        // Clamped at the right edge of the texture
+        assert_eq!(surface.get(1, 1), perspective.get(1, 1));
+        assert_eq!(surface.get(5, 1), 7);
+        assert_eq!(affine.get(0, 0), 0);
+        assert_eq!(affine.get(5, 1), 5);
+        assert_eq!(affine.get(2, 3), 14);
+    }
+
+    #[test]
+    fn perspective_correct_interpolation() {
+        // A quad receding in depth from z = 1 to z = 3: the nearer half of
+        // the texture covers most of the screen, so the switch from texel 0
+        // to texel 1 (s = 1) happens at 75% of the width, not at 50%.
+        let texture = Texture::new(2, 1, vec![7, 9]);
+        let mut fb = framebuffer(30, 4);
+        let verts = [Vertex::new(0.0, 0.0, 1.0, 0.0, 0.0),
+                     Vertex::new(30.0, 0.0, 3.0, 2.0, 0.0),
+                     Vertex::new(30.0, 4.0, 3.0, 2.0, 0.0),
+                     Vertex::new(0.0, 4.0, 1.0, 0.0, 0.0)];
+        fb.polygon(&verts, Shading::Perspective(&texture));
+        assert_eq!(fb.get(21, 2), 7);
+        assert_eq!(fb.get(23, 2), 9);
+
+        let mut affine = framebuffer(30, 4);
+        affine.polygon(&verts, Shading::Affine(&texture));
+        assert_eq!(affine.get(16, 2), 9);
+    }
+
+    #[derive(Debug)]
+    struct Diagonal;
+
+    impl ScreenShader for Diagonal {
+        fn color(&self, x: usize, y: usize) -> u8 {
+            (x + y) as u8
+        }
+    }
+
+    #[test]
+    fn screen_and_turbulent_shading() {
+        let mut fb = framebuffer(8, 8);
+        fb.polygon(&quad(2.0, 2.0, 6.0, 6.0, 1.0), Shading::Screen(&Diagonal));
+        assert_eq!((fb.get(2, 3), fb.get(5, 5), fb.get(6, 6)), (5, 10, 0));
+
+        let texture = Texture::new(4, 4, (0..16).collect());
+        let mut turbulent = framebuffer(8, 8);
+        turbulent.polygon(&quad(0.0, 0.0, 8.0, 8.0, 3.0), Shading::Turbulent(&texture, 0.0));
+        // Shifted by 8 texels in both directions, twice the texture size
+        let mut perspective = framebuffer(8, 8);
+        perspective.polygon(&quad(0.0, 0.0, 8.0, 8.0, 3.0), Shading::Perspective(&texture));
+        assert_eq!(turbulent.get(1, 0), perspective.get(1, 0));
+    }
+
+    #[test]
+    fn blended_turbulence() {
+        let grey: Vec<u8> = (0..256).flat_map(|i| vec![i as u8; 3]).collect();
+        let mut fb = Framebuffer::with_palette(8, 8, Palette::from_bytes(&grey));
+        fb.enable_zbuffer();
+        // In front of the left half
+        fb.polygon(&quad(0.0, 0.0, 4.0, 8.0, 1.0), Shading::Flat(200));
+        let table = BlendTable::new(fb.palette(), 0.5);
+        let texture = Texture::new(4, 4, vec![100; 16]);
+        fb.polygon(&quad(0.0, 0.0, 8.0, 8.0, 2.0),
+                   Shading::TurbulentBlended(&texture, 0.0, &table));
+        assert_eq!((fb.get(3, 4), fb.get(4, 4)), (200, 50));
+        assert_eq!(fb.zbuffer().unwrap()[4 * 8 + 4], depth_value(0.5));
+    }
+
+    #[test]
+    fn gouraud_shading() {
+        // Light level l maps color c to c + l
+        let bytes: Vec<u8> = (0..64 * 256).map(|i| (i % 256 + i / 256) as u8).collect();
+        let colormap = Colormap::from_bytes(&bytes);
+        let texture = Texture::new(1, 1, vec![10]);
+        let mut fb = framebuffer(16, 4);
+        let verts = [Vertex::new(0.0, 0.0, 1.0, 0.0, 0.0).with_light(0.0),
+                     Vertex::new(16.0, 0.0, 1.0, 0.0, 0.0).with_light(32.0),
+                     Vertex::new(16.0, 4.0, 1.0, 0.0, 0.0).with_light(32.0),
+                     Vertex::new(0.0, 4.0, 1.0, 0.0, 0.0).with_light(0.0)];
+        fb.polygon(&verts, Shading::Gouraud(&texture, &colormap));
+        // Pixel centers at x + 0.5, two levels per pixel
+        assert_eq!((fb.get(0, 1), fb.get(7, 2), fb.get(15, 3)), (11, 25, 41));
+    }
+
+    #[test]
+    fn transparent_sprite_pixels() {
+        // The left half is transparent
+        let texture = Texture::new(2, 1, vec![255, 6]);
+        let mut fb = framebuffer(8, 8);
+        fb.enable_zbuffer();
+        fb.polygon(&quad(0.0, 0.0, 8.0, 8.0, 2.0), Shading::Flat(1));
+        let mut sprite = quad(0.0, 0.0, 8.0, 8.0, 1.0);
+        for v in &mut sprite {
+            v.s /= 4.0;
+        }
+        fb.polygon(&sprite, Shading::Sprite(&texture));
+        assert_eq!((fb.get(3, 3), fb.get(4, 3)), (1, 6));
+        // Neither pixel nor depth of transparent pixels are written
+        let zbuffer = fb.zbuffer().unwrap();
+        assert_eq!((zbuffer[3], zbuffer[4]), (depth_value(0.5), depth_value(1.0)));
+    }
+
+    #[test]
+    fn ignores_degenerate_triangles() {
+        let mut fb = framebuffer(8, 8);
+        let v = Vertex::new(1.0, 1.0, 1.0, 0.0, 0.0);
+        fb.triangle(&[v, Vertex { x: 5.0, y: 5.0, ..v }, Vertex { x: 3.0, y: 3.0, ..v }],
+                    Shading::Flat(4));
+        assert!(fb.pixels().iter().all(|&p| p == 0));
+    }
+}
@@ -0,0 +1,119 @@
+use std::f32::consts::PI;
+
+use files::LmpImage;
+
+/// Period of the turbulence in texels.
+const TURB_CYCLE: f32 = 128.0;
+/// Largest displacement of the turbulence is twice this, in texels.
+const TURB_AMPLITUDE: f32 = 8.0;
+/// Steps of the turbulence per second.
+const TURB_SPEED: f32 = 20.0;
+
+/// A palettized texture that can be sampled by the rasterizers. Texture
+/// coordinates wrap around in both directions, so surfaces can tile them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Texture {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Texture {
+    pub fn new(width: usize, height: usize, pixels: Vec<u8>) -> Texture {
+        assert_eq!(pixels.len(), width * height);
+        Texture {
+            width: width,
+            height: height,
+            pixels: pixels,
+        }
+    }
+
+    pub fn from_lmp(image: &LmpImage) -> Texture {
+        let size = (image.width() * image.height()) as usize;
+        Texture::new(image.width() as usize,
+                     image.height() as usize,
+                     image.pixels()[..size].to_vec())
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Returns the texel at the given integer coordinates, wrapping around
+    /// the edges of the texture.
+    #[inline]
+    pub fn get(&self, s: i32, t: i32) -> u8 {
+        let w = self.width as i32;
+        let h = self.height as i32;
+        let x = ((s % w) + w) % w;
+        let y = ((t % h) + h) % h;
+        self.pixels[(y * w + x) as usize]
+    }
+
+    /// Samples the texture at floating point texel coordinates (nearest
+    /// neighbour).
+    #[inline]
+    pub fn sample(&self, s: f32, t: f32) -> u8 {
+        self.get(s.floor() as i32, t.floor() as i32)
+    }
+
+    /// Samples the texture like `sample`, but clamps the coordinates to the
+    /// edges instead of wrapping them.
+    #[inline]
+    pub fn sample_clamped(&self, s: f32, t: f32) -> u8 {
+        let x = (s.floor() as i32).clamp(0, self.width as i32 - 1);
+        let y = (t.floor() as i32).clamp(0, self.height as i32 - 1);
+        self.pixels[y as usize * self.width + x as usize]
+    }
+
+    /// Samples the texture warped by Quake's sine turbulence at `time`
+    /// seconds, like `D_DrawTurbulent8Span`. Every row is shifted
+    /// sideways depending on its t coordinate and every column vertically
+    /// depending on its s coordinate.
+    #[inline]
+    pub fn sample_turbulent(&self, s: f32, t: f32, time: f32) -> u8 {
+        let phase = (time * TURB_SPEED).floor();
+        let turb = |x: f32| {
+            TURB_AMPLITUDE * (1.0 + ((x.floor() + phase) * 2.0 * PI / TURB_CYCLE).sin())
+        };
+        self.sample(s + turb(t), t + turb(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Texture;
+
+    #[test]
+    fn wraps_coordinates() {
+        let tex = Texture::new(2, 2, vec![1, 2, 3, 4]);
+        assert_eq!(tex.get(0, 0), 1);
+        assert_eq!(tex.get(3, 0), 2);
+        assert_eq!(tex.get(-1, -1), 4);
+        assert_eq!(tex.sample(-0.5, 1.5), 4);
+        assert_eq!(tex.sample_clamped(-0.5, 1.5), 3);
+        assert_eq!(tex.sample_clamped(2.0, -3.0), 2);
+    }
+
+    #[test]
+    fn turbulence() {
+        // Every texel holds its s coordinate
+        let tex = Texture::new(64, 64, (0..64 * 64).map(|i| (i % 64) as u8).collect());
+        // Displaced by 8 texels at the start of the cycle, up to 16 a
+        // quarter cycle later
+        assert_eq!(tex.sample_turbulent(0.5, 0.5, 0.0), 8);
+        assert_eq!(tex.sample_turbulent(0.5, 0.5, 1.6), 16);
+        assert_eq!(tex.sample_turbulent(0.5, 0.5, 4.8), 0);
+        assert_eq!(tex.sample_turbulent(0.5, 0.5, 1.6), tex.sample_turbulent(0.5, 0.5, 8.0));
+    }
+}
@@ -0,0 +1,146 @@
+//! Player colors. The skin of `progs/player.mdl` and the setup menu's
+//! `gfx/menuplyr.lmp` use two ranges of the palette for the shirt and the
+//! pants. They are recolored with a translation table that replaces each
+//! range with one of the 14 color ranges the players choose from, like
+//! `M_BuildTranslationTable` and `R_TranslatePlayerSkin`.
+
+use std::fmt;
+
+use drawing::{Framebuffer, TRANSPARENT, Texture};
+use files::LmpImage;
+
+/// First palette index of the shirt colors.
+pub const TOP_RANGE: usize = 16;
+/// First palette index of the pants colors.
+pub const BOTTOM_RANGE: usize = 96;
+/// The palette is made up of 16 ranges of 16 colors.
+const RANGE_SIZE: usize = 16;
+/// Ranges from this index on run from bright to dark, all others from dark
+/// to bright. Their colors are reversed when copied.
+const FIRST_BACKWARDS: usize = 128;
+
+/// A mapping from every palette index to the one it is drawn with.
+#[derive(Copy, Clone)]
+pub struct Translation {
+    table: [u8; 256],
+}
+
+impl fmt::Debug for Translation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Translation")
+    }
+}
+
+impl PartialEq for Translation {
+    fn eq(&self, other: &Translation) -> bool {
+        self.table[..] == other.table[..]
+    }
+}
+
+impl Translation {
+    /// The translation that leaves every color alone.
+    pub fn identity() -> Translation {
+        let mut table = [0; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            *entry = i as u8;
+        }
+        Translation { table: table }
+    }
+
+    /// The translation for a player with shirt color `top` and pants color
+    /// `bottom`, both from 0 to 15.
+    pub fn new(top: u8, bottom: u8) -> Translation {
+        let mut translation = Translation::identity();
+        translation.copy_range(TOP_RANGE, top);
+        translation.copy_range(BOTTOM_RANGE, bottom);
+        translation
+    }
+
+    /// The translation for the `colors` of the scoreboard and the `color`
+    /// command, with the shirt in the high and the pants in the low nibble.
+    pub fn from_colors(colors: u8) -> Translation {
+        Translation::new(colors >> 4, colors & 15)
+    }
+
+    /// Replaces the range starting at `dest` with color range `color`.
+    fn copy_range(&mut self, dest: usize, color: u8) {
+        let source = (color as usize & 15) * RANGE_SIZE;
+        for i in 0..RANGE_SIZE {
+            let j = if source < FIRST_BACKWARDS { i } else { RANGE_SIZE - 1 - i };
+            self.table[dest + i] = (source + j) as u8;
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, color: u8) -> u8 {
+        self.table[color as usize]
+    }
+
+    /// A recolored copy of `texture`, e.g. a player skin.
+    pub fn texture(&self, texture: &Texture) -> Texture {
+        let pixels = texture.pixels().iter().map(|&p| self.get(p)).collect();
+        Texture::new(texture.width(), texture.height(), pixels)
+    }
+}
+
+impl Framebuffer {
+    /// Draws an image recolored by `translation`, skipping transparent
+    /// pixels, like `M_DrawTransPicTranslate` for the player in the setup
+    /// menu.
+    pub fn draw_pic_translated(&mut self,
+                               x_pos: usize,
+                               y_pos: usize,
+                               image: &LmpImage,
+                               translation: &Translation) {
+        for j in 0..image.height() as usize {
+            let y = j + y_pos;
+            if y >= self.height() {
+                break;
+            }
+            for i in 0..image.width() as usize {
+                let x = i + x_pos;
+                if x >= self.width() {
+                    break;
+                }
+                let src = image.get(i as u32, j as u32);
+                if src != TRANSPARENT {
+                    self.set(x, y, translation.get(src));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drawing::{Framebuffer, Palette, Texture};
+    use files::LmpImage;
+    use super::{BOTTOM_RANGE, TOP_RANGE, Translation};
+
+    #[test]
+    fn translation_tables() {
+        let identity = Translation::identity();
+        assert!((0..256).all(|c| identity.get(c as u8) == c as u8));
+        assert_eq!(Translation::new(1, 6), identity);
+
+        // Forward ranges are copied in order, backward ones reversed
+        let t = Translation::new(4, 13);
+        assert_eq!((t.get(TOP_RANGE as u8), t.get(TOP_RANGE as u8 + 15)), (64, 79));
+        assert_eq!((t.get(BOTTOM_RANGE as u8), t.get(BOTTOM_RANGE as u8 + 15)), (223, 208));
+        assert_eq!((t.get(15), t.get(32), t.get(95), t.get(112)), (15, 32, 95, 112));
+        assert_eq!(Translation::from_colors(0x4d), t);
+
+        let skin = Texture::new(2, 1, vec![20, 200]);
+        assert_eq!(t.texture(&skin).pixels(), &[68, 200]);
+    }
+
+    #[test]
+    fn draws_translated_pics() {
+        let mut fb = Framebuffer::with_palette(3, 2, Palette::from_bytes(&[0; 768]));
+        // A 2x2 image with a transparent pixel
+        let bytes = [2, 0, 0, 0, 2, 0, 0, 0, 16, 255, 100, 5];
+        let image = LmpImage::from_bytes(&bytes).unwrap();
+        fb.draw_pic_translated(1, 0, &image, &Translation::new(2, 0));
+        assert_eq!(fb.pixels(), &[0, 32, 0, 0, 4, 5]);
+    }
+}
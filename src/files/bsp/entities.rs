@@ -0,0 +1,415 @@
+//! Parsing and writing of the entity lump, a text block of records like
+//!
+//! ```text
+//! {
+//! "classname" "info_player_start"
+//! "origin" "480 -352 88"
+//! }
+//! ```
+//!
+//! Maps can have their entities replaced without recompiling them by
+//! putting a `maps/<name>.ent` file with the same syntax into a PAK.
+
+use std::fmt;
+use std::path::Path;
+
+use files::{PackContainer, PackError};
+use files::bsp::{Bsp, BspResult};
+use util::Vec3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntityError {
+    /// The text ended in the middle of an entity.
+    UnexpectedEof,
+    /// A token other than `{` was found between entities.
+    ExpectedOpenBrace(usize),
+    /// A key was followed by `}` instead of a value.
+    MissingValue(usize),
+    /// A quoted string wasn't closed.
+    UnterminatedString(usize),
+}
+
+impl fmt::Display for EntityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EntityError::UnexpectedEof => write!(f, "unexpected end of entity data"),
+            EntityError::ExpectedOpenBrace(line) => write!(f, "line {}: expected '{{'", line),
+            EntityError::MissingValue(line) => write!(f, "line {}: key without value", line),
+            EntityError::UnterminatedString(line) => {
+                write!(f, "line {}: unterminated string", line)
+            }
+        }
+    }
+}
+
+pub type EntityResult<T> = Result<T, EntityError>;
+
+/// Spawnflags that exclude an entity from a skill level or deathmatch.
+pub const SPAWNFLAG_NOT_EASY: u32 = 256;
+pub const SPAWNFLAG_NOT_MEDIUM: u32 = 512;
+pub const SPAWNFLAG_NOT_HARD: u32 = 1024;
+pub const SPAWNFLAG_NOT_DEATHMATCH: u32 = 2048;
+
+/// An entity: key/value pairs in the order they appear in the map.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Entity {
+    pairs: Vec<(String, String)>,
+}
+
+impl Entity {
+    pub fn new() -> Entity {
+        Entity { pairs: vec![] }
+    }
+
+    pub fn pairs(&self) -> &[(String, String)] {
+        &self.pairs
+    }
+
+    /// Returns the value of `key`. If a key appears more than once, the last
+    /// value wins, like in the engine's spawn code.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs.iter().rev().find(|p| p.0 == key).map(|p| &p.1[..])
+    }
+
+    /// Replaces the value of `key`, or appends the pair if the key is new.
+    /// Quotes can't be written to the entity lump, so they are removed from
+    /// both.
+    pub fn set(&mut self, key: &str, value: &str) {
+        let key = &key.replace('"', "")[..];
+        let value = value.replace('"', "");
+        match self.pairs.iter().position(|p| p.0 == key) {
+            Some(i) => {
+                self.pairs[i].1 = value;
+                // Drop duplicates, so the new value is the one `get` returns
+                let mut idx = 0;
+                self.pairs.retain(|p| {
+                    idx += 1;
+                    idx - 1 == i || p.0 != key
+                });
+            }
+            None => self.pairs.push((key.into(), value)),
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.pairs.retain(|p| p.0 != key);
+    }
+
+    pub fn classname(&self) -> Option<&str> {
+        self.get("classname")
+    }
+
+    pub fn get_float(&self, key: &str) -> Option<f32> {
+        self.get(key).and_then(|v| v.trim().parse().ok())
+    }
+
+    /// Parses a vector value like `"480 -352 88"`. Returns `None` unless
+    /// there are exactly three numbers.
+    pub fn get_vec3(&self, key: &str) -> Option<Vec3> {
+        self.get(key).and_then(|v| {
+            let c = v.split_whitespace().map(|c| c.parse().ok()).collect::<Option<Vec<f32>>>();
+            match c {
+                Some(ref c) if c.len() == 3 => Some(Vec3::new(c[0], c[1], c[2])),
+                _ => None,
+            }
+        })
+    }
+
+    /// Returns the orientation (pitch, yaw, roll) from either the "angles"
+    /// key or the yaw-only "angle" key, where -1 means straight up and -2
+    /// straight down.
+    pub fn angles(&self) -> Option<Vec3> {
+        if let Some(angles) = self.get_vec3("angles") {
+            return Some(angles);
+        }
+        self.get_float("angle").map(|yaw| if yaw == -1.0 {
+            Vec3::new(-90.0, 0.0, 0.0)
+        } else if yaw == -2.0 {
+            Vec3::new(90.0, 0.0, 0.0)
+        } else {
+            Vec3::new(0.0, yaw, 0.0)
+        })
+    }
+
+    /// Parses a bit field. Fields are floats in QuakeC, so values like
+    /// `"1.000000"` are accepted.
+    pub fn get_flags(&self, key: &str) -> Option<u32> {
+        self.get_float(key).map(|f| f as u32)
+    }
+
+    pub fn spawnflags(&self) -> u32 {
+        self.get_flags("spawnflags").unwrap_or(0)
+    }
+
+    pub fn has_spawnflag(&self, flag: u32) -> bool {
+        self.spawnflags() & flag != 0
+    }
+}
+
+/// Splits entity text into tokens, following the rules of `COM_Parse`:
+/// `//` comments, quoted strings and single character braces.
+struct Tokenizer<'a> {
+    text: &'a str,
+    pos: usize,
+    line: usize,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Open,
+    Close,
+    Str(&'a str),
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(text: &'a str) -> Tokenizer<'a> {
+        Tokenizer {
+            text: text,
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn next(&mut self) -> EntityResult<Option<Token<'a>>> {
+        let bytes = self.text.as_bytes();
+        // Skip whitespace and comments
+        loop {
+            while self.pos < bytes.len() && bytes[self.pos].is_ascii_whitespace() {
+                if bytes[self.pos] == b'\n' {
+                    self.line += 1;
+                }
+                self.pos += 1;
+            }
+            if self.text[self.pos..].starts_with("//") {
+                while self.pos < bytes.len() && bytes[self.pos] != b'\n' {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+
+        if self.pos >= bytes.len() {
+            return Ok(None);
+        }
+        match bytes[self.pos] {
+            b'{' => {
+                self.pos += 1;
+                Ok(Some(Token::Open))
+            }
+            b'}' => {
+                self.pos += 1;
+                Ok(Some(Token::Close))
+            }
+            b'"' => {
+                let start = self.pos + 1;
+                match self.text[start..].find('"') {
+                    Some(len) => {
+                        let s = &self.text[start..start + len];
+                        self.line += s.matches('\n').count();
+                        self.pos = start + len + 1;
+                        Ok(Some(Token::Str(s)))
+                    }
+                    None => Err(EntityError::UnterminatedString(self.line)),
+                }
+            }
+            _ => {
+                let start = self.pos;
+                while self.pos < bytes.len() &&
+                      !bytes[self.pos].is_ascii_whitespace() &&
+                      bytes[self.pos] != b'{' && bytes[self.pos] != b'}' &&
+                      bytes[self.pos] != b'"' {
+                    self.pos += 1;
+                }
+                Ok(Some(Token::Str(&self.text[start..self.pos])))
+            }
+        }
+    }
+}
+
+/// Parses an entity lump or `.ent` file.
+pub fn parse_entities(text: &str) -> EntityResult<Vec<Entity>> {
+    let mut tokens = Tokenizer::new(text);
+    let mut entities = vec![];
+
+    while let Some(token) = try!(tokens.next()) {
+        if token != Token::Open {
+            return Err(EntityError::ExpectedOpenBrace(tokens.line));
+        }
+        let mut entity = Entity::new();
+        loop {
+            let key = match try!(tokens.next()) {
+                Some(Token::Close) => break,
+                Some(Token::Str(key)) => key,
+                Some(Token::Open) => return Err(EntityError::MissingValue(tokens.line)),
+                None => return Err(EntityError::UnexpectedEof),
+            };
+            match try!(tokens.next()) {
+                Some(Token::Str(value)) => entity.pairs.push((key.into(), value.into())),
+                Some(_) => return Err(EntityError::MissingValue(tokens.line)),
+                None => return Err(EntityError::UnexpectedEof),
+            }
+        }
+        entities.push(entity);
+    }
+
+    Ok(entities)
+}
+
+/// Writes entities in the format of the entity lump.
+pub fn write_entities(entities: &[Entity]) -> String {
+    let mut s = String::new();
+    for entity in entities {
+        s.push_str("{\n");
+        for pair in &entity.pairs {
+            s.push_str(&format!("\"{}\" \"{}\"\n", pair.0, pair.1));
+        }
+        s.push_str("}\n");
+    }
+    s
+}
+
+/// Returns the entities of the map loaded from `map_path` (e.g.
+/// `maps/e1m1.bsp`). If the PAKs contain `maps/e1m1.ent`, its entities are
+/// used instead of the ones in the BSP. Errors reading it are passed on.
+pub fn load_entities(paks: &mut PackContainer,
+                     map_path: &str,
+                     bsp: &Bsp)
+                     -> BspResult<Vec<Entity>> {
+    let ent_path = Path::new(map_path).with_extension("ent");
+    let override_bytes = match ent_path.to_str().map(|p| paks.read(p)) {
+        Some(Ok(bytes)) => Some(bytes),
+        Some(Err(PackError::UnknownContentFileName)) | None => None,
+        Some(Err(err)) => return Err(err.into()),
+    };
+    let entities = match override_bytes {
+        Some(bytes) => try!(parse_entities(&String::from_utf8_lossy(&bytes))),
+        None => try!(parse_entities(&bsp.entities)),
+    };
+    Ok(entities)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use byteorder::{ByteOrder, LittleEndian};
+    use files::{PackContainer, PackError};
+    use files::bsp::{Bsp, BspError};
+    use files::testing::{floor_bsp, write_pak};
+    use util::Vec3;
+    use super::*;
+
+    const TEXT: &'static str = "{\n\"classname\" \"worldspawn\"\n\"message\" \"The Slipgate \
+                                Complex\"\n}\n// a comment\n{\n\"classname\" \
+                                \"info_player_start\"\n\"origin\" \"480 -352 88\"\n\"angle\" \
+                                \"90\"\n}\n{\"classname\" \"light\" \"spawnflags\" \"1.000000\" \
+                                \"angle\" \"-2\"}";
+
+    #[test]
+    fn parse() {
+        let entities = parse_entities(TEXT).unwrap();
+        assert_eq!(entities.len(), 3);
+        assert_eq!(entities[0].get("message"), Some("The Slipgate Complex"));
+        assert_eq!(entities[1].classname(), Some("info_player_start"));
+        assert_eq!(entities[1].get_vec3("origin"), Some(Vec3::new(480.0, -352.0, 88.0)));
+        assert_eq!(entities[1].angles(), Some(Vec3::new(0.0, 90.0, 0.0)));
+        assert_eq!(entities[2].angles(), Some(Vec3::new(90.0, 0.0, 0.0)));
+        assert_eq!(entities[2].spawnflags(), 1);
+        assert!(!entities[2].has_spawnflag(SPAWNFLAG_NOT_DEATHMATCH));
+        assert_eq!(entities[2].get_float("angle"), Some(-2.0));
+        assert_eq!(entities[0].get_vec3("message"), None);
+
+        let mut e = Entity::new();
+        for &(value, valid) in &[("1 2 3", true), ("1 x 2 3", false), ("1 2", false),
+                                 ("1 2 3 4", false)] {
+            e.set("origin", value);
+            assert_eq!(e.get_vec3("origin").is_some(), valid, "{}", value);
+        }
+        assert_eq!(entities[0].angles(), None);
+    }
+
+    #[test]
+    fn keeps_order_and_last_value_wins() {
+        let entities = parse_entities("{ \"b\" \"1\" \"a\" \"2\" \"b\" \"3\" }").unwrap();
+        let keys: Vec<&str> = entities[0].pairs().iter().map(|p| &p.0[..]).collect();
+        assert_eq!(keys, vec!["b", "a", "b"]);
+        assert_eq!(entities[0].get("b"), Some("3"));
+
+        let mut e = entities[0].clone();
+        e.set("b", "4");
+        e.set("c", "5");
+        assert_eq!(e.get("b"), Some("4"));
+        assert_eq!(e.pairs().len(), 3);
+        e.remove("a");
+        assert_eq!(write_entities(&[e]), "{\n\"b\" \"4\"\n\"c\" \"5\"\n}\n");
+    }
+
+    #[test]
+    fn quotes_are_removed() {
+        let mut e = Entity::new();
+        e.set("mess\"age", "say \"hi\"");
+        assert_eq!(e.get("message"), Some("say hi"));
+        let text = write_entities(&[e.clone()]);
+        assert_eq!(parse_entities(&text).unwrap(), vec![e]);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let entities = parse_entities(TEXT).unwrap();
+        let text = write_entities(&entities);
+        assert_eq!(parse_entities(&text).unwrap(), entities);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_entities("\"key\" \"value\""),
+                   Err(EntityError::ExpectedOpenBrace(1)));
+        assert_eq!(parse_entities("{\n\"key\"\n}"), Err(EntityError::MissingValue(3)));
+        assert_eq!(parse_entities("{ \"key\" \"value"),
+                   Err(EntityError::UnterminatedString(1)));
+        assert_eq!(parse_entities("{ \"key\" \"value\""),
+                   Err(EntityError::UnexpectedEof));
+        assert_eq!(parse_entities("  // nothing\n"), Ok(vec![]));
+    }
+
+    #[test]
+    fn unquoted_non_ascii() {
+        // The second bytes of 'à' and 'Å' are whitespace as Latin-1
+        let entities = parse_entities("{ message voilà\tÅngström café }").unwrap();
+        assert_eq!(entities[0].get("message"), Some("voilà"));
+        assert_eq!(entities[0].get("Ångström"), Some("café"));
+    }
+
+    #[test]
+    fn ent_file_overrides_lump() {
+        let bsp = Bsp::from_bytes(&floor_bsp().build()).unwrap();
+        let ent = b"{ \"classname\" \"worldspawn\" }\n{ \"classname\" \"info_player_start\" \
+                    \"origin\" \"16 16 24\" }";
+        let path = write_pak("entities_override", &[("maps/floor.ent", ent)]);
+        let mut paks = PackContainer::new();
+        paks.read_pack(path).unwrap();
+
+        let patched = load_entities(&mut paks, "maps/floor.bsp", &bsp).unwrap();
+        assert_eq!(patched[1].get("origin"), Some("16 16 24"));
+        let original = load_entities(&mut paks, "maps/other.bsp", &bsp).unwrap();
+        assert_eq!(original[1].get("origin"), Some("0 0 24"));
+    }
+
+    #[test]
+    fn ent_file_read_errors() {
+        let bsp = Bsp::from_bytes(&floor_bsp().build()).unwrap();
+        let path = write_pak("entities_broken", &[("maps/floor.ent", b"{ }")]);
+        // Claim that the file is longer than the PAK
+        let mut bytes = fs::read(&path).unwrap();
+        let len = bytes.len();
+        LittleEndian::write_i32(&mut bytes[len - 4..], 1000);
+        fs::write(&path, bytes).unwrap();
+        let mut paks = PackContainer::new();
+        paks.read_pack(path).unwrap();
+
+        match load_entities(&mut paks, "maps/floor.bsp", &bsp) {
+            Err(BspError::PackError(PackError::IoError(_))) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
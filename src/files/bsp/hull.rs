@@ -0,0 +1,345 @@
+//! Collision detection against the clipping hulls of a BSP.
+//!
+//! Every model has hulls for boxes of three sizes: hull 0 is the node tree
+//! itself and clips points, hull 1 is expanded by the player's box and hull
+//! 2 by the box of large monsters like the shambler. Tracing the origin of a
+//! box through the matching hull is then equivalent to moving the box
+//! through the original brushes.
+
+use files::bsp::{Bsp, NodeChild};
+use util::Vec3;
+
+/// Hull for points, e.g. projectiles.
+pub const HULL_POINT: usize = 0;
+/// Hull for player sized boxes.
+pub const HULL_PLAYER: usize = 1;
+/// Hull for shambler sized boxes.
+pub const HULL_LARGE: usize = 2;
+
+/// The box sizes (mins, maxs) the hulls were expanded by.
+pub const HULL_SIZES: [([f32; 3], [f32; 3]); 3] = [([0.0, 0.0, 0.0], [0.0, 0.0, 0.0]),
+                                                   ([-16.0, -16.0, -24.0], [16.0, 16.0, 32.0]),
+                                                   ([-32.0, -32.0, -24.0], [32.0, 32.0, 64.0])];
+
+/// Traces stop this far in front of a plane, so that the end position isn't
+/// considered inside of the solid behind it because of rounding errors.
+const DIST_EPSILON: f32 = 0.03125;
+
+pub const CONTENTS_EMPTY: i32 = -1;
+pub const CONTENTS_SOLID: i32 = -2;
+pub const CONTENTS_WATER: i32 = -3;
+pub const CONTENTS_SLIME: i32 = -4;
+pub const CONTENTS_LAVA: i32 = -5;
+pub const CONTENTS_SKY: i32 = -6;
+/// The last of the currents, the lowest contents value there is.
+pub const CONTENTS_CURRENT_DOWN: i32 = -14;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Contents {
+    Empty,
+    Solid,
+    Water,
+    Slime,
+    Lava,
+    Sky,
+}
+
+impl Contents {
+    /// Converts a contents value from a leaf or clip node. The currents
+    /// (-9 to -14) are water that pushes players; like in the engine they
+    /// count as water. Unknown values are treated as solid.
+    pub fn from_value(value: i32) -> Contents {
+        match value {
+            CONTENTS_EMPTY => Contents::Empty,
+            CONTENTS_WATER | CONTENTS_CURRENT_DOWN..=-9 => Contents::Water,
+            CONTENTS_SLIME => Contents::Slime,
+            CONTENTS_LAVA => Contents::Lava,
+            CONTENTS_SKY => Contents::Sky,
+            _ => Contents::Solid,
+        }
+    }
+
+    pub fn is_liquid(&self) -> bool {
+        matches!(*self, Contents::Water | Contents::Slime | Contents::Lava)
+    }
+}
+
+/// The result of a trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    /// How far along the move the trace got, from 0 to 1.
+    pub fraction: f32,
+    pub end_pos: Vec3,
+    /// The plane that was hit, facing towards the start of the trace. Only
+    /// valid if `fraction` is less than 1.
+    pub plane_normal: Vec3,
+    pub plane_dist: f32,
+    /// The start position is inside a solid.
+    pub start_solid: bool,
+    /// The whole move is inside a solid.
+    pub all_solid: bool,
+    /// Part of the move is in empty space.
+    pub in_open: bool,
+    /// Part of the move is in a liquid.
+    pub in_water: bool,
+}
+
+impl Trace {
+    fn new(end: Vec3) -> Trace {
+        Trace {
+            fraction: 1.0,
+            end_pos: end,
+            plane_normal: Vec3::new(0.0, 0.0, 0.0),
+            plane_dist: 0.0,
+            start_solid: false,
+            all_solid: true,
+            in_open: false,
+            in_water: false,
+        }
+    }
+}
+
+/// One clipping hull of a model.
+pub struct Hull<'a> {
+    bsp: &'a Bsp,
+    index: usize,
+    first: i32,
+    pub mins: Vec3,
+    pub maxs: Vec3,
+}
+
+impl<'a> Hull<'a> {
+    /// Returns the plane and the children of a node. Hull 0 uses the nodes
+    /// and leafs of the renderer, the others the clip nodes. Leaf contents
+    /// that would be taken for a node count as solid; `Bsp` rejects them, but
+    /// its fields can still be changed afterwards.
+    fn node(&self, num: i32) -> (usize, [i32; 2]) {
+        if self.index == HULL_POINT {
+            let node = &self.bsp.nodes[num as usize];
+            let child = |c: NodeChild| match c {
+                NodeChild::Node(n) => n as i32,
+                NodeChild::Leaf(l) if self.bsp.leafs[l].contents >= 0 => CONTENTS_SOLID,
+                NodeChild::Leaf(l) => self.bsp.leafs[l].contents,
+            };
+            (node.plane, [child(node.children[0]), child(node.children[1])])
+        } else {
+            let node = &self.bsp.clipnodes[num as usize];
+            (node.plane, node.children)
+        }
+    }
+
+    fn distance(&self, plane: usize, point: Vec3) -> f32 {
+        let plane = &self.bsp.planes[plane];
+        point.dot(plane.normal) - plane.dist
+    }
+
+    fn contents_from(&self, mut num: i32, point: Vec3) -> i32 {
+        while num >= 0 {
+            let (plane, children) = self.node(num);
+            num = if self.distance(plane, point) < 0.0 {
+                children[1]
+            } else {
+                children[0]
+            };
+        }
+        num
+    }
+
+    /// The raw contents value at `point`.
+    pub fn contents_value(&self, point: Vec3) -> i32 {
+        self.contents_from(self.first, point)
+    }
+
+    pub fn point_contents(&self, point: Vec3) -> Contents {
+        Contents::from_value(self.contents_value(point))
+    }
+
+    /// Traces a move from `start` to `end` and returns where it stopped.
+    pub fn trace(&self, start: Vec3, end: Vec3) -> Trace {
+        let mut trace = Trace::new(end);
+        self.recursive_check(self.first, 0.0, 1.0, start, end, &mut trace);
+        trace
+    }
+
+    /// Follows the segment from `p1` to `p2` (fractions `p1f` and `p2f` of
+    /// the whole move) through node `num`. Returns false once an impact has
+    /// been recorded in `trace`.
+    fn recursive_check(&self,
+                       num: i32,
+                       p1f: f32,
+                       p2f: f32,
+                       p1: Vec3,
+                       p2: Vec3,
+                       trace: &mut Trace)
+                       -> bool {
+        if num < 0 {
+            if num == CONTENTS_SOLID {
+                trace.start_solid = true;
+            } else {
+                trace.all_solid = false;
+                if num == CONTENTS_EMPTY {
+                    trace.in_open = true;
+                } else {
+                    trace.in_water = true;
+                }
+            }
+            return true;
+        }
+
+        let (plane, children) = self.node(num);
+        let t1 = self.distance(plane, p1);
+        let t2 = self.distance(plane, p2);
+        if t1 >= 0.0 && t2 >= 0.0 {
+            return self.recursive_check(children[0], p1f, p2f, p1, p2, trace);
+        }
+        if t1 < 0.0 && t2 < 0.0 {
+            return self.recursive_check(children[1], p1f, p2f, p1, p2, trace);
+        }
+
+        // Split the segment slightly in front of the plane
+        let frac = if t1 < 0.0 {
+            (t1 + DIST_EPSILON) / (t1 - t2)
+        } else {
+            (t1 - DIST_EPSILON) / (t1 - t2)
+        };
+        let mut frac = frac.clamp(0.0, 1.0);
+        let mut midf = p1f + (p2f - p1f) * frac;
+        let mut mid = p1 + (p2 - p1) * frac;
+        let side = if t1 < 0.0 { 1 } else { 0 };
+
+        if !self.recursive_check(children[side], p1f, midf, p1, mid, trace) {
+            return false;
+        }
+        if self.contents_from(children[side ^ 1], mid) != CONTENTS_SOLID {
+            return self.recursive_check(children[side ^ 1], midf, p2f, mid, p2, trace);
+        }
+        if trace.all_solid {
+            // Never got out of the solid area
+            return false;
+        }
+
+        // The other side of the node is solid, this is the impact point
+        let plane = &self.bsp.planes[plane];
+        if side == 0 {
+            trace.plane_normal = plane.normal;
+            trace.plane_dist = plane.dist;
+        } else {
+            trace.plane_normal = -plane.normal;
+            trace.plane_dist = -plane.dist;
+        }
+
+        // Back up until the point is outside of the solid
+        while self.contents_value(mid) == CONTENTS_SOLID {
+            frac -= 0.1;
+            if frac < 0.0 {
+                trace.fraction = midf;
+                trace.end_pos = mid;
+                return false;
+            }
+            midf = p1f + (p2f - p1f) * frac;
+            mid = p1 + (p2 - p1) * frac;
+        }
+
+        trace.fraction = midf;
+        trace.end_pos = mid;
+        false
+    }
+}
+
+impl Bsp {
+    /// Returns hull `hull` (0 to 2) of model `model`.
+    pub fn hull<'a>(&'a self, model: usize, hull: usize) -> Hull<'a> {
+        assert!(hull < HULL_SIZES.len(), "invalid hull {}", hull);
+        let (mins, maxs) = HULL_SIZES[hull];
+        Hull {
+            bsp: self,
+            index: hull,
+            first: self.models[model].headnodes[hull],
+            mins: Vec3::new(mins[0], mins[1], mins[2]),
+            maxs: Vec3::new(maxs[0], maxs[1], maxs[2]),
+        }
+    }
+
+    /// The contents of the world at `point`.
+    pub fn point_contents(&self, point: Vec3) -> Contents {
+        self.hull(0, HULL_POINT).point_contents(point)
+    }
+
+    /// Traces a box of the size of hull `hull` through the world.
+    pub fn trace(&self, hull: usize, start: Vec3, end: Vec3) -> Trace {
+        self.hull(0, hull).trace(start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use files::bsp::Bsp;
+    use files::testing::floor_bsp;
+    use util::Vec3;
+    use super::{Contents, HULL_PLAYER, HULL_POINT};
+
+    fn floor() -> Bsp {
+        Bsp::from_bytes(&floor_bsp().build()).unwrap()
+    }
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn contents() {
+        let bsp = floor();
+        assert_eq!(bsp.point_contents(Vec3::new(0.0, 0.0, 10.0)), Contents::Empty);
+        assert_eq!(bsp.point_contents(Vec3::new(0.0, 0.0, -10.0)), Contents::Solid);
+        // The player hull is solid up to the height of the player's feet
+        let hull = bsp.hull(0, HULL_PLAYER);
+        assert_eq!(hull.point_contents(Vec3::new(0.0, 0.0, 10.0)), Contents::Solid);
+        assert_eq!(hull.point_contents(Vec3::new(0.0, 0.0, 30.0)), Contents::Empty);
+
+        assert_eq!(Contents::from_value(-5), Contents::Lava);
+        assert_eq!(Contents::from_value(-10), Contents::Water);
+        assert!(Contents::Slime.is_liquid());
+        assert!(!Contents::Sky.is_liquid());
+    }
+
+    #[test]
+    fn corrupt_leaf_contents_are_solid() {
+        // Contents 0 in the empty leaf would lead back to the root node
+        let mut bsp = floor();
+        bsp.leafs[1].contents = 0;
+        assert_eq!(bsp.point_contents(Vec3::new(0.0, 0.0, 10.0)), Contents::Solid);
+        let trace = bsp.trace(HULL_POINT, Vec3::new(0.0, 0.0, 24.0), Vec3::new(0.0, 0.0, -24.0));
+        assert!(trace.all_solid);
+    }
+
+    #[test]
+    fn trace_hits_floor() {
+        let bsp = floor();
+        let trace = bsp.trace(HULL_POINT, Vec3::new(0.0, 0.0, 24.0), Vec3::new(0.0, 0.0, -24.0));
+        assert_near(trace.fraction, (24.0 - 0.03125) / 48.0);
+        assert_near(trace.end_pos.z, 0.03125);
+        assert_eq!(trace.plane_normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(!trace.start_solid && !trace.all_solid && trace.in_open);
+
+        let trace = bsp.trace(HULL_PLAYER, Vec3::new(8.0, 0.0, 48.0), Vec3::new(8.0, 0.0, 0.0));
+        assert_near(trace.end_pos.z, 24.03125);
+        assert_eq!(trace.end_pos.x, 8.0);
+        assert_eq!(trace.plane_dist, 24.0);
+    }
+
+    #[test]
+    fn trace_in_open_and_solid() {
+        let bsp = floor();
+        let end = Vec3::new(32.0, 0.0, 40.0);
+        let trace = bsp.trace(HULL_PLAYER, Vec3::new(0.0, 0.0, 40.0), end);
+        assert_eq!(trace.fraction, 1.0);
+        assert_eq!(trace.end_pos, end);
+
+        let trace = bsp.trace(HULL_PLAYER, Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 48.0));
+        assert!(trace.start_solid && !trace.all_solid);
+        assert_eq!(trace.fraction, 1.0);
+
+        let trace = bsp.trace(HULL_POINT, Vec3::new(0.0, 0.0, -8.0), Vec3::new(8.0, 0.0, -8.0));
+        assert!(trace.start_solid && trace.all_solid);
+    }
+}
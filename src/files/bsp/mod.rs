@@ -0,0 +1,878 @@
+//! Loader for BSP version 29 maps, the format of the `maps/*.bsp` files in
+//! the original Quake PAKs.
+//!
+//! The file starts with a version number and a directory of 15 lumps (offset
+//! and length). Every lump is bounds checked against the file, and every index
+//! one lump makes into another is validated, so the rest of the engine can
+//! index the resulting vectors without checking again.
+
+use std::io;
+use std::io::prelude::*;
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use files::{PackContainer, PackError};
+use files::bsp::hull::CONTENTS_CURRENT_DOWN;
+use util::Vec3;
+use hprof;
+
+pub mod entities;
+pub mod hull;
+pub mod textures;
+pub mod vis;
+
+pub use self::entities::{Entity, EntityError, load_entities};
+pub use self::textures::{TextureKind, TextureSet, WorldTexture};
+pub use self::vis::Pvs;
+
+pub const BSP_VERSION: i32 = 29;
+pub const NUM_LUMPS: usize = 15;
+/// Number of mip levels stored for every texture.
+pub const MIP_LEVELS: usize = 4;
+/// Number of collision hulls per model: point, player and shambler sized,
+/// plus an unused one.
+pub const MAX_HULLS: usize = 4;
+/// Number of light styles a face can combine.
+pub const MAX_LIGHTSTYLES: usize = 4;
+/// Light style slot that isn't used.
+pub const NO_LIGHTSTYLE: u8 = 255;
+/// Size of a lightmap sample in texels.
+pub const LIGHTMAP_SAMPLE: i32 = 16;
+/// Largest width and height of a lightmapped face in texels.
+pub const MAX_SURFACE_EXTENT: i32 = 256;
+
+const LUMP_NAMES: [&'static str; NUM_LUMPS] = ["entities",
+                                                "planes",
+                                                "textures",
+                                                "vertices",
+                                                "visibility",
+                                                "nodes",
+                                                "texinfo",
+                                                "faces",
+                                                "lighting",
+                                                "clipnodes",
+                                                "leafs",
+                                                "marksurfaces",
+                                                "edges",
+                                                "surfedges",
+                                                "models"];
+
+const LUMP_ENTITIES: usize = 0;
+const LUMP_PLANES: usize = 1;
+const LUMP_TEXTURES: usize = 2;
+const LUMP_VERTICES: usize = 3;
+const LUMP_VISIBILITY: usize = 4;
+const LUMP_NODES: usize = 5;
+const LUMP_TEXINFO: usize = 6;
+const LUMP_FACES: usize = 7;
+const LUMP_LIGHTING: usize = 8;
+const LUMP_CLIPNODES: usize = 9;
+const LUMP_LEAFS: usize = 10;
+const LUMP_MARKSURFACES: usize = 11;
+const LUMP_EDGES: usize = 12;
+const LUMP_SURFEDGES: usize = 13;
+const LUMP_MODELS: usize = 14;
+
+#[derive(Debug)]
+pub enum BspError {
+    IoError(io::Error),
+    PackError(PackError),
+    UnsupportedVersion(i32),
+    /// A lump lies (partially) outside of the file.
+    LumpOutOfBounds(&'static str),
+    /// A lump's length isn't a multiple of the size of its elements.
+    BadLumpSize(&'static str),
+    /// An element of the first lump references a nonexistent element.
+    InvalidIndex(&'static str, usize),
+    /// A node of the `nodes` or `clipnodes` lump has a child that doesn't
+    /// come after it, so the tree might loop. qbsp writes parents first.
+    NodeCycle(&'static str, usize),
+    /// A face that isn't sky or liquid is too large for a lightmap.
+    BadSurfaceExtents(usize),
+    /// A leaf's contents isn't one of the `CONTENTS_*` values of `hull`.
+    InvalidContents(usize),
+    /// A texture in the texture lump is malformed.
+    InvalidTexture(usize),
+    /// An animated texture sequence skips a frame.
+    MissingAnimationFrame(String),
+    InvalidEntities(EntityError),
+}
+
+impl From<io::Error> for BspError {
+    fn from(err: io::Error) -> BspError {
+        BspError::IoError(err)
+    }
+}
+
+impl From<PackError> for BspError {
+    fn from(err: PackError) -> BspError {
+        BspError::PackError(err)
+    }
+}
+
+impl From<EntityError> for BspError {
+    fn from(err: EntityError) -> BspError {
+        BspError::InvalidEntities(err)
+    }
+}
+
+pub type BspResult<T> = Result<T, BspError>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub dist: f32,
+    /// 0-2 for planes along the x, y or z axis, 3-5 for planes that are
+    /// closest to being axial.
+    pub kind: i32,
+}
+
+/// A texture as stored in the texture lump: 8 bit palette indices for four
+/// mip levels, each half the size of the previous one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MipTex {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub mips: [Vec<u8>; MIP_LEVELS],
+}
+
+/// One of the two children of a node.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NodeChild {
+    Node(usize),
+    Leaf(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub plane: usize,
+    /// The children in front of and behind the plane.
+    pub children: [NodeChild; 2],
+    pub mins: Vec3,
+    pub maxs: Vec3,
+    pub first_face: usize,
+    pub num_faces: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TexInfo {
+    /// Texture coordinates of a point p are `p.dot(s) + s_offset` and
+    /// `p.dot(t) + t_offset`.
+    pub s: Vec3,
+    pub s_offset: f32,
+    pub t: Vec3,
+    pub t_offset: f32,
+    pub miptex: usize,
+    pub flags: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Face {
+    pub plane: usize,
+    /// Whether the face lies on the back side of its plane.
+    pub back_side: bool,
+    /// Index of the first surfedge.
+    pub first_edge: usize,
+    pub num_edges: usize,
+    pub texinfo: usize,
+    pub styles: [u8; MAX_LIGHTSTYLES],
+    /// Offset into the lighting lump, or `None` if the face isn't lit.
+    pub light_offset: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipNode {
+    pub plane: usize,
+    /// Non-negative values are clip node indices, negative ones are
+    /// contents values.
+    pub children: [i32; 2],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leaf {
+    pub contents: i32,
+    /// Offset of the compressed PVS in the visibility lump, or `None` if
+    /// everything is visible from this leaf.
+    pub vis_offset: Option<usize>,
+    pub mins: Vec3,
+    pub maxs: Vec3,
+    pub first_marksurface: usize,
+    pub num_marksurfaces: usize,
+    pub ambient_level: [u8; 4],
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Edge {
+    pub vertices: [usize; 2],
+}
+
+/// A model is the world (model 0) or one of the brush entities (doors,
+/// platforms, ...) of the map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Model {
+    pub mins: Vec3,
+    pub maxs: Vec3,
+    pub origin: Vec3,
+    /// Root node (hull 0) and root clip nodes (hulls 1-3).
+    pub headnodes: [i32; MAX_HULLS],
+    pub visleafs: usize,
+    pub first_face: usize,
+    pub num_faces: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Bsp {
+    pub entities: String,
+    pub planes: Vec<Plane>,
+    /// Textures can be missing from the lump (if the compiler couldn't find
+    /// them), those are `None`.
+    pub textures: Vec<Option<MipTex>>,
+    pub vertices: Vec<Vec3>,
+    pub visibility: Vec<u8>,
+    pub nodes: Vec<Node>,
+    pub texinfo: Vec<TexInfo>,
+    pub faces: Vec<Face>,
+    pub lighting: Vec<u8>,
+    pub clipnodes: Vec<ClipNode>,
+    pub leafs: Vec<Leaf>,
+    /// Face indices, referenced by leafs.
+    pub marksurfaces: Vec<usize>,
+    pub edges: Vec<Edge>,
+    /// Edge indices, referenced by faces. A negative value means that the
+    /// edge is used in the opposite direction.
+    pub surfedges: Vec<i32>,
+    pub models: Vec<Model>,
+}
+
+/// Directory entry of a lump.
+#[derive(Debug, Copy, Clone)]
+struct Lump {
+    offset: i32,
+    length: i32,
+}
+
+impl Bsp {
+    /// Reads and parses a map from the PAK files, e.g. `maps/e1m1.bsp`.
+    pub fn load(paks: &mut PackContainer, name: &str) -> BspResult<Bsp> {
+        let bytes = try!(paks.read(name));
+        Bsp::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> BspResult<Bsp> {
+        let _guard = hprof::enter("Bsp::from_bytes");
+        let mut rdr = io::Cursor::new(data);
+        let version = try!(rdr.read_i32::<LittleEndian>());
+        if version != BSP_VERSION {
+            return Err(BspError::UnsupportedVersion(version));
+        }
+        let mut lumps = [Lump {
+            offset: 0,
+            length: 0,
+        }; NUM_LUMPS];
+        for lump in lumps.iter_mut() {
+            lump.offset = try!(rdr.read_i32::<LittleEndian>());
+            lump.length = try!(rdr.read_i32::<LittleEndian>());
+        }
+
+        let lump = |i: usize, size: usize| lump_bytes(data, &lumps[i], LUMP_NAMES[i], size);
+
+        let bsp = Bsp {
+            entities: try!(read_entities(try!(lump(LUMP_ENTITIES, 1)))),
+            planes: try!(read_planes(try!(lump(LUMP_PLANES, 20)))),
+            textures: try!(read_textures(try!(lump(LUMP_TEXTURES, 1)))),
+            vertices: try!(read_all(try!(lump(LUMP_VERTICES, 12)), read_vec3)),
+            visibility: try!(lump(LUMP_VISIBILITY, 1)).to_vec(),
+            nodes: try!(read_all(try!(lump(LUMP_NODES, 24)), read_node)),
+            texinfo: try!(read_all(try!(lump(LUMP_TEXINFO, 40)), read_texinfo)),
+            faces: try!(read_all(try!(lump(LUMP_FACES, 20)), read_face)),
+            lighting: try!(lump(LUMP_LIGHTING, 1)).to_vec(),
+            clipnodes: try!(read_all(try!(lump(LUMP_CLIPNODES, 8)), read_clipnode)),
+            leafs: try!(read_all(try!(lump(LUMP_LEAFS, 28)), read_leaf)),
+            marksurfaces: try!(read_all(try!(lump(LUMP_MARKSURFACES, 2)),
+                                        |r| r.read_u16::<LittleEndian>().map(|i| i as usize))),
+            edges: try!(read_all(try!(lump(LUMP_EDGES, 4)), read_edge)),
+            surfedges: try!(read_all(try!(lump(LUMP_SURFEDGES, 4)),
+                                     |r| r.read_i32::<LittleEndian>())),
+            models: try!(read_all(try!(lump(LUMP_MODELS, 64)), read_model)),
+        };
+        try!(bsp.validate());
+        Ok(bsp)
+    }
+
+    /// The world model, which contains all static geometry.
+    pub fn world(&self) -> &Model {
+        &self.models[0]
+    }
+
+    /// Returns the index of the vertex an entry of the surfedge list starts
+    /// at, taking the edge direction into account.
+    pub fn surfedge_vertex(&self, surfedge: usize) -> usize {
+        let e = self.surfedges[surfedge];
+        if e >= 0 {
+            self.edges[e as usize].vertices[0]
+        } else {
+            self.edges[e.unsigned_abs() as usize].vertices[1]
+        }
+    }
+
+    /// The vertices of a face in order.
+    pub fn face_vertices(&self, face: &Face) -> Vec<Vec3> {
+        (face.first_edge..face.first_edge + face.num_edges)
+            .map(|i| self.vertices[self.surfedge_vertex(i)])
+            .collect()
+    }
+
+    /// The smallest and largest texture coordinates of a face's vertices.
+    /// They are calculated in double precision like the original, so that
+    /// lightmaps line up the same way.
+    pub fn texture_bounds(&self, face: &Face) -> ([f64; 2], [f64; 2]) {
+        let texinfo = &self.texinfo[face.texinfo];
+        let mut mins = [f64::MAX; 2];
+        let mut maxs = [f64::MIN; 2];
+        for v in self.face_vertices(face) {
+            let coords = [v.dot(texinfo.s) as f64 + texinfo.s_offset as f64,
+                          v.dot(texinfo.t) as f64 + texinfo.t_offset as f64];
+            for i in 0..2 {
+                mins[i] = mins[i].min(coords[i]);
+                maxs[i] = maxs[i].max(coords[i]);
+            }
+        }
+        (mins, maxs)
+    }
+
+    /// Whether the lightmap of a face is small enough, like the "Bad surface
+    /// extents" check of `CalcSurfaceExtents`. Its corners must also fit
+    /// into integers.
+    fn extents_ok(&self, face: &Face) -> bool {
+        let (mins, maxs) = self.texture_bounds(face);
+        let sample = LIGHTMAP_SAMPLE as f64;
+        (0..2).all(|i| {
+            let bmin = (mins[i] / sample).floor() * sample;
+            let bmax = (maxs[i] / sample).ceil() * sample;
+            bmax - bmin <= MAX_SURFACE_EXTENT as f64 && bmin >= i32::MIN as f64 &&
+            bmax <= i32::MAX as f64
+        })
+    }
+
+    /// Checks that all references between lumps are in bounds, that the
+    /// node trees can be walked without looping, that leafs have valid
+    /// contents and that the faces fit their lightmaps.
+    fn validate(&self) -> BspResult<()> {
+        let check = |ok: bool, lump: &'static str, index: usize| {
+            if ok {
+                Ok(())
+            } else {
+                Err(BspError::InvalidIndex(lump, index))
+            }
+        };
+        let range_ok = |first: usize, count: usize, len: usize| first + count <= len;
+
+        if self.models.is_empty() {
+            return Err(BspError::BadLumpSize("models"));
+        }
+        for (i, e) in self.edges.iter().enumerate() {
+            try!(check(e.vertices.iter().all(|&v| v < self.vertices.len()), "edges", i));
+        }
+        for (i, &e) in self.surfedges.iter().enumerate() {
+            try!(check((e.unsigned_abs() as usize) < self.edges.len(), "surfedges", i));
+        }
+        for (i, t) in self.texinfo.iter().enumerate() {
+            try!(check(t.miptex < self.textures.len(), "texinfo", i));
+        }
+        for (i, f) in self.faces.iter().enumerate() {
+            let ok = f.plane < self.planes.len() && f.texinfo < self.texinfo.len() &&
+                     f.num_edges >= 3 &&
+                     range_ok(f.first_edge, f.num_edges, self.surfedges.len()) &&
+                     f.light_offset.map_or(true, |o| o < self.lighting.len());
+            try!(check(ok, "faces", i));
+        }
+        for (i, &m) in self.marksurfaces.iter().enumerate() {
+            try!(check(m < self.faces.len(), "marksurfaces", i));
+        }
+        for (i, n) in self.nodes.iter().enumerate() {
+            let children_ok = n.children.iter().all(|c| match *c {
+                NodeChild::Node(c) => c < self.nodes.len(),
+                NodeChild::Leaf(l) => l < self.leafs.len(),
+            });
+            let ok = n.plane < self.planes.len() && children_ok &&
+                     range_ok(n.first_face, n.num_faces, self.faces.len());
+            try!(check(ok, "nodes", i));
+            let acyclic = n.children.iter().all(|c| match *c {
+                NodeChild::Node(c) => c > i,
+                NodeChild::Leaf(_) => true,
+            });
+            if !acyclic {
+                return Err(BspError::NodeCycle("nodes", i));
+            }
+        }
+        for (i, c) in self.clipnodes.iter().enumerate() {
+            let ok = c.plane < self.planes.len() &&
+                     c.children.iter().all(|&c| c < 0 || (c as usize) < self.clipnodes.len());
+            try!(check(ok, "clipnodes", i));
+            if !c.children.iter().all(|&c| c < 0 || c as usize > i) {
+                return Err(BspError::NodeCycle("clipnodes", i));
+            }
+        }
+        for (i, l) in self.leafs.iter().enumerate() {
+            let ok = range_ok(l.first_marksurface,
+                              l.num_marksurfaces,
+                              self.marksurfaces.len()) &&
+                     l.vis_offset.map_or(true, |o| o < self.visibility.len());
+            try!(check(ok, "leafs", i));
+            // Walks through hull 0 take non-negative contents for nodes
+            if l.contents >= 0 || l.contents < CONTENTS_CURRENT_DOWN {
+                return Err(BspError::InvalidContents(i));
+            }
+        }
+        for (i, m) in self.models.iter().enumerate() {
+            let hulls_ok = m.headnodes[1..]
+                               .iter()
+                               .all(|&h| h < 0 || (h as usize) < self.clipnodes.len());
+            let ok = m.headnodes[0] >= 0 && (m.headnodes[0] as usize) < self.nodes.len() &&
+                     hulls_ok && m.visleafs < self.leafs.len() &&
+                     range_ok(m.first_face, m.num_faces, self.faces.len());
+            try!(check(ok, "models", i));
+        }
+        // Sky and liquids aren't lightmapped, so they can be of any size
+        for (i, f) in self.faces.iter().enumerate() {
+            let special = match self.textures[self.texinfo[f.texinfo].miptex] {
+                Some(ref t) => TextureKind::from_name(&t.name).is_special(),
+                None => false,
+            };
+            if !special && !self.extents_ok(f) {
+                return Err(BspError::BadSurfaceExtents(i));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the bytes of a lump after checking that it lies within the file
+/// and consists of whole elements of `size` bytes.
+fn lump_bytes<'a>(data: &'a [u8],
+                  lump: &Lump,
+                  name: &'static str,
+                  size: usize)
+                  -> BspResult<&'a [u8]> {
+    if lump.offset < 0 || lump.length < 0 {
+        return Err(BspError::LumpOutOfBounds(name));
+    }
+    let start = lump.offset as usize;
+    let end = start + lump.length as usize;
+    if end > data.len() {
+        return Err(BspError::LumpOutOfBounds(name));
+    }
+    if lump.length as usize % size != 0 {
+        return Err(BspError::BadLumpSize(name));
+    }
+    Ok(&data[start..end])
+}
+
+/// Reads elements with `read` until the lump is exhausted.
+fn read_all<'a, T, F>(bytes: &'a [u8], read: F) -> BspResult<Vec<T>>
+    where F: Fn(&mut io::Cursor<&'a [u8]>) -> io::Result<T>
+{
+    let mut rdr = io::Cursor::new(bytes);
+    let mut items = vec![];
+    while (rdr.position() as usize) < bytes.len() {
+        items.push(try!(read(&mut rdr)));
+    }
+    Ok(items)
+}
+
+fn read_vec3<R: Read>(rdr: &mut R) -> io::Result<Vec3> {
+    let x = try!(rdr.read_f32::<LittleEndian>());
+    let y = try!(rdr.read_f32::<LittleEndian>());
+    let z = try!(rdr.read_f32::<LittleEndian>());
+    Ok(Vec3::new(x, y, z))
+}
+
+/// Reads a bounding box corner stored as three shorts.
+fn read_short_vec3<R: Read>(rdr: &mut R) -> io::Result<Vec3> {
+    let x = try!(rdr.read_i16::<LittleEndian>());
+    let y = try!(rdr.read_i16::<LittleEndian>());
+    let z = try!(rdr.read_i16::<LittleEndian>());
+    Ok(Vec3::new(x as f32, y as f32, z as f32))
+}
+
+/// Turns a fixed size, NUL padded name into a string.
+fn read_name(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn read_entities(bytes: &[u8]) -> BspResult<String> {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+fn read_planes(bytes: &[u8]) -> BspResult<Vec<Plane>> {
+    read_all(bytes, |rdr| {
+        let normal = try!(read_vec3(rdr));
+        let dist = try!(rdr.read_f32::<LittleEndian>());
+        let kind = try!(rdr.read_i32::<LittleEndian>());
+        Ok(Plane {
+            normal: normal,
+            dist: dist,
+            kind: kind,
+        })
+    })
+}
+
+fn read_textures(bytes: &[u8]) -> BspResult<Vec<Option<MipTex>>> {
+    if bytes.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut rdr = io::Cursor::new(bytes);
+    let count = try!(rdr.read_i32::<LittleEndian>());
+    if count < 0 || 4 + count as usize * 4 > bytes.len() {
+        return Err(BspError::BadLumpSize("textures"));
+    }
+
+    let mut textures = vec![];
+    for i in 0..count as usize {
+        let offset = try!(rdr.read_i32::<LittleEndian>());
+        if offset < 0 {
+            textures.push(None);
+        } else {
+            let texture = try!(read_miptex(bytes, offset as usize).ok_or(BspError::InvalidTexture(i)));
+            textures.push(Some(texture));
+        }
+    }
+    Ok(textures)
+}
+
+/// Reads the texture starting at `offset` in the texture lump. Returns `None`
+/// if the texture or any of its mip levels doesn't fit into the lump.
+fn read_miptex(lump: &[u8], offset: usize) -> Option<MipTex> {
+    const HEADER_SIZE: usize = 16 + 4 + 4 + 4 * MIP_LEVELS;
+    if offset + HEADER_SIZE > lump.len() {
+        return None;
+    }
+    let data = &lump[offset..];
+    let mut rdr = io::Cursor::new(&data[16..HEADER_SIZE]);
+    let width = rdr.read_u32::<LittleEndian>().unwrap();
+    let height = rdr.read_u32::<LittleEndian>().unwrap();
+    if width == 0 || height == 0 || width % 16 != 0 || height % 16 != 0 ||
+       width > 4096 || height > 4096 {
+        return None;
+    }
+
+    let mut mips: [Vec<u8>; MIP_LEVELS] = Default::default();
+    for (level, mip) in mips.iter_mut().enumerate() {
+        let start = rdr.read_u32::<LittleEndian>().unwrap() as usize;
+        let size = ((width >> level) * (height >> level)) as usize;
+        if start + size > data.len() {
+            return None;
+        }
+        *mip = data[start..start + size].to_vec();
+    }
+
+    Some(MipTex {
+        name: read_name(&data[..16]),
+        width: width,
+        height: height,
+        mips: mips,
+    })
+}
+
+fn read_node(rdr: &mut io::Cursor<&[u8]>) -> io::Result<Node> {
+    let plane = try!(rdr.read_i32::<LittleEndian>());
+    let front = try!(rdr.read_i16::<LittleEndian>());
+    let back = try!(rdr.read_i16::<LittleEndian>());
+    let mins = try!(read_short_vec3(rdr));
+    let maxs = try!(read_short_vec3(rdr));
+    let first_face = try!(rdr.read_u16::<LittleEndian>());
+    let num_faces = try!(rdr.read_u16::<LittleEndian>());
+    let child = |c: i16| if c >= 0 {
+        NodeChild::Node(c as usize)
+    } else {
+        NodeChild::Leaf((-(c as i32) - 1) as usize)
+    };
+
+    Ok(Node {
+        // Negative plane numbers end up out of bounds and fail validation
+        plane: plane as u32 as usize,
+        children: [child(front), child(back)],
+        mins: mins,
+        maxs: maxs,
+        first_face: first_face as usize,
+        num_faces: num_faces as usize,
+    })
+}
+
+fn read_texinfo(rdr: &mut io::Cursor<&[u8]>) -> io::Result<TexInfo> {
+    let s = try!(read_vec3(rdr));
+    let s_offset = try!(rdr.read_f32::<LittleEndian>());
+    let t = try!(read_vec3(rdr));
+    let t_offset = try!(rdr.read_f32::<LittleEndian>());
+    let miptex = try!(rdr.read_i32::<LittleEndian>());
+    let flags = try!(rdr.read_i32::<LittleEndian>());
+    Ok(TexInfo {
+        s: s,
+        s_offset: s_offset,
+        t: t,
+        t_offset: t_offset,
+        miptex: miptex as u32 as usize,
+        flags: flags,
+    })
+}
+
+fn read_face(rdr: &mut io::Cursor<&[u8]>) -> io::Result<Face> {
+    let plane = try!(rdr.read_u16::<LittleEndian>());
+    let side = try!(rdr.read_i16::<LittleEndian>());
+    let first_edge = try!(rdr.read_i32::<LittleEndian>());
+    let num_edges = try!(rdr.read_u16::<LittleEndian>());
+    let texinfo = try!(rdr.read_u16::<LittleEndian>());
+    let mut styles = [0; MAX_LIGHTSTYLES];
+    try!(rdr.read_exact(&mut styles));
+    let light_offset = try!(rdr.read_i32::<LittleEndian>());
+    Ok(Face {
+        plane: plane as usize,
+        back_side: side != 0,
+        first_edge: first_edge as u32 as usize,
+        num_edges: num_edges as usize,
+        texinfo: texinfo as usize,
+        styles: styles,
+        light_offset: if light_offset < 0 {
+            None
+        } else {
+            Some(light_offset as usize)
+        },
+    })
+}
+
+fn read_clipnode(rdr: &mut io::Cursor<&[u8]>) -> io::Result<ClipNode> {
+    let plane = try!(rdr.read_i32::<LittleEndian>());
+    let front = try!(rdr.read_i16::<LittleEndian>());
+    let back = try!(rdr.read_i16::<LittleEndian>());
+    Ok(ClipNode {
+        plane: plane as u32 as usize,
+        children: [front as i32, back as i32],
+    })
+}
+
+fn read_leaf(rdr: &mut io::Cursor<&[u8]>) -> io::Result<Leaf> {
+    let contents = try!(rdr.read_i32::<LittleEndian>());
+    let vis_offset = try!(rdr.read_i32::<LittleEndian>());
+    let mins = try!(read_short_vec3(rdr));
+    let maxs = try!(read_short_vec3(rdr));
+    let first_marksurface = try!(rdr.read_u16::<LittleEndian>());
+    let num_marksurfaces = try!(rdr.read_u16::<LittleEndian>());
+    let mut ambient_level = [0; 4];
+    try!(rdr.read_exact(&mut ambient_level));
+    Ok(Leaf {
+        contents: contents,
+        vis_offset: if vis_offset < 0 {
+            None
+        } else {
+            Some(vis_offset as usize)
+        },
+        mins: mins,
+        maxs: maxs,
+        first_marksurface: first_marksurface as usize,
+        num_marksurfaces: num_marksurfaces as usize,
+        ambient_level: ambient_level,
+    })
+}
+
+fn read_edge(rdr: &mut io::Cursor<&[u8]>) -> io::Result<Edge> {
+    let v0 = try!(rdr.read_u16::<LittleEndian>());
+    let v1 = try!(rdr.read_u16::<LittleEndian>());
+    Ok(Edge { vertices: [v0 as usize, v1 as usize] })
+}
+
+fn read_model(rdr: &mut io::Cursor<&[u8]>) -> io::Result<Model> {
+    let mins = try!(read_vec3(rdr));
+    let maxs = try!(read_vec3(rdr));
+    let origin = try!(read_vec3(rdr));
+    let mut headnodes = [0; MAX_HULLS];
+    for h in headnodes.iter_mut() {
+        *h = try!(rdr.read_i32::<LittleEndian>());
+    }
+    let visleafs = try!(rdr.read_i32::<LittleEndian>());
+    let first_face = try!(rdr.read_i32::<LittleEndian>());
+    let num_faces = try!(rdr.read_i32::<LittleEndian>());
+    Ok(Model {
+        mins: mins,
+        maxs: maxs,
+        origin: origin,
+        headnodes: headnodes,
+        visleafs: visleafs as u32 as usize,
+        first_face: first_face as u32 as usize,
+        num_faces: num_faces as u32 as usize,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use files::PackContainer;
+    use byteorder::{ByteOrder, LittleEndian};
+    use files::testing::{floor_bsp, floor_bsp_with, write_pak};
+    use util::Vec3;
+    use super::{Bsp, BspError, LUMP_TEXINFO, NodeChild};
+
+    #[test]
+    fn parse_floor() {
+        let bsp = Bsp::from_bytes(&floor_bsp().build()).unwrap();
+        assert!(bsp.entities.starts_with("{\n\"classname\" \"worldspawn\""));
+        assert_eq!(bsp.planes.len(), 2);
+        assert_eq!(bsp.planes[1].normal, Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(bsp.planes[1].dist, 24.0);
+        assert_eq!(bsp.vertices.len(), 4);
+        assert_eq!(bsp.nodes[0].children, [NodeChild::Leaf(1), NodeChild::Leaf(0)]);
+        assert_eq!(bsp.leafs[1].vis_offset, Some(0));
+        assert_eq!(bsp.leafs[0].vis_offset, None);
+        assert_eq!(bsp.leafs[1].maxs, Vec3::new(64.0, 64.0, 64.0));
+        assert_eq!(bsp.faces[0].light_offset, Some(0));
+        assert_eq!(bsp.faces[0].styles, [0, 255, 255, 255]);
+        assert_eq!(bsp.lighting.len(), 81);
+        assert_eq!(bsp.clipnodes[0].children, [-1, -2]);
+        assert_eq!(bsp.world().visleafs, 1);
+
+        let texture = bsp.textures[0].as_ref().unwrap();
+        assert_eq!(texture.name, "floor");
+        assert_eq!((texture.width, texture.height), (16, 16));
+        assert_eq!(texture.mips[0].len(), 256);
+        assert_eq!(texture.mips[3].len(), 4);
+    }
+
+    #[test]
+    fn face_vertices_follow_edge_directions() {
+        let bsp = Bsp::from_bytes(&floor_bsp().build()).unwrap();
+        let verts = bsp.face_vertices(&bsp.faces[0]);
+        assert_eq!(verts,
+                   vec![Vec3::new(-64.0, -64.0, 0.0),
+                        Vec3::new(-64.0, 64.0, 0.0),
+                        Vec3::new(64.0, 64.0, 0.0),
+                        Vec3::new(64.0, -64.0, 0.0)]);
+    }
+
+    #[test]
+    fn rejects_wrong_version() {
+        let mut bytes = floor_bsp().build();
+        bytes[0] = 30;
+        match Bsp::from_bytes(&bytes) {
+            Err(BspError::UnsupportedVersion(30)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = floor_bsp().build();
+        for &len in &[0, 10, 100, bytes.len() - 1] {
+            assert!(Bsp::from_bytes(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_bad_lump_size() {
+        let mut builder = floor_bsp();
+        builder.lump_mut(3).push(0);
+        match Bsp::from_bytes(&builder.build()) {
+            Err(BspError::BadLumpSize("vertices")) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_indices() {
+        let mut builder = floor_bsp();
+        builder.edge(0, 4);
+        match Bsp::from_bytes(&builder.build()) {
+            Err(BspError::InvalidIndex("edges", 5)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let mut builder = floor_bsp();
+        builder.surfedge(-9);
+        assert!(Bsp::from_bytes(&builder.build()).is_err());
+
+        let mut builder = floor_bsp();
+        builder.node(0, [-3, -1], [0; 3], [0; 3], (0, 0));
+        match Bsp::from_bytes(&builder.build()) {
+            Err(BspError::InvalidIndex("nodes", 1)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_surface_extents() {
+        // The floor is 128 units wide, with 4 texels per unit
+        let stretched = |texture: &str| {
+            let mut builder = floor_bsp_with(texture, 16, 16, |_, _, _| 7);
+            LittleEndian::write_f32(&mut builder.lump_mut(LUMP_TEXINFO)[..4], 4.0);
+            Bsp::from_bytes(&builder.build())
+        };
+        match stretched("floor") {
+            Err(BspError::BadSurfaceExtents(0)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(stretched("*water").is_ok());
+        assert!(stretched("sky1").is_ok());
+    }
+
+    #[test]
+    fn rejects_node_cycles() {
+        // A node that is its own child
+        let mut builder = floor_bsp();
+        builder.node(0, [1, -1], [0; 3], [0; 3], (0, 0));
+        match Bsp::from_bytes(&builder.build()) {
+            Err(BspError::NodeCycle("nodes", 1)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // A clip node pointing back at its parent
+        let mut builder = floor_bsp();
+        builder.clipnode(0, [1, -1]).clipnode(0, [-2, 0]);
+        match Bsp::from_bytes(&builder.build()) {
+            Err(BspError::NodeCycle("clipnodes", 1)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_leaf_contents() {
+        // Contents 0 would send a walk through hull 0 back to node 0
+        for &contents in &[0, 5, -15] {
+            let mut builder = floor_bsp();
+            builder.leaf(contents, -1, [0; 3], [0; 3], (0, 0));
+            match Bsp::from_bytes(&builder.build()) {
+                Err(BspError::InvalidContents(2)) => {}
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+        let mut builder = floor_bsp();
+        builder.leaf(-14, -1, [0; 3], [0; 3], (0, 0));
+        assert!(Bsp::from_bytes(&builder.build()).is_ok());
+    }
+
+    #[test]
+    fn rejects_broken_textures() {
+        let mut builder = floor_bsp();
+        builder.texture("huge", 4096, 4096, 0);
+        let mut bytes = builder.build();
+        // Cut off the pixels of the second texture
+        let len = bytes.len();
+        bytes.truncate(len - 4096 * 4096);
+        assert!(Bsp::from_bytes(&bytes).is_err());
+
+        let mut builder = floor_bsp();
+        builder.texture("odd", 15, 16, 0);
+        match Bsp::from_bytes(&builder.build()) {
+            Err(BspError::InvalidTexture(1)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn load_from_pak() {
+        let path = write_pak("bsp_load", &[("maps/floor.bsp", &floor_bsp().build())]);
+        let mut paks = PackContainer::new();
+        paks.read_pack(path).unwrap();
+        let bsp = Bsp::load(&mut paks, "maps/floor.bsp").unwrap();
+        assert_eq!(bsp.faces.len(), 1);
+        assert!(Bsp::load(&mut paks, "maps/missing.bsp").is_err());
+    }
+}
@@ -0,0 +1,320 @@
+//! Decoding of the miptex lump into textures for the rasterizers.
+//!
+//! Texture names carry meaning: `sky*` textures are drawn as the scrolling
+//! sky, `*` textures (water, slime, lava, teleporters) are warped, and
+//! `+0name` to `+9name` are the frames of an animation. Buttons and other
+//! entities can switch to an alternate animation `+aname` to `+jname`.
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use drawing::{Palette, Texture};
+use files::bsp::{Bsp, BspError, BspResult, MIP_LEVELS, MipTex};
+use files::png;
+
+/// Maximum number of frames in an animation sequence.
+pub const MAX_ANIMATION_FRAMES: usize = 10;
+/// Animation frames per second.
+pub const ANIMATION_FPS: f32 = 5.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextureKind {
+    Normal,
+    Sky,
+    /// Liquids and teleporters, drawn with a warp instead of lightmaps.
+    Turbulent,
+    /// Invisible brushes that only block movement.
+    Clip,
+    /// Invisible brushes of trigger entities.
+    Trigger,
+}
+
+impl TextureKind {
+    pub fn from_name(name: &str) -> TextureKind {
+        let name = name.to_lowercase();
+        if name.starts_with("sky") {
+            TextureKind::Sky
+        } else if name.starts_with('*') {
+            TextureKind::Turbulent
+        } else if name == "clip" {
+            TextureKind::Clip
+        } else if name.starts_with("trigger") {
+            TextureKind::Trigger
+        } else {
+            TextureKind::Normal
+        }
+    }
+
+    /// Whether surfaces with this texture are drawn without lightmaps.
+    pub fn is_special(&self) -> bool {
+        matches!(*self, TextureKind::Sky | TextureKind::Turbulent)
+    }
+
+    /// Whether surfaces with this texture aren't drawn at all.
+    pub fn is_invisible(&self) -> bool {
+        matches!(*self, TextureKind::Clip | TextureKind::Trigger)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorldTexture {
+    pub name: String,
+    pub kind: TextureKind,
+    /// The mip levels, each half the size of the previous one.
+    pub mips: Vec<Texture>,
+    /// Texture indices of the animation this texture belongs to, in order.
+    /// Empty if the texture isn't animated.
+    pub frames: Vec<usize>,
+    /// Texture indices of the alternate animation, if there is one.
+    pub alternate_frames: Vec<usize>,
+}
+
+impl WorldTexture {
+    fn new(miptex: &MipTex) -> WorldTexture {
+        let mips = miptex.mips
+                         .iter()
+                         .enumerate()
+                         .map(|(level, pixels)| {
+                             Texture::new((miptex.width >> level) as usize,
+                                          (miptex.height >> level) as usize,
+                                          pixels.clone())
+                         })
+                         .collect();
+        WorldTexture {
+            name: miptex.name.clone(),
+            kind: TextureKind::from_name(&miptex.name),
+            mips: mips,
+            frames: vec![],
+            alternate_frames: vec![],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.mips[0].width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.mips[0].height()
+    }
+
+    pub fn mip(&self, level: usize) -> &Texture {
+        &self.mips[level.min(MIP_LEVELS - 1)]
+    }
+
+    /// Writes a mip level as an indexed PNG image.
+    pub fn write_png<W: Write>(&self,
+                               out: &mut W,
+                               level: usize,
+                               palette: &Palette)
+                               -> io::Result<()> {
+        let mip = self.mip(level);
+        png::write_indexed(out,
+                           mip.width() as u32,
+                           mip.height() as u32,
+                           &palette.to_bytes(),
+                           mip.pixels())
+    }
+}
+
+/// Splits an animation frame name like `+1slip` into the frame number,
+/// whether it is part of the alternate sequence and the base name.
+fn parse_animation_name(name: &str) -> Option<(usize, bool, &str)> {
+    let bytes = name.as_bytes();
+    if bytes.len() < 2 || bytes[0] != b'+' {
+        return None;
+    }
+    match bytes[1].to_ascii_lowercase() {
+        c @ b'0'..=b'9' => Some(((c - b'0') as usize, false, &name[2..])),
+        c @ b'a'..=b'j' => Some(((c - b'a') as usize, true, &name[2..])),
+        _ => None,
+    }
+}
+
+/// All textures of a map, with their animation chains resolved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureSet {
+    textures: Vec<Option<WorldTexture>>,
+}
+
+impl TextureSet {
+    /// Decodes the textures of `bsp`. Fails if an animation sequence has a
+    /// gap, e.g. `+0lava` and `+2lava` without `+1lava`.
+    pub fn new(bsp: &Bsp) -> BspResult<TextureSet> {
+        let mut textures: Vec<_> = bsp.textures
+                                      .iter()
+                                      .map(|t| t.as_ref().map(WorldTexture::new))
+                                      .collect();
+
+        for i in 0..textures.len() {
+            let base = match textures[i] {
+                // Textures of an animation that was already resolved are skipped
+                Some(ref t) if t.frames.is_empty() && t.alternate_frames.is_empty() => {
+                    match parse_animation_name(&t.name) {
+                        Some((_, _, base)) => base.to_lowercase(),
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+
+            let mut primary = [None; MAX_ANIMATION_FRAMES];
+            let mut alternate = [None; MAX_ANIMATION_FRAMES];
+            for (j, t) in textures.iter().enumerate() {
+                let t = match *t {
+                    Some(ref t) => t,
+                    None => continue,
+                };
+                if let Some((frame, alt, name)) = parse_animation_name(&t.name) {
+                    if name.to_lowercase() == base {
+                        let sequence = if alt { &mut alternate } else { &mut primary };
+                        sequence[frame] = Some(j);
+                    }
+                }
+            }
+            let primary = try!(sequence_indices(&primary, &base));
+            let alternate = try!(sequence_indices(&alternate, &base));
+
+            for &j in primary.iter().chain(alternate.iter()) {
+                if let Some(ref mut t) = textures[j] {
+                    t.frames = primary.clone();
+                    t.alternate_frames = alternate.clone();
+                }
+            }
+        }
+
+        Ok(TextureSet { textures: textures })
+    }
+
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+
+    /// Returns a texture, or `None` for missing textures.
+    pub fn get(&self, index: usize) -> Option<&WorldTexture> {
+        self.textures.get(index).and_then(|t| t.as_ref())
+    }
+
+    /// Returns the texture to draw instead of `index` at `time` seconds.
+    /// With `alternate` set (e.g. a pressed button), the alternate sequence is
+    /// used if the texture has one.
+    pub fn animation_frame(&self, index: usize, time: f32, alternate: bool) -> usize {
+        let texture = match self.get(index) {
+            Some(t) => t,
+            None => return index,
+        };
+        let sequence = if alternate && !texture.alternate_frames.is_empty() {
+            &texture.alternate_frames
+        } else if !texture.frames.is_empty() {
+            &texture.frames
+        } else {
+            return index;
+        };
+        let frame = (time.max(0.0) * ANIMATION_FPS) as usize;
+        sequence[frame % sequence.len()]
+    }
+
+    /// Writes the full size mip level of every texture as `<name>.png` into
+    /// `dir`. `*` isn't allowed in file names everywhere, so it is written as
+    /// `#`.
+    pub fn export_png<P: AsRef<Path>>(&self, dir: P, palette: &Palette) -> io::Result<()> {
+        let dir = dir.as_ref();
+        for texture in self.textures.iter().filter_map(|t| t.as_ref()) {
+            let file_name = format!("{}.png", texture.name.replace('*', "#"));
+            let mut file = io::BufWriter::new(try!(File::create(dir.join(file_name))));
+            try!(texture.write_png(&mut file, 0, palette));
+        }
+        Ok(())
+    }
+}
+
+/// Converts the frames found for a sequence into a list of indices, which
+/// must be contiguous from frame 0.
+fn sequence_indices(frames: &[Option<usize>], base: &str) -> BspResult<Vec<usize>> {
+    let count = frames.iter().rposition(|f| f.is_some()).map_or(0, |last| last + 1);
+    frames[..count]
+        .iter()
+        .map(|f| f.ok_or_else(|| BspError::MissingAnimationFrame(base.into())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use drawing::Palette;
+    use files::bsp::{Bsp, BspError};
+    use files::testing::{floor_bsp, temp_path};
+    use super::{TextureKind, TextureSet};
+
+    fn bsp_with_textures(names: &[&str]) -> Bsp {
+        let mut builder = floor_bsp();
+        for (i, name) in names.iter().enumerate() {
+            builder.texture(name, 32, 16, i as u8 + 1);
+        }
+        Bsp::from_bytes(&builder.build()).unwrap()
+    }
+
+    #[test]
+    fn kinds() {
+        assert_eq!(TextureKind::from_name("sky4"), TextureKind::Sky);
+        assert_eq!(TextureKind::from_name("*water0"), TextureKind::Turbulent);
+        assert_eq!(TextureKind::from_name("CLIP"), TextureKind::Clip);
+        assert_eq!(TextureKind::from_name("trigger"), TextureKind::Trigger);
+        assert_eq!(TextureKind::from_name("+0button"), TextureKind::Normal);
+        assert!(TextureKind::Turbulent.is_special());
+        assert!(TextureKind::Clip.is_invisible());
+    }
+
+    #[test]
+    fn mip_levels() {
+        let textures = TextureSet::new(&bsp_with_textures(&["*water0"])).unwrap();
+        let water = textures.get(1).unwrap();
+        assert_eq!(water.kind, TextureKind::Turbulent);
+        let sizes: Vec<_> = water.mips.iter().map(|m| (m.width(), m.height())).collect();
+        assert_eq!(sizes, vec![(32, 16), (16, 8), (8, 4), (4, 2)]);
+        assert_eq!(water.mip(9).get(0, 0), 1);
+    }
+
+    #[test]
+    fn animation_chains() {
+        let bsp = bsp_with_textures(&["+1button", "+0button", "+Abutton", "+2button", "+0lava"]);
+        let textures = TextureSet::new(&bsp).unwrap();
+        assert_eq!(textures.get(1).unwrap().frames, vec![2, 1, 4]);
+        assert_eq!(textures.get(4).unwrap().alternate_frames, vec![3]);
+        assert_eq!(textures.get(5).unwrap().frames, vec![5]);
+        assert!(textures.get(0).unwrap().frames.is_empty());
+
+        assert_eq!(textures.animation_frame(1, 0.0, false), 2);
+        assert_eq!(textures.animation_frame(1, 0.2, false), 1);
+        assert_eq!(textures.animation_frame(1, 0.5, false), 4);
+        assert_eq!(textures.animation_frame(1, 0.6, false), 2);
+        assert_eq!(textures.animation_frame(1, 0.6, true), 3);
+        assert_eq!(textures.animation_frame(5, 0.6, true), 5);
+        assert_eq!(textures.animation_frame(0, 1.0, true), 0);
+    }
+
+    #[test]
+    fn missing_frame() {
+        match TextureSet::new(&bsp_with_textures(&["+0slime", "+2slime"])) {
+            Err(BspError::MissingAnimationFrame(ref name)) if name == "slime" => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn export() {
+        let dir = temp_path("texture-export");
+        fs::create_dir_all(&dir).unwrap();
+        let textures = TextureSet::new(&bsp_with_textures(&["*lava1"])).unwrap();
+        textures.export_png(&dir, &Palette::from_bytes(&[0; 768])).unwrap();
+        let bytes = fs::read(dir.join("#lava1.png")).unwrap();
+        assert_eq!(&bytes[1..4], b"PNG");
+        assert!(dir.join("floor.png").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
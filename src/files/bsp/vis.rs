@@ -0,0 +1,211 @@
+//! Point location in the node tree and the potentially visible set (PVS).
+//!
+//! For every leaf, the visibility lump stores which other leafs can be seen
+//! from anywhere inside it, as a run-length encoded bit field: a zero byte is
+//! followed by the number of zero bytes it stands for, all other bytes are
+//! copied. Bit `i` refers to leaf `i + 1`, since leaf 0 is the shared solid
+//! leaf that is never visible.
+
+use files::bsp::{Bsp, NodeChild};
+use util::Vec3;
+
+/// A set of leafs, one bit per leaf.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeafSet {
+    bits: Vec<u8>,
+    num_leafs: usize,
+}
+
+impl LeafSet {
+    /// Creates a set of the leafs `1..=num_leafs`, with all of them visible
+    /// or none.
+    pub fn new(num_leafs: usize, visible: bool) -> LeafSet {
+        LeafSet {
+            bits: vec![if visible { 0xFF } else { 0 }; (num_leafs + 7) / 8],
+            num_leafs: num_leafs,
+        }
+    }
+
+    /// Decompresses the row of the visibility data starting at `offset`.
+    /// Truncated data is treated as invisible leafs.
+    pub fn decompress(data: &[u8], offset: usize, num_leafs: usize) -> LeafSet {
+        let mut set = LeafSet::new(num_leafs, false);
+        let row = set.bits.len();
+        let mut input = data.iter().skip(offset);
+        let mut out = 0;
+        while out < row {
+            match input.next() {
+                Some(&0) => {
+                    let count = input.next().map_or(0, |&c| c as usize);
+                    if count == 0 {
+                        break;
+                    }
+                    out += count;
+                }
+                Some(&b) => {
+                    set.bits[out] = b;
+                    out += 1;
+                }
+                None => break,
+            }
+        }
+        set
+    }
+
+    /// Whether `leaf` is in the set. Leaf 0 (solid) never is.
+    pub fn contains(&self, leaf: usize) -> bool {
+        if leaf == 0 || leaf > self.num_leafs {
+            return false;
+        }
+        self.bits[(leaf - 1) >> 3] & (1 << ((leaf - 1) & 7)) != 0
+    }
+
+    /// The leafs in the set, in ascending order.
+    pub fn leafs(&self) -> Vec<usize> {
+        (1..self.num_leafs + 1).filter(|&l| self.contains(l)).collect()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+}
+
+/// The decompressed PVS of every leaf of the world model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pvs {
+    sets: Vec<LeafSet>,
+    num_leafs: usize,
+}
+
+impl Pvs {
+    pub fn new(bsp: &Bsp) -> Pvs {
+        let num_leafs = bsp.world().visleafs;
+        let sets = bsp.leafs
+                      .iter()
+                      .enumerate()
+                      .map(|(i, leaf)| match leaf.vis_offset {
+                          Some(offset) if i != 0 => {
+                              LeafSet::decompress(&bsp.visibility, offset, num_leafs)
+                          }
+                          // Maps compiled without vis see everything
+                          _ => LeafSet::new(num_leafs, true),
+                      })
+                      .collect();
+        Pvs {
+            sets: sets,
+            num_leafs: num_leafs,
+        }
+    }
+
+    /// The leafs visible from `leaf`. Out of range leafs see everything, like
+    /// leaf 0 does.
+    pub fn visible_from(&self, leaf: usize) -> &LeafSet {
+        self.sets.get(leaf).unwrap_or(&self.sets[0])
+    }
+
+    pub fn leaf_is_visible(&self, from: usize, to: usize) -> bool {
+        self.visible_from(from).contains(to)
+    }
+
+    pub fn num_leafs(&self) -> usize {
+        self.num_leafs
+    }
+}
+
+impl Bsp {
+    /// Returns the index of the world leaf that contains `point`. Points on
+    /// a plane belong to its back side.
+    pub fn point_in_leaf(&self, point: Vec3) -> usize {
+        let mut node = self.world().headnodes[0] as usize;
+        loop {
+            let n = &self.nodes[node];
+            let plane = &self.planes[n.plane];
+            let side = if point.dot(plane.normal) - plane.dist > 0.0 {
+                0
+            } else {
+                1
+            };
+            match n.children[side] {
+                NodeChild::Node(child) => node = child,
+                NodeChild::Leaf(leaf) => return leaf,
+            }
+        }
+    }
+
+    /// Decompresses the PVS of a single leaf.
+    pub fn leaf_pvs(&self, leaf: usize) -> LeafSet {
+        let num_leafs = self.world().visleafs;
+        match self.leafs.get(leaf).and_then(|l| l.vis_offset) {
+            Some(offset) if leaf != 0 => LeafSet::decompress(&self.visibility, offset, num_leafs),
+            _ => LeafSet::new(num_leafs, true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use files::bsp::Bsp;
+    use files::testing::{BspBuilder, floor_bsp};
+    use util::Vec3;
+    use super::{LeafSet, Pvs};
+
+    /// Splits space into three leafs: x <= 0 (3), x > 0 and y > 0 (1) and
+    /// x > 0 and y <= 0 (2). Leaf 1 sees 2, leaf 2 sees 1 and 3, leaf 3 sees
+    /// nothing.
+    fn quadrant_bsp() -> Bsp {
+        let mut b = BspBuilder::new();
+        b.plane([1.0, 0.0, 0.0], 0.0, 0)
+         .plane([0.0, 1.0, 0.0], 0.0, 1)
+         .visibility(&[0b011, 0b111, 0, 1])
+         .node(0, [1, -4], [-64, -64, -64], [64, 64, 64], (0, 0))
+         .node(1, [-2, -3], [0, -64, -64], [64, 64, 64], (0, 0))
+         .leaf(-2, -1, [0; 3], [0; 3], (0, 0))
+         .leaf(-1, 0, [0, 0, -64], [64, 64, 64], (0, 0))
+         .leaf(-1, 1, [0, -64, -64], [64, 0, 64], (0, 0))
+         .leaf(-1, 2, [-64, -64, -64], [0, 64, 64], (0, 0))
+         .model([-64.0; 3], [64.0; 3], [0, -1, -1, -1], 3, (0, 0));
+        Bsp::from_bytes(&b.build()).unwrap()
+    }
+
+    #[test]
+    fn decompress() {
+        let set = LeafSet::decompress(&[0xFF, 0x01, 0x00, 0x02, 0x80], 1, 32);
+        assert_eq!(set.as_bytes(), &[0x01, 0, 0, 0x80]);
+        assert_eq!(set.leafs(), vec![1, 32]);
+        assert!(!set.contains(0));
+        assert!(!set.contains(33));
+
+        // Truncated data and runs past the end of the row
+        let set = LeafSet::decompress(&[0x03, 0x00, 0xFF], 0, 16);
+        assert_eq!(set.as_bytes(), &[0x03, 0]);
+        assert_eq!(LeafSet::decompress(&[0x07], 0, 16).leafs(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn point_in_leaf() {
+        let bsp = quadrant_bsp();
+        assert_eq!(bsp.point_in_leaf(Vec3::new(10.0, 10.0, 0.0)), 1);
+        assert_eq!(bsp.point_in_leaf(Vec3::new(10.0, -10.0, 0.0)), 2);
+        assert_eq!(bsp.point_in_leaf(Vec3::new(10.0, 0.0, 0.0)), 2);
+        assert_eq!(bsp.point_in_leaf(Vec3::new(-10.0, 10.0, 0.0)), 3);
+
+        let floor = Bsp::from_bytes(&floor_bsp().build()).unwrap();
+        assert_eq!(floor.point_in_leaf(Vec3::new(0.0, 0.0, 24.0)), 1);
+        assert_eq!(floor.point_in_leaf(Vec3::new(0.0, 0.0, -1.0)), 0);
+    }
+
+    #[test]
+    fn leaf_visibility() {
+        let bsp = quadrant_bsp();
+        let pvs = Pvs::new(&bsp);
+        assert_eq!(pvs.num_leafs(), 3);
+        assert!(pvs.leaf_is_visible(1, 2));
+        assert!(!pvs.leaf_is_visible(1, 3));
+        assert!(pvs.leaf_is_visible(2, 3));
+        assert_eq!(pvs.visible_from(3).leafs(), Vec::<usize>::new());
+        // The solid leaf sees everything but is never seen
+        assert!(pvs.leaf_is_visible(0, 3));
+        assert!(!pvs.leaf_is_visible(2, 0));
+        assert_eq!(bsp.leaf_pvs(2), *pvs.visible_from(2));
+    }
+}
@@ -0,0 +1,706 @@
+//! Loader for the alias models of `progs/*.mdl`: monsters, items, weapons
+//! and the player.
+//!
+//! A model is a triangle mesh with one or more 8 bit skins and a number of
+//! vertex animation frames. Skins and frames can be grouped, in which case
+//! the group is animated by itself with the given intervals. Frame vertices
+//! are stored compressed as one byte per axis, relative to the model's scale
+//! and origin.
+
+use std::io;
+use std::io::prelude::*;
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use drawing::Texture;
+use files::{PackContainer, PackError};
+use util::Vec3;
+use hprof;
+
+/// "IDPO" as a little endian integer.
+pub const MDL_IDENT: i32 = 0x4f504449;
+pub const MDL_VERSION: i32 = 6;
+
+/// Limits of the original engine.
+pub const MAX_SKINS: usize = 32;
+pub const MAX_VERTICES: usize = 2000;
+pub const MAX_TRIANGLES: usize = 4096;
+pub const MAX_FRAMES: usize = 256;
+/// Number of entries in the table of precomputed vertex normals.
+pub const NUM_VERTEX_NORMALS: usize = 162;
+
+/// Model flags, mostly trails left behind by moving models.
+pub const EF_ROCKET: i32 = 1;
+pub const EF_GRENADE: i32 = 2;
+pub const EF_GIB: i32 = 4;
+/// Items that spin around their vertical axis.
+pub const EF_ROTATE: i32 = 8;
+pub const EF_TRACER: i32 = 16;
+pub const EF_ZOMGIB: i32 = 32;
+pub const EF_TRACER2: i32 = 64;
+pub const EF_TRACER3: i32 = 128;
+
+/// Size of the header in bytes.
+const HEADER_SIZE: usize = 84;
+
+#[derive(Debug)]
+pub enum MdlError {
+    IoError(io::Error),
+    PackError(PackError),
+    /// The file doesn't start with "IDPO".
+    BadIdent(i32),
+    UnsupportedVersion(i32),
+    /// A count or size in the header is out of range.
+    InvalidHeader(&'static str),
+    /// An element references a nonexistent element.
+    InvalidIndex(&'static str, usize),
+    /// The intervals of a skin or frame group don't increase.
+    InvalidInterval,
+}
+
+impl From<io::Error> for MdlError {
+    fn from(err: io::Error) -> MdlError {
+        MdlError::IoError(err)
+    }
+}
+
+impl From<PackError> for MdlError {
+    fn from(err: PackError) -> MdlError {
+        MdlError::PackError(err)
+    }
+}
+
+pub type MdlResult<T> = Result<T, MdlError>;
+
+/// How the animation of a model's instances is synchronized.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SyncType {
+    /// All instances animate in step.
+    Sync,
+    /// Every instance starts at a random point of its group animations.
+    Random,
+}
+
+/// One or more skin images of `skin_width * skin_height` palette indices.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Skin {
+    Single(Texture),
+    /// Skins cycled through by time. `intervals` are the times at which
+    /// each skin ends, relative to the start of the group.
+    Group {
+        intervals: Vec<f32>,
+        images: Vec<Texture>,
+    },
+}
+
+/// Texture coordinates of a vertex, in skin texels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StVert {
+    /// Whether the vertex lies on the seam between the front and back half
+    /// of the skin. Back facing triangles use it shifted by half the skin
+    /// width.
+    pub on_seam: bool,
+    pub s: i32,
+    pub t: i32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Triangle {
+    /// Whether the triangle faces the front half of the skin, so its seam
+    /// vertices are used unshifted.
+    pub faces_front: bool,
+    pub vertices: [usize; 3],
+}
+
+/// A decompressed frame vertex.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FrameVertex {
+    pub position: Vec3,
+    /// Index into the table of vertex normals.
+    pub normal: usize,
+}
+
+/// The vertex positions of one animation frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimpleFrame {
+    pub name: String,
+    pub mins: Vec3,
+    pub maxs: Vec3,
+    pub vertices: Vec<FrameVertex>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Single(SimpleFrame),
+    /// Frames cycled through by time, like skin groups.
+    Group {
+        intervals: Vec<f32>,
+        frames: Vec<SimpleFrame>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mdl {
+    /// Frame vertices are `compressed * scale + origin`.
+    pub scale: Vec3,
+    pub origin: Vec3,
+    pub radius: f32,
+    pub eye_position: Vec3,
+    pub skin_width: usize,
+    pub skin_height: usize,
+    pub skins: Vec<Skin>,
+    pub st_verts: Vec<StVert>,
+    pub triangles: Vec<Triangle>,
+    pub frames: Vec<Frame>,
+    pub sync_type: SyncType,
+    /// A combination of the `EF_*` flags.
+    pub flags: i32,
+    /// Average size of the triangles, used to pick a level of detail.
+    pub size: f32,
+}
+
+impl Mdl {
+    /// Reads and parses a model from the PAK files, e.g.
+    /// `progs/player.mdl`.
+    pub fn load(paks: &mut PackContainer, name: &str) -> MdlResult<Mdl> {
+        let bytes = try!(paks.read(name));
+        Mdl::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> MdlResult<Mdl> {
+        let _guard = hprof::enter("Mdl::from_bytes");
+        if data.len() < HEADER_SIZE {
+            return Err(MdlError::InvalidHeader("size"));
+        }
+        let mut rdr = io::Cursor::new(data);
+        let ident = try!(rdr.read_i32::<LittleEndian>());
+        if ident != MDL_IDENT {
+            return Err(MdlError::BadIdent(ident));
+        }
+        let version = try!(rdr.read_i32::<LittleEndian>());
+        if version != MDL_VERSION {
+            return Err(MdlError::UnsupportedVersion(version));
+        }
+
+        let scale = try!(read_vec3(&mut rdr));
+        let origin = try!(read_vec3(&mut rdr));
+        let radius = try!(rdr.read_f32::<LittleEndian>());
+        let eye_position = try!(read_vec3(&mut rdr));
+        let invalid = MdlError::InvalidHeader;
+        let num_skins = try!(read_count(&mut rdr, 1, MAX_SKINS, "skins", invalid));
+        let skin_width = try!(read_count(&mut rdr, 1, data.len(), "skin width", invalid));
+        let skin_height = try!(read_count(&mut rdr, 1, data.len(), "skin height", invalid));
+        let num_verts = try!(read_count(&mut rdr, 1, MAX_VERTICES, "vertices", invalid));
+        let num_tris = try!(read_count(&mut rdr, 1, MAX_TRIANGLES, "triangles", invalid));
+        let num_frames = try!(read_count(&mut rdr, 1, MAX_FRAMES, "frames", invalid));
+        let sync_type = match try!(rdr.read_i32::<LittleEndian>()) {
+            0 => SyncType::Sync,
+            1 => SyncType::Random,
+            _ => return Err(MdlError::InvalidHeader("sync type")),
+        };
+        let flags = try!(rdr.read_i32::<LittleEndian>());
+        let size = try!(rdr.read_f32::<LittleEndian>());
+        if skin_width % 4 != 0 {
+            return Err(MdlError::InvalidHeader("skin width"));
+        }
+
+        let mut skins = Vec::with_capacity(num_skins);
+        for _ in 0..num_skins {
+            skins.push(try!(read_skin(&mut rdr, skin_width, skin_height)));
+        }
+
+        let mut st_verts = Vec::with_capacity(num_verts);
+        for _ in 0..num_verts {
+            let on_seam = try!(rdr.read_i32::<LittleEndian>()) != 0;
+            let s = try!(rdr.read_i32::<LittleEndian>());
+            let t = try!(rdr.read_i32::<LittleEndian>());
+            st_verts.push(StVert {
+                on_seam: on_seam,
+                s: s,
+                t: t,
+            });
+        }
+
+        let mut triangles = Vec::with_capacity(num_tris);
+        for i in 0..num_tris {
+            let faces_front = try!(rdr.read_i32::<LittleEndian>()) != 0;
+            let mut vertices = [0; 3];
+            for v in vertices.iter_mut() {
+                let index = try!(rdr.read_i32::<LittleEndian>());
+                if index < 0 || index as usize >= num_verts {
+                    return Err(MdlError::InvalidIndex("triangles", i));
+                }
+                *v = index as usize;
+            }
+            triangles.push(Triangle {
+                faces_front: faces_front,
+                vertices: vertices,
+            });
+        }
+
+        let mut frames = Vec::with_capacity(num_frames);
+        for _ in 0..num_frames {
+            frames.push(try!(read_frame(&mut rdr, num_verts, scale, origin)));
+        }
+
+        Ok(Mdl {
+            scale: scale,
+            origin: origin,
+            radius: radius,
+            eye_position: eye_position,
+            skin_width: skin_width,
+            skin_height: skin_height,
+            skins: skins,
+            st_verts: st_verts,
+            triangles: triangles,
+            frames: frames,
+            sync_type: sync_type,
+            flags: flags,
+            size: size,
+        })
+    }
+
+    /// The image of skin `skin` at `time` seconds, like `R_AliasSetupSkin`.
+    /// Skin numbers out of range use skin 0.
+    pub fn skin(&self, skin: usize, time: f32) -> &Texture {
+        match self.skins.get(skin).unwrap_or(&self.skins[0]) {
+            Skin::Single(image) => image,
+            Skin::Group { intervals, images } => &images[group_index(intervals, time)],
+        }
+    }
+
+    /// Frame `frame` at `time` seconds, like `R_AliasSetupFrame`. Frame
+    /// numbers out of range use frame 0.
+    pub fn frame(&self, frame: usize, time: f32) -> &SimpleFrame {
+        match self.frames.get(frame).unwrap_or(&self.frames[0]) {
+            Frame::Single(frame) => frame,
+            Frame::Group { intervals, frames } => &frames[group_index(intervals, time)],
+        }
+    }
+}
+
+/// Index of the group member shown at `time`, for groups whose members end
+/// at the given `intervals`. The animation loops after the last interval.
+pub fn group_index(intervals: &[f32], time: f32) -> usize {
+    let total = intervals[intervals.len() - 1];
+    let t = time.max(0.0) % total;
+    intervals.iter().position(|&end| end > t).unwrap_or(intervals.len() - 1)
+}
+
+fn read_vec3<R: Read>(rdr: &mut R) -> io::Result<Vec3> {
+    let x = try!(rdr.read_f32::<LittleEndian>());
+    let y = try!(rdr.read_f32::<LittleEndian>());
+    let z = try!(rdr.read_f32::<LittleEndian>());
+    Ok(Vec3::new(x, y, z))
+}
+
+/// Reads a count and checks it against `min..=max`, failing with
+/// `invalid(what)` otherwise. Shared with the sprite loader, which has its
+/// own error type.
+pub fn read_count<R, E>(rdr: &mut R,
+                        min: usize,
+                        max: usize,
+                        what: &'static str,
+                        invalid: fn(&'static str) -> E)
+                        -> Result<usize, E>
+    where R: Read,
+          E: From<io::Error>
+{
+    let count = try!(rdr.read_i32::<LittleEndian>());
+    if count < min as i32 || count as usize > max {
+        Err(invalid(what))
+    } else {
+        Ok(count as usize)
+    }
+}
+
+/// Reads `len` bytes, failing before allocating if the file is shorter.
+fn read_bytes(rdr: &mut io::Cursor<&[u8]>, len: usize) -> io::Result<Vec<u8>> {
+    let remaining = rdr.get_ref().len() - rdr.position() as usize;
+    if len > remaining {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated model"));
+    }
+    let mut bytes = vec![0; len];
+    try!(rdr.read_exact(&mut bytes));
+    Ok(bytes)
+}
+
+/// Reads the `count` intervals of a skin, frame or sprite group, failing
+/// with `invalid` if they don't increase.
+pub fn read_intervals<R, E>(rdr: &mut R, count: usize, invalid: E) -> Result<Vec<f32>, E>
+    where R: Read,
+          E: From<io::Error>
+{
+    let mut intervals = Vec::with_capacity(count);
+    for _ in 0..count {
+        let interval = try!(rdr.read_f32::<LittleEndian>());
+        // Intervals must increase, or the group couldn't be animated
+        let previous = intervals.last().cloned().unwrap_or(0.0);
+        if interval.is_nan() || interval <= previous {
+            return Err(invalid);
+        }
+        intervals.push(interval);
+    }
+    Ok(intervals)
+}
+
+fn read_skin(rdr: &mut io::Cursor<&[u8]>, width: usize, height: usize) -> MdlResult<Skin> {
+    let image = |rdr: &mut io::Cursor<&[u8]>| {
+        read_bytes(rdr, width * height).map(|pixels| Texture::new(width, height, pixels))
+    };
+    match try!(rdr.read_i32::<LittleEndian>()) {
+        0 => Ok(Skin::Single(try!(image(rdr)))),
+        1 => {
+            let count = try!(read_count(rdr, 1, MAX_SKINS, "group size", MdlError::InvalidHeader));
+            let intervals = try!(read_intervals(rdr, count, MdlError::InvalidInterval));
+            let mut images = Vec::with_capacity(intervals.len());
+            for _ in 0..intervals.len() {
+                images.push(try!(image(rdr)));
+            }
+            Ok(Skin::Group {
+                intervals: intervals,
+                images: images,
+            })
+        }
+        _ => Err(MdlError::InvalidHeader("skin type")),
+    }
+}
+
+/// Reads a compressed vertex: a byte per axis and a normal index.
+fn read_trivertx(rdr: &mut io::Cursor<&[u8]>,
+                 scale: Vec3,
+                 origin: Vec3)
+                 -> MdlResult<FrameVertex> {
+    let mut v = [0; 4];
+    try!(rdr.read_exact(&mut v));
+    if v[3] as usize >= NUM_VERTEX_NORMALS {
+        return Err(MdlError::InvalidIndex("normals", v[3] as usize));
+    }
+    Ok(FrameVertex {
+        position: Vec3::new(v[0] as f32 * scale.x + origin.x,
+                            v[1] as f32 * scale.y + origin.y,
+                            v[2] as f32 * scale.z + origin.z),
+        normal: v[3] as usize,
+    })
+}
+
+/// Reads a frame without its type: bounds, name and vertices.
+fn read_simple_frame(rdr: &mut io::Cursor<&[u8]>,
+                     num_verts: usize,
+                     scale: Vec3,
+                     origin: Vec3)
+                     -> MdlResult<SimpleFrame> {
+    let mins = try!(read_trivertx(rdr, scale, origin)).position;
+    let maxs = try!(read_trivertx(rdr, scale, origin)).position;
+    let name = try!(read_bytes(rdr, 16));
+    let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    let mut vertices = Vec::with_capacity(num_verts);
+    for _ in 0..num_verts {
+        vertices.push(try!(read_trivertx(rdr, scale, origin)));
+    }
+    Ok(SimpleFrame {
+        name: String::from_utf8_lossy(&name[..end]).into_owned(),
+        mins: mins,
+        maxs: maxs,
+        vertices: vertices,
+    })
+}
+
+fn read_frame(rdr: &mut io::Cursor<&[u8]>,
+              num_verts: usize,
+              scale: Vec3,
+              origin: Vec3)
+              -> MdlResult<Frame> {
+    match try!(rdr.read_i32::<LittleEndian>()) {
+        0 => Ok(Frame::Single(try!(read_simple_frame(rdr, num_verts, scale, origin)))),
+        1 => {
+            let count = try!(read_count(rdr, 1, MAX_FRAMES, "group size", MdlError::InvalidHeader));
+            // The bounds of the whole group aren't needed
+            try!(read_bytes(rdr, 8));
+            let intervals = try!(read_intervals(rdr, count, MdlError::InvalidInterval));
+            let mut frames = Vec::with_capacity(count);
+            for _ in 0..count {
+                frames.push(try!(read_simple_frame(rdr, num_verts, scale, origin)));
+            }
+            Ok(Frame::Group {
+                intervals: intervals,
+                frames: frames,
+            })
+        }
+        _ => Err(MdlError::InvalidHeader("frame type")),
+    }
+}
+
+/// The vertex normals frame vertices refer to, from `anorms.h`.
+pub const VERTEX_NORMALS: [[f32; 3]; NUM_VERTEX_NORMALS] =
+    [[-0.525731, 0.000000, 0.850651],
+     [-0.442863, 0.238856, 0.864188],
+     [-0.295242, 0.000000, 0.955423],
+     [-0.309017, 0.500000, 0.809017],
+     [-0.162460, 0.262866, 0.951056],
+     [0.000000, 0.000000, 1.000000],
+     [0.000000, 0.850651, 0.525731],
+     [-0.147621, 0.716567, 0.681718],
+     [0.147621, 0.716567, 0.681718],
+     [0.000000, 0.525731, 0.850651],
+     [0.309017, 0.500000, 0.809017],
+     [0.525731, 0.000000, 0.850651],
+     [0.295242, 0.000000, 0.955423],
+     [0.442863, 0.238856, 0.864188],
+     [0.162460, 0.262866, 0.951056],
+     [-0.681718, 0.147621, 0.716567],
+     [-0.809017, 0.309017, 0.500000],
+     [-0.587785, 0.425325, 0.688191],
+     [-0.850651, 0.525731, 0.000000],
+     [-0.864188, 0.442863, 0.238856],
+     [-0.716567, 0.681718, 0.147621],
+     [-0.688191, 0.587785, 0.425325],
+     [-0.500000, 0.809017, 0.309017],
+     [-0.238856, 0.864188, 0.442863],
+     [-0.425325, 0.688191, 0.587785],
+     [-0.716567, 0.681718, -0.147621],
+     [-0.500000, 0.809017, -0.309017],
+     [-0.525731, 0.850651, 0.000000],
+     [0.000000, 0.850651, -0.525731],
+     [-0.238856, 0.864188, -0.442863],
+     [0.000000, 0.955423, -0.295242],
+     [-0.262866, 0.951056, -0.162460],
+     [0.000000, 1.000000, 0.000000],
+     [0.000000, 0.955423, 0.295242],
+     [-0.262866, 0.951056, 0.162460],
+     [0.238856, 0.864188, 0.442863],
+     [0.262866, 0.951056, 0.162460],
+     [0.500000, 0.809017, 0.309017],
+     [0.238856, 0.864188, -0.442863],
+     [0.262866, 0.951056, -0.162460],
+     [0.500000, 0.809017, -0.309017],
+     [0.850651, 0.525731, 0.000000],
+     [0.716567, 0.681718, 0.147621],
+     [0.716567, 0.681718, -0.147621],
+     [0.525731, 0.850651, 0.000000],
+     [0.425325, 0.688191, 0.587785],
+     [0.864188, 0.442863, 0.238856],
+     [0.688191, 0.587785, 0.425325],
+     [0.809017, 0.309017, 0.500000],
+     [0.681718, 0.147621, 0.716567],
+     [0.587785, 0.425325, 0.688191],
+     [0.955423, 0.295242, 0.000000],
+     [1.000000, 0.000000, 0.000000],
+     [0.951056, 0.162460, 0.262866],
+     [0.850651, -0.525731, 0.000000],
+     [0.955423, -0.295242, 0.000000],
+     [0.864188, -0.442863, 0.238856],
+     [0.951056, -0.162460, 0.262866],
+     [0.809017, -0.309017, 0.500000],
+     [0.681718, -0.147621, 0.716567],
+     [0.850651, 0.000000, 0.525731],
+     [0.864188, 0.442863, -0.238856],
+     [0.809017, 0.309017, -0.500000],
+     [0.951056, 0.162460, -0.262866],
+     [0.525731, 0.000000, -0.850651],
+     [0.681718, 0.147621, -0.716567],
+     [0.681718, -0.147621, -0.716567],
+     [0.850651, 0.000000, -0.525731],
+     [0.809017, -0.309017, -0.500000],
+     [0.864188, -0.442863, -0.238856],
+     [0.951056, -0.162460, -0.262866],
+     [0.147621, 0.716567, -0.681718],
+     [0.309017, 0.500000, -0.809017],
+     [0.425325, 0.688191, -0.587785],
+     [0.442863, 0.238856, -0.864188],
+     [0.587785, 0.425325, -0.688191],
+     [0.688191, 0.587785, -0.425325],
+     [-0.147621, 0.716567, -0.681718],
+     [-0.309017, 0.500000, -0.809017],
+     [0.000000, 0.525731, -0.850651],
+     [-0.525731, 0.000000, -0.850651],
+     [-0.442863, 0.238856, -0.864188],
+     [-0.295242, 0.000000, -0.955423],
+     [-0.162460, 0.262866, -0.951056],
+     [0.000000, 0.000000, -1.000000],
+     [0.295242, 0.000000, -0.955423],
+     [0.162460, 0.262866, -0.951056],
+     [-0.442863, -0.238856, -0.864188],
+     [-0.309017, -0.500000, -0.809017],
+     [-0.162460, -0.262866, -0.951056],
+     [0.000000, -0.850651, -0.525731],
+     [-0.147621, -0.716567, -0.681718],
+     [0.147621, -0.716567, -0.681718],
+     [0.000000, -0.525731, -0.850651],
+     [0.309017, -0.500000, -0.809017],
+     [0.442863, -0.238856, -0.864188],
+     [0.162460, -0.262866, -0.951056],
+     [0.238856, -0.864188, -0.442863],
+     [0.500000, -0.809017, -0.309017],
+     [0.425325, -0.688191, -0.587785],
+     [0.716567, -0.681718, -0.147621],
+     [0.688191, -0.587785, -0.425325],
+     [0.587785, -0.425325, -0.688191],
+     [0.000000, -0.955423, -0.295242],
+     [0.000000, -1.000000, 0.000000],
+     [0.262866, -0.951056, -0.162460],
+     [0.000000, -0.850651, 0.525731],
+     [0.000000, -0.955423, 0.295242],
+     [0.238856, -0.864188, 0.442863],
+     [0.262866, -0.951056, 0.162460],
+     [0.500000, -0.809017, 0.309017],
+     [0.716567, -0.681718, 0.147621],
+     [0.525731, -0.850651, 0.000000],
+     [-0.238856, -0.864188, -0.442863],
+     [-0.500000, -0.809017, -0.309017],
+     [-0.262866, -0.951056, -0.162460],
+     [-0.850651, -0.525731, 0.000000],
+     [-0.716567, -0.681718, -0.147621],
+     [-0.716567, -0.681718, 0.147621],
+     [-0.525731, -0.850651, 0.000000],
+     [-0.500000, -0.809017, 0.309017],
+     [-0.238856, -0.864188, 0.442863],
+     [-0.262866, -0.951056, 0.162460],
+     [-0.864188, -0.442863, 0.238856],
+     [-0.809017, -0.309017, 0.500000],
+     [-0.688191, -0.587785, 0.425325],
+     [-0.681718, -0.147621, 0.716567],
+     [-0.442863, -0.238856, 0.864188],
+     [-0.587785, -0.425325, 0.688191],
+     [-0.309017, -0.500000, 0.809017],
+     [-0.147621, -0.716567, 0.681718],
+     [-0.425325, -0.688191, 0.587785],
+     [-0.162460, -0.262866, 0.951056],
+     [0.442863, -0.238856, 0.864188],
+     [0.162460, -0.262866, 0.951056],
+     [0.309017, -0.500000, 0.809017],
+     [0.147621, -0.716567, 0.681718],
+     [0.000000, -0.525731, 0.850651],
+     [0.425325, -0.688191, 0.587785],
+     [0.587785, -0.425325, 0.688191],
+     [0.688191, -0.587785, 0.425325],
+     [-0.955423, 0.295242, 0.000000],
+     [-0.951056, 0.162460, 0.262866],
+     [-1.000000, 0.000000, 0.000000],
+     [-0.850651, 0.000000, 0.525731],
+     [-0.955423, -0.295242, 0.000000],
+     [-0.951056, -0.162460, 0.262866],
+     [-0.864188, 0.442863, -0.238856],
+     [-0.951056, 0.162460, -0.262866],
+     [-0.809017, 0.309017, -0.500000],
+     [-0.864188, -0.442863, -0.238856],
+     [-0.951056, -0.162460, -0.262866],
+     [-0.809017, -0.309017, -0.500000],
+     [-0.681718, 0.147621, -0.716567],
+     [-0.681718, -0.147621, -0.716567],
+     [-0.850651, 0.000000, -0.525731],
+     [-0.688191, 0.587785, -0.425325],
+     [-0.587785, 0.425325, -0.688191],
+     [-0.425325, 0.688191, -0.587785],
+     [-0.425325, -0.688191, -0.587785],
+     [-0.587785, -0.425325, -0.688191],
+     [-0.688191, -0.587785, -0.425325]];
+
+#[cfg(test)]
+mod tests {
+    use files::testing::MdlBuilder;
+    use util::Vec3;
+    use super::*;
+
+    /// A single triangle with two frames, one of them a group.
+    fn triangle() -> MdlBuilder {
+        let mut b = MdlBuilder::new([0.5, 1.0, 2.0], [-10.0, 0.0, 5.0], 8, 4);
+        b.flags(EF_ROTATE)
+         .skin(|x, y| (x + y * 8) as u8)
+         .skin_group(&[0.1, 0.3], &[20, 30])
+         .st_vert(false, 0, 0)
+         .st_vert(true, 7, 0)
+         .st_vert(false, 0, 3)
+         .triangle(true, [0, 1, 2])
+         .frame("stand1", &[[0, 0, 0, 0], [20, 0, 0, 1], [0, 10, 3, 161]])
+         .frame_group(&[0.5, 1.0],
+                      &[("run1", &[[1, 1, 1, 2]; 3]), ("run2", &[[2, 2, 2, 3]; 3])]);
+        b
+    }
+
+    #[test]
+    fn parses_models() {
+        let mdl = Mdl::from_bytes(&triangle().build()).unwrap();
+        assert_eq!((mdl.skin_width, mdl.skin_height), (8, 4));
+        assert_eq!(mdl.flags, EF_ROTATE);
+        assert_eq!(mdl.sync_type, SyncType::Sync);
+        assert_eq!(mdl.skins.len(), 2);
+        assert_eq!(mdl.skin(0, 0.0).get(1, 1), 9);
+        assert_eq!(mdl.st_verts[1],
+                   StVert {
+                       on_seam: true,
+                       s: 7,
+                       t: 0,
+                   });
+        assert_eq!(mdl.triangles[0].vertices, [0, 1, 2]);
+        assert!(mdl.triangles[0].faces_front);
+
+        let frame = mdl.frame(0, 0.0);
+        assert_eq!(frame.name, "stand1");
+        assert_eq!(frame.vertices[1].position, Vec3::new(0.0, 0.0, 5.0));
+        assert_eq!(frame.vertices[2].position, Vec3::new(-10.0, 10.0, 11.0));
+        assert_eq!(frame.vertices[2].normal, 161);
+        assert_eq!(frame.maxs, Vec3::new(117.5, 255.0, 515.0));
+    }
+
+    #[test]
+    fn groups_animate_by_time() {
+        let mdl = Mdl::from_bytes(&triangle().build()).unwrap();
+        assert_eq!(mdl.skin(1, 0.05).get(0, 0), 20);
+        assert_eq!(mdl.skin(1, 0.2).get(0, 0), 30);
+        assert_eq!(mdl.skin(1, 0.35).get(0, 0), 20);
+        assert_eq!(mdl.frame(1, 0.25).name, "run1");
+        assert_eq!(mdl.frame(1, 0.75).name, "run2");
+        assert_eq!(mdl.frame(1, 1.25).name, "run1");
+        // Out of range falls back to the first one
+        assert_eq!(mdl.frame(5, 0.0).name, "stand1");
+        assert_eq!(mdl.skin(5, 0.0).get(1, 1), 9);
+    }
+
+    #[test]
+    fn rejects_invalid_models() {
+        let bytes = triangle().build();
+        // Truncated anywhere
+        for len in 0..bytes.len() {
+            assert!(Mdl::from_bytes(&bytes[..len]).is_err());
+        }
+
+        let mut bad_ident = bytes.clone();
+        bad_ident[0] = b'X';
+        match Mdl::from_bytes(&bad_ident) {
+            Err(MdlError::BadIdent(_)) => {}
+            other => panic!("{:?}", other),
+        }
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 8;
+        match Mdl::from_bytes(&bad_version) {
+            Err(MdlError::UnsupportedVersion(8)) => {}
+            other => panic!("{:?}", other),
+        }
+        // A huge vertex count
+        let mut bad_count = bytes.clone();
+        bad_count[60..64].copy_from_slice(&[0xff, 0xff, 0xff, 0x7f]);
+        match Mdl::from_bytes(&bad_count) {
+            Err(MdlError::InvalidHeader("vertices")) => {}
+            other => panic!("{:?}", other),
+        }
+
+        let mut b = triangle();
+        b.triangle(false, [0, 1, 3]);
+        match Mdl::from_bytes(&b.build()) {
+            Err(MdlError::InvalidIndex("triangles", 1)) => {}
+            other => panic!("{:?}", other),
+        }
+        let mut b = triangle();
+        b.frame("bad", &[[0, 0, 0, 162]; 3]);
+        match Mdl::from_bytes(&b.build()) {
+            Err(MdlError::InvalidIndex("normals", 162)) => {}
+            other => panic!("{:?}", other),
+        }
+        let mut b = triangle();
+        b.skin_group(&[0.5, 0.5], &[1, 2]);
+        match Mdl::from_bytes(&b.build()) {
+            Err(MdlError::InvalidInterval) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -1,6 +1,14 @@
+pub mod bsp;
 pub mod filemanager;
 pub mod lmp;
+pub mod mdl;
+pub mod obj;
 pub mod packfile;
+pub mod png;
+pub mod spr;
+#[cfg(test)]
+pub mod testing;
+pub mod y4m;
 
 pub use self::filemanager::*;
 pub use self::lmp::LmpImage;
@@ -0,0 +1,436 @@
+//! Export of map and model geometry to Wavefront OBJ, to inspect it in
+//! common 3D tools. Every mesh is written as an object with texture
+//! coordinates, and its materials go into an MTL file next to it that
+//! references the textures as PNG images.
+//!
+//! Quake's z axis points up, while most tools expect y to point up, so
+//! positions are written as `(x, z, -y)`. Quake's polygons are wound
+//! clockwise when seen from the front, OBJ expects them counterclockwise,
+//! so the order of the vertices is reversed.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use drawing::{Palette, Texture};
+use files::{PackContainer, PackError};
+use files::bsp::{Bsp, BspError, BspResult, TextureSet};
+use files::mdl::{Mdl, MdlError, SimpleFrame};
+use files::png;
+use util::Vec3;
+
+/// Material of faces whose texture is missing from the map.
+pub const MISSING_MATERIAL: &'static str = "missing";
+
+#[derive(Debug)]
+pub enum ExportError {
+    IoError(io::Error),
+    PackError(PackError),
+    BspError(BspError),
+    MdlError(MdlError),
+    /// The file is neither a map (`.bsp`) nor an alias model (`.mdl`).
+    UnsupportedFile(String),
+}
+
+impl From<io::Error> for ExportError {
+    fn from(err: io::Error) -> ExportError {
+        ExportError::IoError(err)
+    }
+}
+
+impl From<PackError> for ExportError {
+    fn from(err: PackError) -> ExportError {
+        ExportError::PackError(err)
+    }
+}
+
+impl From<BspError> for ExportError {
+    fn from(err: BspError) -> ExportError {
+        ExportError::BspError(err)
+    }
+}
+
+impl From<MdlError> for ExportError {
+    fn from(err: MdlError) -> ExportError {
+        ExportError::MdlError(err)
+    }
+}
+
+pub type ExportResult<T> = Result<T, ExportError>;
+
+/// A material, with the file name of its texture relative to the MTL file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    pub name: String,
+    pub texture: Option<String>,
+}
+
+/// A polygon as pairs of position and texture coordinate indices into its
+/// mesh, in Quake's (clockwise) order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjFace {
+    pub material: String,
+    pub vertices: Vec<(usize, usize)>,
+}
+
+/// One object of an OBJ file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjMesh {
+    pub name: String,
+    pub positions: Vec<Vec3>,
+    /// Texture coordinates from 0 to 1, with `v` pointing up.
+    pub uvs: Vec<(f32, f32)>,
+    pub faces: Vec<ObjFace>,
+}
+
+impl ObjMesh {
+    pub fn new(name: &str) -> ObjMesh {
+        ObjMesh {
+            name: name.into(),
+            positions: vec![],
+            uvs: vec![],
+            faces: vec![],
+        }
+    }
+
+    /// The faces of model `model` of `bsp`, with a material per texture.
+    /// The world is called `world` and the brush models `*1`, `*2`, ...
+    /// like the `model` keys of their entities.
+    pub fn from_bsp_model(bsp: &Bsp, textures: &TextureSet, model: usize) -> ObjMesh {
+        let name = if model == 0 {
+            "world".into()
+        } else {
+            format!("*{}", model)
+        };
+        let mut mesh = ObjMesh::new(&name);
+        // Edges are shared, so are the vertices of neighbouring faces
+        let mut positions = HashMap::new();
+        let m = &bsp.models[model];
+        for face in &bsp.faces[m.first_face..m.first_face + m.num_faces] {
+            let texinfo = &bsp.texinfo[face.texinfo];
+            let texture = textures.get(texinfo.miptex);
+            // Missing textures are drawn with a 64x64 checkerboard
+            let (width, height) = texture.map_or((64.0, 64.0), |t| {
+                (t.width() as f32, t.height() as f32)
+            });
+            let vertices = (face.first_edge..face.first_edge + face.num_edges)
+                               .map(|i| {
+                                   let vertex = bsp.surfedge_vertex(i);
+                                   let v = bsp.vertices[vertex];
+                                   let next = mesh.positions.len();
+                                   let position = *positions.entry(vertex).or_insert(next);
+                                   if position == next {
+                                       mesh.positions.push(v);
+                                   }
+                                   let s = v.dot(texinfo.s) + texinfo.s_offset;
+                                   let t = v.dot(texinfo.t) + texinfo.t_offset;
+                                   mesh.uvs.push((s / width, 1.0 - t / height));
+                                   (position, mesh.uvs.len() - 1)
+                               })
+                               .collect();
+            mesh.faces.push(ObjFace {
+                material: texture.map_or(MISSING_MATERIAL.into(), |t| material_name(&t.name)),
+                vertices: vertices,
+            });
+        }
+        mesh
+    }
+
+    /// The triangles of an alias model in the pose of `frame`, which is one
+    /// of the frames of `mdl`, with its skin as the material `skin`.
+    pub fn from_alias_frame(mdl: &Mdl, frame: &SimpleFrame, name: &str) -> ObjMesh {
+        let mut mesh = ObjMesh::new(name);
+        mesh.positions = frame.vertices.iter().map(|v| v.position).collect();
+        let (width, height) = (mdl.skin_width as f32, mdl.skin_height as f32);
+        // Seam vertices of back facing triangles use the back half of the
+        // skin, so every vertex has two sets of coordinates
+        for &shift in &[0.0, width / 2.0] {
+            for st in &mdl.st_verts {
+                let s = st.s as f32 + if st.on_seam { shift } else { 0.0 };
+                mesh.uvs.push(((s + 0.5) / width, 1.0 - (st.t as f32 + 0.5) / height));
+            }
+        }
+        let back = mdl.st_verts.len();
+        mesh.faces = mdl.triangles
+                        .iter()
+                        .map(|triangle| {
+                            let shift = if triangle.faces_front { 0 } else { back };
+                            ObjFace {
+                                material: "skin".into(),
+                                vertices: triangle.vertices
+                                                  .iter()
+                                                  .map(|&v| (v, v + shift))
+                                                  .collect(),
+                            }
+                        })
+                        .collect();
+        mesh
+    }
+}
+
+/// The material of a texture, which is also the name of its PNG file
+/// without the extension. `*` isn't allowed in file names everywhere, so it
+/// is replaced by `#`, like `TextureSet::export_png` does.
+pub fn material_name(texture: &str) -> String {
+    texture.replace('*', "#")
+}
+
+/// Writes `meshes` as objects of one OBJ file, whose materials are in
+/// `mtllib` if given.
+pub fn write_obj<W: Write>(out: &mut W,
+                           mtllib: Option<&str>,
+                           meshes: &[ObjMesh])
+                           -> io::Result<()> {
+    if let Some(mtllib) = mtllib {
+        try!(writeln!(out, "mtllib {}", mtllib));
+    }
+    // Indices are 1-based and count through the whole file
+    let (mut first_position, mut first_uv) = (1, 1);
+    for mesh in meshes {
+        try!(writeln!(out, "o {}", mesh.name));
+        for p in &mesh.positions {
+            try!(writeln!(out, "v {} {} {}", p.x, p.z, -p.y));
+        }
+        for &(u, v) in &mesh.uvs {
+            try!(writeln!(out, "vt {} {}", u, v));
+        }
+        let mut material = None;
+        for face in &mesh.faces {
+            if material != Some(&face.material) {
+                try!(writeln!(out, "usemtl {}", face.material));
+                material = Some(&face.material);
+            }
+            try!(write!(out, "f"));
+            for &(position, uv) in face.vertices.iter().rev() {
+                try!(write!(out, " {}/{}", position + first_position, uv + first_uv));
+            }
+            try!(writeln!(out));
+        }
+        first_position += mesh.positions.len();
+        first_uv += mesh.uvs.len();
+    }
+    Ok(())
+}
+
+/// Writes an MTL file with unlit, fully diffuse materials.
+pub fn write_mtl<W: Write>(out: &mut W, materials: &[Material]) -> io::Result<()> {
+    for material in materials {
+        try!(writeln!(out, "newmtl {}", material.name));
+        try!(writeln!(out, "Ka 0 0 0\nKd 1 1 1\nKs 0 0 0\nillum 1"));
+        if let Some(ref texture) = material.texture {
+            try!(writeln!(out, "map_Kd {}", texture));
+        }
+    }
+    Ok(())
+}
+
+/// Writes the world and brush models of `bsp` to `dir/<name>.obj`, their
+/// materials to `dir/<name>.mtl` and the textures as PNG images next to
+/// them.
+pub fn export_bsp<P: AsRef<Path>>(bsp: &Bsp,
+                                  palette: &Palette,
+                                  dir: P,
+                                  name: &str)
+                                  -> BspResult<()> {
+    let dir = dir.as_ref();
+    let textures = try!(TextureSet::new(bsp));
+    let meshes: Vec<_> = (0..bsp.models.len())
+                             .map(|m| ObjMesh::from_bsp_model(bsp, &textures, m))
+                             .collect();
+
+    let mut materials: Vec<_> = (0..textures.len())
+                                    .filter_map(|i| textures.get(i))
+                                    .map(|t| {
+                                        let name = material_name(&t.name);
+                                        Material {
+                                            texture: Some(format!("{}.png", name)),
+                                            name: name,
+                                        }
+                                    })
+                                    .collect();
+    materials.push(Material {
+        name: MISSING_MATERIAL.into(),
+        texture: None,
+    });
+
+    try!(write_files(dir, name, &meshes, &materials));
+    try!(textures.export_png(dir, palette));
+    Ok(())
+}
+
+/// Writes `frame` of `mdl` to `dir/<name>.obj` with `skin` (e.g. from
+/// `Mdl::skin`) as `dir/<name>.png`.
+pub fn export_alias<P: AsRef<Path>>(mdl: &Mdl,
+                                    frame: &SimpleFrame,
+                                    skin: &Texture,
+                                    palette: &Palette,
+                                    dir: P,
+                                    name: &str)
+                                    -> io::Result<()> {
+    let dir = dir.as_ref();
+    let materials = [Material {
+                         name: "skin".into(),
+                         texture: Some(format!("{}.png", name)),
+                     }];
+    try!(write_files(dir, name, &[ObjMesh::from_alias_frame(mdl, frame, name)], &materials));
+    let mut file = io::BufWriter::new(try!(File::create(dir.join(format!("{}.png", name)))));
+    png::write_indexed(&mut file,
+                       skin.width() as u32,
+                       skin.height() as u32,
+                       &palette.to_bytes(),
+                       skin.pixels())
+}
+
+/// Exports a map (e.g. `maps/e1m1.bsp`) or the first frame and skin of an
+/// alias model (e.g. `progs/player.mdl`) from the PAK files to `dir`, which
+/// is created if needed. The files are named after `file`.
+pub fn export_file<P: AsRef<Path>>(paks: &mut PackContainer,
+                                   file: &str,
+                                   dir: P)
+                                   -> ExportResult<()> {
+    let dir = dir.as_ref();
+    let name = match Path::new(file).file_stem().and_then(|s| s.to_str()) {
+        Some(name) => name,
+        None => return Err(ExportError::UnsupportedFile(file.into())),
+    };
+    let palette = try!(Palette::new(paks));
+    if file.ends_with(".bsp") {
+        let bsp = try!(Bsp::load(paks, file));
+        try!(fs::create_dir_all(dir));
+        try!(export_bsp(&bsp, &palette, dir, name));
+    } else if file.ends_with(".mdl") {
+        let mdl = try!(Mdl::load(paks, file));
+        try!(fs::create_dir_all(dir));
+        try!(export_alias(&mdl, mdl.frame(0, 0.0), mdl.skin(0, 0.0), &palette, dir, name));
+    } else {
+        return Err(ExportError::UnsupportedFile(file.into()));
+    }
+    Ok(())
+}
+
+/// Writes `dir/<name>.obj` and `dir/<name>.mtl`.
+fn write_files(dir: &Path,
+               name: &str,
+               meshes: &[ObjMesh],
+               materials: &[Material])
+               -> io::Result<()> {
+    let mtllib = format!("{}.mtl", name);
+    let mut obj = io::BufWriter::new(try!(File::create(dir.join(format!("{}.obj", name)))));
+    try!(write_obj(&mut obj, Some(&mtllib), meshes));
+    let mut mtl = io::BufWriter::new(try!(File::create(dir.join(mtllib))));
+    write_mtl(&mut mtl, materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use drawing::Palette;
+    use files::bsp::{Bsp, TextureSet};
+    use files::mdl::Mdl;
+    use files::PackContainer;
+    use files::testing::{MdlBuilder, floor_bsp, floor_bsp_with, temp_path, write_pak};
+    use util::Vec3;
+    use super::{ExportError, ObjMesh, export_alias, export_bsp, export_file, write_obj};
+
+    fn obj_text(meshes: &[ObjMesh]) -> String {
+        let mut out = vec![];
+        write_obj(&mut out, Some("floor.mtl"), meshes).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn bsp_faces() {
+        let bsp = Bsp::from_bytes(&floor_bsp_with("*lava1", 64, 32, |_, _, _| 1).build()).unwrap();
+        let textures = TextureSet::new(&bsp).unwrap();
+        let mesh = ObjMesh::from_bsp_model(&bsp, &textures, 0);
+        assert_eq!(mesh.name, "world");
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.positions[1], Vec3::new(-64.0, 64.0, 0.0));
+        // s and t are x and y, divided by the texture size
+        assert_eq!(&mesh.uvs[..2], &[(-1.0, 3.0), (-1.0, -1.0)]);
+        assert_eq!(mesh.faces.len(), 1);
+        assert_eq!(mesh.faces[0].material, "#lava1");
+        assert_eq!(mesh.faces[0].vertices, vec![(0, 0), (1, 1), (2, 2), (3, 3)]);
+
+        let text = obj_text(&[mesh.clone(), mesh]);
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(&lines[..3], &["mtllib floor.mtl", "o world", "v -64 0 64"]);
+        assert_eq!(lines[6], "vt -1 3");
+        // Reversed, and counting on in the second object
+        assert_eq!(&lines[10..12], &["usemtl #lava1", "f 4/4 3/3 2/2 1/1"]);
+        assert_eq!(lines[22], "f 8/8 7/7 6/6 5/5");
+    }
+
+    #[test]
+    fn alias_frames() {
+        let mut b = MdlBuilder::new([1.0, 1.0, 1.0], [-16.0, -16.0, -16.0], 8, 4);
+        b.skin(|_, _| 3)
+         .st_vert(true, 0, 0)
+         .st_vert(false, 2, 2)
+         .st_vert(false, 6, 3)
+         .triangle(true, [0, 1, 2])
+         .triangle(false, [2, 1, 0])
+         .frame("stand", &[[16, 16, 16, 0], [32, 16, 16, 0], [16, 32, 16, 0]]);
+        let mdl = Mdl::from_bytes(&b.build()).unwrap();
+        let mesh = ObjMesh::from_alias_frame(&mdl, mdl.frame(0, 0.0), "stand");
+        assert_eq!(mesh.positions[1], Vec3::new(16.0, 0.0, 0.0));
+        assert_eq!(&mesh.uvs[..2], &[(0.0625, 0.875), (0.3125, 0.375)]);
+        // The seam vertex of the back facing triangle is shifted
+        assert_eq!(mesh.uvs[3], (0.5625, 0.875));
+        assert_eq!(mesh.faces[0].vertices, vec![(0, 0), (1, 1), (2, 2)]);
+        assert_eq!(mesh.faces[1].vertices, vec![(2, 5), (1, 4), (0, 3)]);
+        assert!(obj_text(&[mesh]).ends_with("usemtl skin\nf 3/3 2/2 1/1\nf 1/4 2/5 3/6\n"));
+    }
+
+    #[test]
+    fn export() {
+        let dir = temp_path("obj-export");
+        fs::create_dir_all(&dir).unwrap();
+        let palette = Palette::from_bytes(&[0; 768]);
+        let bsp = Bsp::from_bytes(&floor_bsp().build()).unwrap();
+        export_bsp(&bsp, &palette, &dir, "floor").unwrap();
+        let obj = fs::read_to_string(dir.join("floor.obj")).unwrap();
+        assert!(obj.starts_with("mtllib floor.mtl\no world\n"));
+        let mtl = fs::read_to_string(dir.join("floor.mtl")).unwrap();
+        assert!(mtl.starts_with("newmtl floor\n"));
+        assert!(mtl.contains("map_Kd floor.png\nnewmtl missing\n"));
+        assert!(dir.join("floor.png").exists());
+
+        let mut b = MdlBuilder::new([1.0, 1.0, 1.0], [0.0, 0.0, 0.0], 4, 4);
+        b.skin(|_, _| 3).st_vert(false, 0, 0).triangle(true, [0, 0, 0]).frame("a", &[[0; 4]]);
+        let mdl = Mdl::from_bytes(&b.build()).unwrap();
+        export_alias(&mdl, mdl.frame(0, 0.0), mdl.skin(0, 0.0), &palette, &dir, "model")
+            .unwrap();
+        let mtl = fs::read_to_string(dir.join("model.mtl")).unwrap();
+        assert!(mtl.ends_with("map_Kd model.png\n"));
+        assert_eq!(&fs::read(dir.join("model.png")).unwrap()[1..4], b"PNG");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn export_from_paks() {
+        let mut b = MdlBuilder::new([1.0, 1.0, 1.0], [0.0, 0.0, 0.0], 4, 4);
+        b.skin(|_, _| 3).st_vert(false, 0, 0).triangle(true, [0, 0, 0]).frame("a", &[[0; 4]]);
+        let path = write_pak("obj_export",
+                             &[("gfx/palette.lmp", &[0; 768]),
+                               ("maps/floor.bsp", &floor_bsp().build()),
+                               ("progs/thing.mdl", &b.build())]);
+        let mut paks = PackContainer::new();
+        paks.read_pack(path).unwrap();
+
+        // The directory is created
+        let dir = temp_path("obj-export-paks").join("out");
+        export_file(&mut paks, "maps/floor.bsp", &dir).unwrap();
+        export_file(&mut paks, "progs/thing.mdl", &dir).unwrap();
+        for file in &["floor.obj", "floor.png", "thing.obj", "thing.png"] {
+            assert!(dir.join(file).exists());
+        }
+        match export_file(&mut paks, "gfx/palette.lmp", &dir) {
+            Err(ExportError::UnsupportedFile(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
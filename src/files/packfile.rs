@@ -137,8 +137,13 @@ impl Pack {
                 try!(file_mgr.seek(self.handle, io::SeekFrom::Start(f.position as u64)));
                 let mut buf = vec![0; f.length as usize];
                 let mut bytes_read = 0;
-                while bytes_read < f.length {
-                    bytes_read += try!(file_mgr.read(self.handle, &mut buf)) as i32;
+                while bytes_read < buf.len() {
+                    let n = try!(file_mgr.read(self.handle, &mut buf[bytes_read..]));
+                    if n == 0 {
+                        let eof = io::Error::new(io::ErrorKind::UnexpectedEof, "truncated PAK");
+                        return Err(eof.into());
+                    }
+                    bytes_read += n;
                 }
                 Ok(buf)
             }
@@ -175,8 +180,8 @@ impl PackContainer {
         for pak in &self.files {
             let result = pak.read_file(filename, &mut self.file_mgr);
             match result {
-                r@Ok(_) => return r,
-                Err(_) => continue,
+                Err(PackError::UnknownContentFileName) => continue,
+                r => return r,
             }
         }
         Err(PackError::UnknownContentFileName)
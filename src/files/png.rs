@@ -0,0 +1,148 @@
+//! A minimal PNG writer. Image data is stored in uncompressed deflate
+//! blocks, so no compression library is needed. The files are bigger than
+//! they could be, but every PNG reader can open them.
+
+use std::io;
+use std::io::prelude::*;
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+
+const SIGNATURE: &'static [u8] = b"\x89PNG\r\n\x1a\n";
+/// Largest payload of a stored deflate block.
+const MAX_STORED_BLOCK: usize = 65535;
+
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_INDEXED: u8 = 3;
+
+/// Writes an 8 bit RGB image. `rgb` holds 3 bytes per pixel, row by row.
+pub fn write_rgb<W: Write>(out: &mut W, width: u32, height: u32, rgb: &[u8]) -> io::Result<()> {
+    assert_eq!(rgb.len(), (width * height * 3) as usize);
+    try!(out.write_all(SIGNATURE));
+    try!(write_header(out, width, height, COLOR_TYPE_RGB));
+    try!(write_image_data(out, rgb, width as usize * 3));
+    write_chunk(out, b"IEND", &[])
+}
+
+/// Writes a palettized image. `palette` holds up to 256 RGB triples and
+/// `pixels` one palette index per pixel, row by row.
+pub fn write_indexed<W: Write>(out: &mut W,
+                               width: u32,
+                               height: u32,
+                               palette: &[u8],
+                               pixels: &[u8])
+                               -> io::Result<()> {
+    assert_eq!(pixels.len(), (width * height) as usize);
+    assert!(palette.len() % 3 == 0 && palette.len() <= 768);
+    try!(out.write_all(SIGNATURE));
+    try!(write_header(out, width, height, COLOR_TYPE_INDEXED));
+    try!(write_chunk(out, b"PLTE", palette));
+    try!(write_image_data(out, pixels, width as usize));
+    write_chunk(out, b"IEND", &[])
+}
+
+fn write_header<W: Write>(out: &mut W, width: u32, height: u32, color_type: u8) -> io::Result<()> {
+    let mut ihdr = vec![];
+    try!(ihdr.write_u32::<BigEndian>(width));
+    try!(ihdr.write_u32::<BigEndian>(height));
+    // Bit depth, color type, compression, filter and interlace method
+    ihdr.extend_from_slice(&[8, color_type, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &ihdr)
+}
+
+/// Writes the IDAT chunk: every row prefixed with filter type 0 (none),
+/// wrapped in a zlib stream of stored blocks.
+fn write_image_data<W: Write>(out: &mut W, data: &[u8], stride: usize) -> io::Result<()> {
+    let mut raw = Vec::with_capacity(data.len() + data.len() / stride.max(1) + 1);
+    for row in data.chunks(stride) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    // zlib header: deflate with a 32K window, no preset dictionary, fastest
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = raw.chunks(MAX_STORED_BLOCK).collect::<Vec<_>>();
+    for (i, block) in blocks.iter().enumerate() {
+        let last = i == blocks.len() - 1;
+        zlib.push(if last { 1 } else { 0 });
+        let len = block.len() as u16;
+        try!(zlib.write_u16::<LittleEndian>(len));
+        try!(zlib.write_u16::<LittleEndian>(!len));
+        zlib.extend_from_slice(block);
+    }
+    if blocks.is_empty() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    try!(zlib.write_u32::<BigEndian>(adler32(&raw)));
+
+    write_chunk(out, b"IDAT", &zlib)
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    try!(out.write_u32::<BigEndian>(data.len() as u32));
+    try!(out.write_all(kind));
+    try!(out.write_all(data));
+    let crc = crc32(crc32_update(0xFFFF_FFFF, kind), data);
+    out.write_u32::<BigEndian>(crc)
+}
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    crc32_update(crc, data) ^ 0xFFFF_FFFF
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{adler32, crc32, write_indexed, write_rgb};
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(0xFFFF_FFFF, b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn rgb_layout() {
+        let mut out = vec![];
+        write_rgb(&mut out, 2, 1, &[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(&out[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&out[12..16], b"IHDR");
+        // IHDR (25 bytes) is followed by IDAT, which contains the filter byte
+        // and both pixels after the zlib and stored block headers.
+        assert_eq!(&out[37..41], b"IDAT");
+        assert_eq!(&out[41..48], &[0x78, 0x01, 1, 7, 0, 0xF8, 0xFF]);
+        assert_eq!(&out[48..55], &[0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(&out[out.len() - 8..out.len() - 4], b"IEND");
+    }
+
+    #[test]
+    fn large_images_use_several_blocks() {
+        let pixels = vec![3; 300 * 300];
+        let palette = [0; 12];
+        let mut out = vec![];
+        write_indexed(&mut out, 300, 300, &palette, &pixels).unwrap();
+        // Raw data: 300 rows of 301 bytes in two stored blocks + checksums
+        let idat_len = 2 + 2 * 5 + 300 * 301 + 4;
+        assert_eq!(out.len(), 8 + 25 + (12 + 12) + (12 + idat_len) + 12);
+    }
+}
@@ -0,0 +1,280 @@
+//! Loader for the sprites of `progs/*.spr`: explosions, bubbles and flames.
+//!
+//! A sprite is a series of 8 bit images drawn on a flat quad that is turned
+//! towards the viewer in one of several ways. Like the frames of alias
+//! models, sprite frames can be grouped into animations with their own
+//! intervals. Color 255 (`drawing::TRANSPARENT`) is transparent.
+
+use std::io;
+use std::io::prelude::*;
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use drawing::Texture;
+use files::{PackContainer, PackError};
+use files::mdl::{SyncType, group_index, read_count, read_intervals};
+use hprof;
+
+/// "IDSP" as a little endian integer.
+pub const SPR_IDENT: i32 = 0x50534449;
+pub const SPR_VERSION: i32 = 1;
+pub const MAX_FRAMES: usize = 256;
+
+/// Size of the header in bytes.
+const HEADER_SIZE: usize = 36;
+
+#[derive(Debug)]
+pub enum SprError {
+    IoError(io::Error),
+    PackError(PackError),
+    /// The file doesn't start with "IDSP".
+    BadIdent(i32),
+    UnsupportedVersion(i32),
+    /// A count, size or type is out of range.
+    InvalidHeader(&'static str),
+    /// The intervals of a frame group don't increase.
+    InvalidInterval,
+}
+
+impl From<io::Error> for SprError {
+    fn from(err: io::Error) -> SprError {
+        SprError::IoError(err)
+    }
+}
+
+impl From<PackError> for SprError {
+    fn from(err: PackError) -> SprError {
+        SprError::PackError(err)
+    }
+}
+
+pub type SprResult<T> = Result<T, SprError>;
+
+/// How a sprite is turned towards the viewer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpriteKind {
+    /// Parallel to the view plane, but always standing upright.
+    ParallelUpright,
+    /// Standing upright and facing the viewer's position.
+    FacingUpright,
+    /// Parallel to the view plane.
+    Parallel,
+    /// Oriented by the entity's angles, like a decal.
+    Oriented,
+    /// Parallel to the view plane, rolled by the entity's roll angle.
+    ParallelOriented,
+}
+
+/// One image of a sprite.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteFrame {
+    /// Position of the image's top left corner relative to the entity's
+    /// origin, in pixels to the right and up.
+    pub origin: [i32; 2],
+    pub image: Texture,
+}
+
+impl SpriteFrame {
+    /// Distances of the image's edges from the origin: left, right, up and
+    /// down.
+    pub fn edges(&self) -> (f32, f32, f32, f32) {
+        let left = self.origin[0] as f32;
+        let up = self.origin[1] as f32;
+        (left, left + self.image.width() as f32, up, up - self.image.height() as f32)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Single(SpriteFrame),
+    /// Frames cycled through by time. `intervals` are the times at which
+    /// each frame ends, relative to the start of the group.
+    Group {
+        intervals: Vec<f32>,
+        frames: Vec<SpriteFrame>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spr {
+    pub kind: SpriteKind,
+    pub radius: f32,
+    /// Size of the largest frame.
+    pub width: usize,
+    pub height: usize,
+    pub frames: Vec<Frame>,
+    pub beam_length: f32,
+    pub sync_type: SyncType,
+}
+
+impl Spr {
+    /// Reads and parses a sprite from the PAK files, e.g. `progs/s_explod.spr`.
+    pub fn load(paks: &mut PackContainer, name: &str) -> SprResult<Spr> {
+        let bytes = try!(paks.read(name));
+        Spr::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> SprResult<Spr> {
+        let _guard = hprof::enter("Spr::from_bytes");
+        if data.len() < HEADER_SIZE {
+            return Err(SprError::InvalidHeader("size"));
+        }
+        let mut rdr = io::Cursor::new(data);
+        let ident = try!(rdr.read_i32::<LittleEndian>());
+        if ident != SPR_IDENT {
+            return Err(SprError::BadIdent(ident));
+        }
+        let version = try!(rdr.read_i32::<LittleEndian>());
+        if version != SPR_VERSION {
+            return Err(SprError::UnsupportedVersion(version));
+        }
+
+        let kind = match try!(rdr.read_i32::<LittleEndian>()) {
+            0 => SpriteKind::ParallelUpright,
+            1 => SpriteKind::FacingUpright,
+            2 => SpriteKind::Parallel,
+            3 => SpriteKind::Oriented,
+            4 => SpriteKind::ParallelOriented,
+            _ => return Err(SprError::InvalidHeader("type")),
+        };
+        let radius = try!(rdr.read_f32::<LittleEndian>());
+        let invalid = SprError::InvalidHeader;
+        let width = try!(read_count(&mut rdr, 0, data.len(), "width", invalid));
+        let height = try!(read_count(&mut rdr, 0, data.len(), "height", invalid));
+        let num_frames = try!(read_count(&mut rdr, 1, MAX_FRAMES, "frames", invalid));
+        let beam_length = try!(rdr.read_f32::<LittleEndian>());
+        let sync_type = match try!(rdr.read_i32::<LittleEndian>()) {
+            0 => SyncType::Sync,
+            1 => SyncType::Random,
+            _ => return Err(SprError::InvalidHeader("sync type")),
+        };
+
+        let mut frames = Vec::with_capacity(num_frames);
+        for _ in 0..num_frames {
+            frames.push(try!(read_frame(&mut rdr)));
+        }
+
+        Ok(Spr {
+            kind: kind,
+            radius: radius,
+            width: width,
+            height: height,
+            frames: frames,
+            beam_length: beam_length,
+            sync_type: sync_type,
+        })
+    }
+
+    /// Frame `frame` at `time` seconds, like `R_GetSpriteframe`. Frame
+    /// numbers out of range use frame 0.
+    pub fn frame(&self, frame: usize, time: f32) -> &SpriteFrame {
+        match self.frames.get(frame).unwrap_or(&self.frames[0]) {
+            Frame::Single(frame) => frame,
+            Frame::Group { intervals, frames } => &frames[group_index(intervals, time)],
+        }
+    }
+}
+
+/// Reads a frame without its type: origin, size and pixels.
+fn read_single_frame(rdr: &mut io::Cursor<&[u8]>) -> SprResult<SpriteFrame> {
+    let x = try!(rdr.read_i32::<LittleEndian>());
+    let y = try!(rdr.read_i32::<LittleEndian>());
+    let remaining = rdr.get_ref().len() - rdr.position() as usize;
+    let width = try!(read_count(rdr, 1, remaining, "frame width", SprError::InvalidHeader));
+    let height = try!(read_count(rdr, 1, remaining, "frame height", SprError::InvalidHeader));
+    if width * height > remaining - 8 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated sprite").into());
+    }
+    let mut pixels = vec![0; width * height];
+    try!(rdr.read_exact(&mut pixels));
+    Ok(SpriteFrame {
+        origin: [x, y],
+        image: Texture::new(width, height, pixels),
+    })
+}
+
+fn read_frame(rdr: &mut io::Cursor<&[u8]>) -> SprResult<Frame> {
+    match try!(rdr.read_i32::<LittleEndian>()) {
+        0 => Ok(Frame::Single(try!(read_single_frame(rdr)))),
+        1 => {
+            let count = try!(read_count(rdr, 1, MAX_FRAMES, "group size", SprError::InvalidHeader));
+            let intervals = try!(read_intervals(rdr, count, SprError::InvalidInterval));
+            let mut frames = Vec::with_capacity(count);
+            for _ in 0..count {
+                frames.push(try!(read_single_frame(rdr)));
+            }
+            Ok(Frame::Group {
+                intervals: intervals,
+                frames: frames,
+            })
+        }
+        _ => Err(SprError::InvalidHeader("frame type")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use files::mdl::SyncType;
+    use files::testing::SprBuilder;
+    use super::*;
+
+    fn explosion() -> SprBuilder {
+        let mut b = SprBuilder::new(2, 16, 8);
+        b.frame([-8, 4], 16, 8, |x, y| (x + y * 16) as u8)
+         .frame_group(&[0.1, 0.2], [-2, 2], 4, 4, &[30, 40]);
+        b
+    }
+
+    #[test]
+    fn parses_sprites() {
+        let spr = Spr::from_bytes(&explosion().build()).unwrap();
+        assert_eq!(spr.kind, SpriteKind::Parallel);
+        assert_eq!((spr.width, spr.height), (16, 8));
+        assert_eq!(spr.sync_type, SyncType::Sync);
+        assert_eq!(spr.frames.len(), 2);
+
+        let frame = spr.frame(0, 0.0);
+        assert_eq!(frame.origin, [-8, 4]);
+        assert_eq!(frame.image.get(3, 1), 19);
+        assert_eq!(frame.edges(), (-8.0, 8.0, 4.0, -4.0));
+
+        assert_eq!(spr.frame(1, 0.05).image.get(0, 0), 30);
+        assert_eq!(spr.frame(1, 0.15).image.get(0, 0), 40);
+        assert_eq!(spr.frame(1, 0.25).image.get(0, 0), 30);
+        assert_eq!(spr.frame(7, 0.0).origin, [-8, 4]);
+    }
+
+    #[test]
+    fn rejects_invalid_sprites() {
+        let bytes = explosion().build();
+        for len in 0..bytes.len() {
+            assert!(Spr::from_bytes(&bytes[..len]).is_err());
+        }
+
+        let mut bad_ident = bytes.clone();
+        bad_ident[3] = b'O';
+        match Spr::from_bytes(&bad_ident) {
+            Err(SprError::BadIdent(_)) => {}
+            other => panic!("{:?}", other),
+        }
+        let mut bad_type = bytes.clone();
+        bad_type[8] = 5;
+        match Spr::from_bytes(&bad_type) {
+            Err(SprError::InvalidHeader("type")) => {}
+            other => panic!("{:?}", other),
+        }
+
+        let mut b = explosion();
+        b.frame_group(&[0.2, 0.1], [0, 0], 1, 1, &[1, 2]);
+        match Spr::from_bytes(&b.build()) {
+            Err(SprError::InvalidInterval) => {}
+            other => panic!("{:?}", other),
+        }
+        // A frame claiming more pixels than there are
+        let mut b = SprBuilder::new(0, 1, 1);
+        b.frame([0, 0], 1, 1, |_, _| 0);
+        let mut huge = b.build();
+        let len = huge.len();
+        huge[len - 5..len - 1].copy_from_slice(&[0, 0, 1, 0]);
+        assert!(Spr::from_bytes(&huge).is_err());
+    }
+}
@@ -0,0 +1,569 @@
+//! Helpers for tests that need game data without shipping the original PAK
+//! files or maps.
+
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::process;
+use byteorder::{LittleEndian, WriteBytesExt};
+
+/// A path in the temporary directory for files a test creates and removes
+/// again. `name` should be unique per test, since tests run in parallel, and
+/// the process ID keeps concurrent test runs apart.
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("quake-rs-{}-{}", process::id(), name))
+}
+
+/// Writes a PAK file containing the given files to the temporary directory
+/// and returns its path. `name` should be unique per test, since tests run
+/// in parallel.
+pub fn write_pak(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+    let mut data = vec![];
+    let mut directory = vec![];
+    let mut offset = 12;
+    for &(filename, contents) in files {
+        let mut entry = [0u8; 56];
+        entry[..filename.len()].copy_from_slice(filename.as_bytes());
+        directory.extend_from_slice(&entry);
+        directory.write_i32::<LittleEndian>(offset).unwrap();
+        directory.write_i32::<LittleEndian>(contents.len() as i32).unwrap();
+        data.extend_from_slice(contents);
+        offset += contents.len() as i32;
+    }
+
+    let mut bytes = b"PACK".to_vec();
+    bytes.write_i32::<LittleEndian>(offset).unwrap();
+    bytes.write_i32::<LittleEndian>(directory.len() as i32).unwrap();
+    bytes.extend_from_slice(&data);
+    bytes.extend_from_slice(&directory);
+
+    let path = env::temp_dir().join(format!("quake-rs-{}.pak", name));
+    let mut file = File::create(&path).unwrap();
+    file.write_all(&bytes).unwrap();
+    path
+}
+
+/// Assembles BSP files lump by lump. Elements are appended in the on-disk
+/// format, indices aren't checked.
+#[derive(Default)]
+pub struct BspBuilder {
+    lumps: [Vec<u8>; 15],
+    textures: Vec<Vec<u8>>,
+}
+
+impl BspBuilder {
+    pub fn new() -> BspBuilder {
+        BspBuilder::default()
+    }
+
+    pub fn entities(&mut self, entities: &str) -> &mut BspBuilder {
+        self.lumps[0] = entities.as_bytes().to_vec();
+        self.lumps[0].push(0);
+        self
+    }
+
+    pub fn plane(&mut self, normal: [f32; 3], dist: f32, kind: i32) -> &mut BspBuilder {
+        {
+            let l = &mut self.lumps[1];
+            for &n in &normal {
+                l.write_f32::<LittleEndian>(n).unwrap();
+            }
+            l.write_f32::<LittleEndian>(dist).unwrap();
+            l.write_i32::<LittleEndian>(kind).unwrap();
+        }
+        self
+    }
+
+    /// Adds a texture whose texels are all set to `color`, at every mip level.
+    pub fn texture(&mut self, name: &str, width: u32, height: u32, color: u8) -> &mut BspBuilder {
+        self.texture_with(name, width, height, |_, _, _| color)
+    }
+
+    /// Adds a texture whose texels are `texel(mip level, x, y)`.
+    pub fn texture_with<F>(&mut self,
+                           name: &str,
+                           width: u32,
+                           height: u32,
+                           texel: F)
+                           -> &mut BspBuilder
+        where F: Fn(u32, u32, u32) -> u8
+    {
+        let mut t = vec![0; 16];
+        t[..name.len()].copy_from_slice(name.as_bytes());
+        t.write_u32::<LittleEndian>(width).unwrap();
+        t.write_u32::<LittleEndian>(height).unwrap();
+        let mut offset = 40;
+        for level in 0..4 {
+            t.write_u32::<LittleEndian>(offset).unwrap();
+            offset += (width >> level) * (height >> level);
+        }
+        for level in 0..4 {
+            for y in 0..height >> level {
+                for x in 0..width >> level {
+                    t.push(texel(level, x, y));
+                }
+            }
+        }
+        self.textures.push(t);
+        self
+    }
+
+    pub fn vertex(&mut self, v: [f32; 3]) -> &mut BspBuilder {
+        for &c in &v {
+            self.lumps[3].write_f32::<LittleEndian>(c).unwrap();
+        }
+        self
+    }
+
+    pub fn visibility(&mut self, vis: &[u8]) -> &mut BspBuilder {
+        self.lumps[4] = vis.to_vec();
+        self
+    }
+
+    pub fn node(&mut self,
+                plane: i32,
+                children: [i16; 2],
+                mins: [i16; 3],
+                maxs: [i16; 3],
+                faces: (u16, u16))
+                -> &mut BspBuilder {
+        {
+            let l = &mut self.lumps[5];
+            l.write_i32::<LittleEndian>(plane).unwrap();
+            for &c in children.iter().chain(&mins).chain(&maxs) {
+                l.write_i16::<LittleEndian>(c).unwrap();
+            }
+            l.write_u16::<LittleEndian>(faces.0).unwrap();
+            l.write_u16::<LittleEndian>(faces.1).unwrap();
+        }
+        self
+    }
+
+    pub fn texinfo(&mut self, s: [f32; 4], t: [f32; 4], miptex: i32, flags: i32) -> &mut BspBuilder {
+        {
+            let l = &mut self.lumps[6];
+            for &v in s.iter().chain(&t) {
+                l.write_f32::<LittleEndian>(v).unwrap();
+            }
+            l.write_i32::<LittleEndian>(miptex).unwrap();
+            l.write_i32::<LittleEndian>(flags).unwrap();
+        }
+        self
+    }
+
+    pub fn face(&mut self,
+                plane: u16,
+                side: i16,
+                edges: (i32, u16),
+                texinfo: u16,
+                styles: [u8; 4],
+                light_offset: i32)
+                -> &mut BspBuilder {
+        {
+            let l = &mut self.lumps[7];
+            l.write_u16::<LittleEndian>(plane).unwrap();
+            l.write_i16::<LittleEndian>(side).unwrap();
+            l.write_i32::<LittleEndian>(edges.0).unwrap();
+            l.write_u16::<LittleEndian>(edges.1).unwrap();
+            l.write_u16::<LittleEndian>(texinfo).unwrap();
+            l.extend_from_slice(&styles);
+            l.write_i32::<LittleEndian>(light_offset).unwrap();
+        }
+        self
+    }
+
+    pub fn lighting(&mut self, light: &[u8]) -> &mut BspBuilder {
+        self.lumps[8].extend_from_slice(light);
+        self
+    }
+
+    pub fn clipnode(&mut self, plane: i32, children: [i16; 2]) -> &mut BspBuilder {
+        self.lumps[9].write_i32::<LittleEndian>(plane).unwrap();
+        self.lumps[9].write_i16::<LittleEndian>(children[0]).unwrap();
+        self.lumps[9].write_i16::<LittleEndian>(children[1]).unwrap();
+        self
+    }
+
+    pub fn leaf(&mut self,
+                contents: i32,
+                vis_offset: i32,
+                mins: [i16; 3],
+                maxs: [i16; 3],
+                marksurfaces: (u16, u16))
+                -> &mut BspBuilder {
+        {
+            let l = &mut self.lumps[10];
+            l.write_i32::<LittleEndian>(contents).unwrap();
+            l.write_i32::<LittleEndian>(vis_offset).unwrap();
+            for &c in mins.iter().chain(&maxs) {
+                l.write_i16::<LittleEndian>(c).unwrap();
+            }
+            l.write_u16::<LittleEndian>(marksurfaces.0).unwrap();
+            l.write_u16::<LittleEndian>(marksurfaces.1).unwrap();
+            l.extend_from_slice(&[0; 4]);
+        }
+        self
+    }
+
+    pub fn marksurface(&mut self, face: u16) -> &mut BspBuilder {
+        self.lumps[11].write_u16::<LittleEndian>(face).unwrap();
+        self
+    }
+
+    pub fn edge(&mut self, v0: u16, v1: u16) -> &mut BspBuilder {
+        self.lumps[12].write_u16::<LittleEndian>(v0).unwrap();
+        self.lumps[12].write_u16::<LittleEndian>(v1).unwrap();
+        self
+    }
+
+    pub fn surfedge(&mut self, edge: i32) -> &mut BspBuilder {
+        self.lumps[13].write_i32::<LittleEndian>(edge).unwrap();
+        self
+    }
+
+    pub fn model(&mut self,
+                 mins: [f32; 3],
+                 maxs: [f32; 3],
+                 headnodes: [i32; 4],
+                 visleafs: i32,
+                 faces: (i32, i32))
+                 -> &mut BspBuilder {
+        {
+            let l = &mut self.lumps[14];
+            for &c in mins.iter().chain(&maxs).chain(&[0.0; 3]) {
+                l.write_f32::<LittleEndian>(c).unwrap();
+            }
+            for &h in &headnodes {
+                l.write_i32::<LittleEndian>(h).unwrap();
+            }
+            l.write_i32::<LittleEndian>(visleafs).unwrap();
+            l.write_i32::<LittleEndian>(faces.0).unwrap();
+            l.write_i32::<LittleEndian>(faces.1).unwrap();
+        }
+        self
+    }
+
+    /// Returns the raw bytes of a lump, for tests that corrupt them.
+    pub fn lump_mut(&mut self, lump: usize) -> &mut Vec<u8> {
+        &mut self.lumps[lump]
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut lumps = self.lumps.clone();
+        if !self.textures.is_empty() {
+            let t = &mut lumps[2];
+            t.clear();
+            t.write_i32::<LittleEndian>(self.textures.len() as i32).unwrap();
+            let mut offset = 4 + 4 * self.textures.len();
+            for texture in &self.textures {
+                t.write_i32::<LittleEndian>(offset as i32).unwrap();
+                offset += texture.len();
+            }
+            for texture in &self.textures {
+                t.extend_from_slice(texture);
+            }
+        }
+
+        let mut bytes = vec![];
+        bytes.write_i32::<LittleEndian>(29).unwrap();
+        let mut offset = 4 + 15 * 8;
+        for lump in &lumps {
+            bytes.write_i32::<LittleEndian>(offset as i32).unwrap();
+            bytes.write_i32::<LittleEndian>(lump.len() as i32).unwrap();
+            // Keep lumps 4 byte aligned, like the map compilers do
+            offset += (lump.len() + 3) & !3;
+        }
+        for lump in &lumps {
+            bytes.extend_from_slice(lump);
+            let padding = ((lump.len() + 3) & !3) - lump.len();
+            bytes.extend(vec![0; padding]);
+        }
+        bytes
+    }
+}
+
+/// A map consisting of a single 128x128 floor face at z = 0, centered on
+/// the origin. Everything below the floor is solid. Leaf 1 is the empty
+/// space above it, and hulls 1 and 2 have the floor moved up by 24 units.
+pub fn floor_bsp() -> BspBuilder {
+    floor_bsp_with("floor", 16, 16, |_, _, _| 7)
+}
+
+/// The map of `floor_bsp`, with the floor textured like
+/// `BspBuilder::texture_with`.
+pub fn floor_bsp_with<F>(texture: &str, width: u32, height: u32, texel: F) -> BspBuilder
+    where F: Fn(u32, u32, u32) -> u8
+{
+    let mut b = BspBuilder::new();
+    b.entities("{\n\"classname\" \"worldspawn\"\n\"wad\" \"gfx/base.wad\"\n}\n\
+                {\n\"classname\" \"info_player_start\"\n\"origin\" \"0 0 24\"\n}\n")
+     .plane([0.0, 0.0, 1.0], 0.0, 2)
+     .plane([0.0, 0.0, 1.0], 24.0, 2)
+     .texture_with(texture, width, height, texel)
+     .vertex([-64.0, -64.0, 0.0])
+     .vertex([-64.0, 64.0, 0.0])
+     .vertex([64.0, 64.0, 0.0])
+     .vertex([64.0, -64.0, 0.0])
+     .visibility(&[0x01])
+     .node(0, [-2, -1], [-64, -64, -64], [64, 64, 64], (0, 1))
+     .texinfo([1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], 0, 0)
+     .face(0, 0, (0, 4), 0, [0, 255, 255, 255], 0)
+     .lighting(&[200; 81])
+     .clipnode(1, [-1, -2])
+     .leaf(-2, -1, [-64, -64, -64], [64, 64, 0], (0, 0))
+     .leaf(-1, 0, [-64, -64, 0], [64, 64, 64], (0, 1))
+     .marksurface(0)
+     .edge(0, 0)
+     .edge(0, 1)
+     .edge(1, 2)
+     .edge(2, 3)
+     .edge(0, 3)
+     .surfedge(1)
+     .surfedge(2)
+     .surfedge(3)
+     .surfedge(-4)
+     .model([-64.0, -64.0, -64.0], [64.0, 64.0, 64.0], [0, 0, 0, 0], 1, (0, 1));
+    b
+}
+
+/// Assembles MDL files. Skins, vertices, triangles and frames are appended in
+/// the on-disk format and counted for the header; indices aren't checked.
+pub struct MdlBuilder {
+    scale: [f32; 3],
+    origin: [f32; 3],
+    skin_size: (u32, u32),
+    flags: i32,
+    skins: (i32, Vec<u8>),
+    st_verts: (i32, Vec<u8>),
+    triangles: (i32, Vec<u8>),
+    frames: (i32, Vec<u8>),
+}
+
+impl MdlBuilder {
+    /// Starts a model with skins of `width * height` texels, whose frame
+    /// vertices are `compressed * scale + origin`.
+    pub fn new(scale: [f32; 3], origin: [f32; 3], width: u32, height: u32) -> MdlBuilder {
+        MdlBuilder {
+            scale: scale,
+            origin: origin,
+            skin_size: (width, height),
+            flags: 0,
+            skins: (0, vec![]),
+            st_verts: (0, vec![]),
+            triangles: (0, vec![]),
+            frames: (0, vec![]),
+        }
+    }
+
+    pub fn flags(&mut self, flags: i32) -> &mut MdlBuilder {
+        self.flags = flags;
+        self
+    }
+
+    /// Adds a skin whose texels are `texel(x, y)`.
+    pub fn skin<F>(&mut self, texel: F) -> &mut MdlBuilder
+        where F: Fn(u32, u32) -> u8
+    {
+        self.skins.0 += 1;
+        self.skins.1.write_i32::<LittleEndian>(0).unwrap();
+        let image = self.skin_image(&texel);
+        self.skins.1.extend(image);
+        self
+    }
+
+    /// Adds a skin group with one single colored image per interval.
+    pub fn skin_group(&mut self, intervals: &[f32], colors: &[u8]) -> &mut MdlBuilder {
+        self.skins.0 += 1;
+        let (width, height) = self.skin_size;
+        let l = &mut self.skins.1;
+        l.write_i32::<LittleEndian>(1).unwrap();
+        l.write_i32::<LittleEndian>(intervals.len() as i32).unwrap();
+        for &interval in intervals {
+            l.write_f32::<LittleEndian>(interval).unwrap();
+        }
+        for &color in colors {
+            l.extend(vec![color; (width * height) as usize]);
+        }
+        self
+    }
+
+    fn skin_image<F>(&self, texel: &F) -> Vec<u8>
+        where F: Fn(u32, u32) -> u8
+    {
+        let (width, height) = self.skin_size;
+        (0..height).flat_map(|y| (0..width).map(move |x| texel(x, y))).collect()
+    }
+
+    pub fn st_vert(&mut self, on_seam: bool, s: i32, t: i32) -> &mut MdlBuilder {
+        self.st_verts.0 += 1;
+        let l = &mut self.st_verts.1;
+        l.write_i32::<LittleEndian>(on_seam as i32).unwrap();
+        l.write_i32::<LittleEndian>(s).unwrap();
+        l.write_i32::<LittleEndian>(t).unwrap();
+        self
+    }
+
+    pub fn triangle(&mut self, faces_front: bool, vertices: [i32; 3]) -> &mut MdlBuilder {
+        self.triangles.0 += 1;
+        let l = &mut self.triangles.1;
+        l.write_i32::<LittleEndian>(faces_front as i32).unwrap();
+        for &v in &vertices {
+            l.write_i32::<LittleEndian>(v).unwrap();
+        }
+        self
+    }
+
+    /// Adds a frame of compressed vertices `[x, y, z, normal]`.
+    pub fn frame(&mut self, name: &str, vertices: &[[u8; 4]]) -> &mut MdlBuilder {
+        self.frames.0 += 1;
+        self.frames.1.write_i32::<LittleEndian>(0).unwrap();
+        write_simple_frame(&mut self.frames.1, name, vertices);
+        self
+    }
+
+    /// Adds a frame group, with one frame per interval.
+    pub fn frame_group(&mut self,
+                       intervals: &[f32],
+                       frames: &[(&str, &[[u8; 4]])])
+                       -> &mut MdlBuilder {
+        self.frames.0 += 1;
+        let l = &mut self.frames.1;
+        l.write_i32::<LittleEndian>(1).unwrap();
+        l.write_i32::<LittleEndian>(intervals.len() as i32).unwrap();
+        l.extend_from_slice(&[0, 0, 0, 0, 255, 255, 255, 0]);
+        for &interval in intervals {
+            l.write_f32::<LittleEndian>(interval).unwrap();
+        }
+        for &(name, vertices) in frames {
+            write_simple_frame(l, name, vertices);
+        }
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut bytes = b"IDPO".to_vec();
+        bytes.write_i32::<LittleEndian>(6).unwrap();
+        for &c in self.scale.iter().chain(&self.origin) {
+            bytes.write_f32::<LittleEndian>(c).unwrap();
+        }
+        // Radius and eye position
+        for &c in &[100.0, 0.0, 0.0, 24.0] {
+            bytes.write_f32::<LittleEndian>(c).unwrap();
+        }
+        let counts = [self.skins.0,
+                      self.skin_size.0 as i32,
+                      self.skin_size.1 as i32,
+                      self.st_verts.0,
+                      self.triangles.0,
+                      self.frames.0,
+                      0,
+                      self.flags];
+        for &c in &counts {
+            bytes.write_i32::<LittleEndian>(c).unwrap();
+        }
+        bytes.write_f32::<LittleEndian>(1.0).unwrap();
+        for part in &[&self.skins, &self.st_verts, &self.triangles, &self.frames] {
+            bytes.extend_from_slice(&part.1);
+        }
+        bytes
+    }
+}
+
+/// Writes the bounds, name and vertices of a frame.
+fn write_simple_frame(l: &mut Vec<u8>, name: &str, vertices: &[[u8; 4]]) {
+    l.extend_from_slice(&[0, 0, 0, 0, 255, 255, 255, 0]);
+    let mut n = [0u8; 16];
+    n[..name.len()].copy_from_slice(name.as_bytes());
+    l.extend_from_slice(&n);
+    for v in vertices {
+        l.extend_from_slice(v);
+    }
+}
+
+/// Assembles SPR files frame by frame.
+pub struct SprBuilder {
+    kind: i32,
+    size: (i32, i32),
+    num_frames: i32,
+    frames: Vec<u8>,
+}
+
+impl SprBuilder {
+    /// Starts a sprite of type `kind` whose largest frame is
+    /// `width * height` pixels.
+    pub fn new(kind: i32, width: i32, height: i32) -> SprBuilder {
+        SprBuilder {
+            kind: kind,
+            size: (width, height),
+            num_frames: 0,
+            frames: vec![],
+        }
+    }
+
+    /// Adds a frame whose pixels are `texel(x, y)`.
+    pub fn frame<F>(&mut self,
+                    origin: [i32; 2],
+                    width: i32,
+                    height: i32,
+                    texel: F)
+                    -> &mut SprBuilder
+        where F: Fn(i32, i32) -> u8
+    {
+        self.num_frames += 1;
+        self.frames.write_i32::<LittleEndian>(0).unwrap();
+        write_sprite_frame(&mut self.frames, origin, width, height, &texel);
+        self
+    }
+
+    /// Adds a frame group with one single colored frame per interval.
+    pub fn frame_group(&mut self,
+                       intervals: &[f32],
+                       origin: [i32; 2],
+                       width: i32,
+                       height: i32,
+                       colors: &[u8])
+                       -> &mut SprBuilder {
+        self.num_frames += 1;
+        let l = &mut self.frames;
+        l.write_i32::<LittleEndian>(1).unwrap();
+        l.write_i32::<LittleEndian>(intervals.len() as i32).unwrap();
+        for &interval in intervals {
+            l.write_f32::<LittleEndian>(interval).unwrap();
+        }
+        for &color in colors {
+            write_sprite_frame(l, origin, width, height, &|_, _| color);
+        }
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut bytes = b"IDSP".to_vec();
+        bytes.write_i32::<LittleEndian>(1).unwrap();
+        bytes.write_i32::<LittleEndian>(self.kind).unwrap();
+        bytes.write_f32::<LittleEndian>(16.0).unwrap();
+        bytes.write_i32::<LittleEndian>(self.size.0).unwrap();
+        bytes.write_i32::<LittleEndian>(self.size.1).unwrap();
+        bytes.write_i32::<LittleEndian>(self.num_frames).unwrap();
+        bytes.write_f32::<LittleEndian>(0.0).unwrap();
+        bytes.write_i32::<LittleEndian>(0).unwrap();
+        bytes.extend_from_slice(&self.frames);
+        bytes
+    }
+}
+
+/// Writes the origin, size and pixels of a sprite frame.
+fn write_sprite_frame(l: &mut Vec<u8>,
+                      origin: [i32; 2],
+                      width: i32,
+                      height: i32,
+                      texel: &Fn(i32, i32) -> u8) {
+    for &v in &[origin[0], origin[1], width, height] {
+        l.write_i32::<LittleEndian>(v).unwrap();
+    }
+    for y in 0..height {
+        for x in 0..width {
+            l.push(texel(x, y));
+        }
+    }
+}
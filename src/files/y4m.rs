@@ -0,0 +1,106 @@
+//! Writer for uncompressed YUV4MPEG2 video streams, which most video tools
+//! (e.g. ffmpeg, mpv) can read directly.
+
+use std::io;
+use std::io::prelude::*;
+
+/// Writes frames with 4:2:0 chroma subsampling and full range BT.601
+/// colors ("C420jpeg").
+pub struct Y4mWriter<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+    // Plane buffers, reused for every frame
+    y: Vec<u8>,
+    u: Vec<u8>,
+    v: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Writes the stream header. `fps` is the frame rate of the video.
+    pub fn new(mut out: W, width: usize, height: usize, fps: u32) -> io::Result<Y4mWriter<W>> {
+        try!(write!(out,
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg\n",
+                    width,
+                    height,
+                    fps));
+        let chroma = (width + 1) / 2 * ((height + 1) / 2);
+        Ok(Y4mWriter {
+            out: out,
+            width: width,
+            height: height,
+            y: vec![0; width * height],
+            u: vec![0; chroma],
+            v: vec![0; chroma],
+        })
+    }
+
+    /// Appends a frame given as 4 bytes per pixel in the order blue, green,
+    /// red, unused (the layout of `Framebuffer::color_buffer`).
+    pub fn write_bgrx(&mut self, bgrx: &[u8]) -> io::Result<()> {
+        assert_eq!(bgrx.len(), self.width * self.height * 4);
+        let (w, h) = (self.width, self.height);
+        let cw = (w + 1) / 2;
+        // Chroma is averaged over up to 4 pixels, accumulate the sums first
+        let mut u_sum = vec![0.0f32; self.u.len()];
+        let mut v_sum = vec![0.0f32; self.v.len()];
+        let mut count = vec![0.0f32; self.u.len()];
+
+        for y in 0..h {
+            for x in 0..w {
+                let px = &bgrx[(y * w + x) * 4..];
+                let (b, g, r) = (px[0] as f32, px[1] as f32, px[2] as f32);
+                let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+                self.y[y * w + x] = clamp(luma);
+                let ci = (y / 2) * cw + x / 2;
+                u_sum[ci] += 128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b;
+                v_sum[ci] += 128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+                count[ci] += 1.0;
+            }
+        }
+        for i in 0..count.len() {
+            self.u[i] = clamp(u_sum[i] / count[i]);
+            self.v[i] = clamp(v_sum[i] / count[i]);
+        }
+
+        try!(self.out.write_all(b"FRAME\n"));
+        try!(self.out.write_all(&self.y));
+        try!(self.out.write_all(&self.u));
+        self.out.write_all(&self.v)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[inline]
+fn clamp(v: f32) -> u8 {
+    v.round().clamp(0.0, 255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Y4mWriter;
+
+    #[test]
+    fn writes_header_and_planes() {
+        let mut out = vec![];
+        {
+            let mut writer = Y4mWriter::new(&mut out, 3, 1, 30).unwrap();
+            // white, black, pure red
+            writer.write_bgrx(&[255, 255, 255, 0, 0, 0, 0, 0, 0, 0, 255, 0]).unwrap();
+        }
+        let header = b"YUV4MPEG2 W3 H1 F30:1 Ip A1:1 C420jpeg\nFRAME\n";
+        assert_eq!(&out[..header.len()], &header[..]);
+        let planes = &out[header.len()..];
+        // 3 luma samples and 2 samples for each chroma plane
+        assert_eq!(planes.len(), 3 + 2 + 2);
+        assert_eq!(&planes[..3], &[255, 0, 76]);
+        // white and black average to neutral chroma
+        assert_eq!(planes[3], 128);
+        assert_eq!(planes[5], 128);
+        // red has a high V (Cr) value
+        assert_eq!(planes[6], 255);
+    }
+}
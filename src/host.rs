@@ -1,14 +1,13 @@
-use sdl2;
-use sdl2::video::Window;
-use sdl2::event::Event;
-use sdl2::EventPump;
-use sdl2::keyboard::Keycode;
-
-use util::{Timer, Options, DurationExt};
-use drawing::Framebuffer;
+use util::{Timer, Options, DurationExt, Vec3};
+use drawing::{Colormap, Framebuffer, Palette};
 use files::*;
+use files::bsp::BspResult;
+use platform::{HostEvent, Key, Platform, HeadlessPlatform, SdlPlatform};
+use capture::{Capture, DEFAULT_CAPTURE_FPS};
+use render::{Camera, DrawMode, Renderer, World};
+use render::cache::cache_size_for;
 
-use std::{io, ptr};
+use std::io;
 use std::io::prelude::*;
 
 use hprof;
@@ -16,15 +15,24 @@ use hprof;
 const DEFAULT_WIDTH: u32 = 800;
 const DEFAULT_HEIGHT: u32 = 600;
 
+/// A map loaded with `-map`, viewed from its player start.
+struct Level {
+    world: World,
+    renderer: Renderer,
+    camera: Camera,
+}
+
 pub struct Host {
-    window: Window,
-    event_pump: EventPump,
+    platform: Box<Platform>,
     timer: Timer,
     framebuffer: Framebuffer,
     options: Options,
     debug: bool,
     paks: PackContainer,
     image_bytes: Vec<u8>,
+    frame_count: u64,
+    capture: Option<Capture>,
+    level: Option<Level>,
 }
 
 impl Default for Host {
@@ -34,33 +42,132 @@ impl Default for Host {
 }
 
 impl Host {
+    /// Creates a host from the command line options. Opens a window, unless
+    /// `-headless` is given, in which case the host runs for `-frames N`
+    /// frames (or forever) without any display.
     pub fn new() -> Host {
         let options = Options::new();
-        let context = sdl2::init().unwrap();
-        let video = context.video().unwrap();
         let width = options.check_param("-width").unwrap_or(DEFAULT_WIDTH);
         let height = options.check_param("-height").unwrap_or(DEFAULT_HEIGHT);
-        let window_builder = video.window("rsquake", width, height);
-        let window = window_builder.build().unwrap();
-        let debug = options.is_set("-debug");
-        // Unlock the framerate in debug mode
-        let timer = Timer::new(debug);
+        let platform: Box<Platform> = if options.is_set("-headless") {
+            Box::new(HeadlessPlatform::new(options.check_param("-frames")))
+        } else {
+            Box::new(SdlPlatform::new("rsquake", width, height).unwrap())
+        };
         let mut paks = PackContainer::new();
         paks.add_game_directory("Id1").unwrap();
-        let image = paks.read("gfx/pause.lmp").unwrap();
 
-        Host {
-            window: window,
-            event_pump: context.event_pump().unwrap(),
+        Host::with_platform(platform, options, paks).unwrap()
+    }
+
+    /// Creates a host that runs on the given platform and loads its data
+    /// from `paks`.
+    ///
+    /// With `-capture <dir>` every frame is saved as a PNG image, with
+    /// `-capturevideo <file>` to a Y4M video. Time then advances by
+    /// `1 / -capturefps` seconds (default 30, also used for 0) per frame.
+    ///
+    /// With `-map <name>`, `maps/<name>.bsp` is drawn from its player start
+    /// instead of the pause image. `-noedges` draws it with plain z-buffered
+    /// polygons instead of edge-sorted spans. `-surfcachesize <KB>` overrides
+    /// the size of the surface cache, which otherwise grows with the
+    /// resolution. `-wateralpha <alpha>` makes liquids translucent.
+    pub fn with_platform(platform: Box<Platform>,
+                         options: Options,
+                         mut paks: PackContainer)
+                         -> PackResult<Host> {
+        let width = options.check_param("-width").unwrap_or(DEFAULT_WIDTH);
+        let height = options.check_param("-height").unwrap_or(DEFAULT_HEIGHT);
+        let debug = options.is_set("-debug");
+        let capture = try!(Host::open_capture(&options, width as usize, height as usize));
+        let timer = match capture {
+            Some(ref capture) => Timer::fixed(capture.frame_duration()),
+            // Unlock the framerate in debug mode, and when nobody's watching
+            None => Timer::new(debug || !platform.is_realtime()),
+        };
+        let palette = try!(Palette::new(&mut paks));
+        let image = try!(paks.read("gfx/pause.lmp"));
+        let level = Host::open_level(&options, &mut paks);
+
+        Ok(Host {
+            platform: platform,
             timer: timer,
-            framebuffer: Framebuffer::new(width as usize, height as usize, &mut paks),
+            framebuffer: Framebuffer::with_palette(width as usize, height as usize, palette),
             options: options,
             debug: debug,
             paks: paks,
             image_bytes: image,
+            frame_count: 0,
+            capture: capture,
+            level: level,
+        })
+    }
+
+    /// Loads the map given with `-map`. Errors are reported and the host
+    /// runs without a map, like the original's `map` command does.
+    fn open_level(options: &Options, paks: &mut PackContainer) -> Option<Level> {
+        options.check_param::<String>("-map").and_then(|name| {
+            match Host::load_level(paks, &name) {
+                Ok(mut level) => {
+                    if options.is_set("-noedges") {
+                        level.renderer.set_mode(DrawMode::Polygons);
+                    }
+                    // Printed with the timings
+                    level.renderer.set_profile_stats(options.is_set("-debug"));
+                    if let Some(alpha) = options.check_param("-wateralpha") {
+                        level.renderer.set_water_alpha(alpha);
+                    }
+                    let width = options.check_param("-width").unwrap_or(DEFAULT_WIDTH);
+                    let height = options.check_param("-height").unwrap_or(DEFAULT_HEIGHT);
+                    let cache_size = match options.check_param::<usize>("-surfcachesize") {
+                        Some(kilobytes) => kilobytes * 1024,
+                        None => cache_size_for(width as usize, height as usize),
+                    };
+                    level.renderer.set_surface_cache_size(cache_size);
+                    Some(level)
+                }
+                Err(err) => {
+                    println!("Couldn't load map {}: {:?}", name, err);
+                    None
+                }
+            }
+        })
+    }
+
+    fn load_level(paks: &mut PackContainer, name: &str) -> BspResult<Level> {
+        let world = try!(World::load(paks, name));
+        let colormap = try!(Colormap::new(paks));
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        let (origin, angles) = world.player_start().unwrap_or((zero, zero));
+        Ok(Level {
+            world: world,
+            renderer: Renderer::new(colormap),
+            camera: Camera::new(origin, angles),
+        })
+    }
+
+    fn open_capture(options: &Options, width: usize, height: usize) -> io::Result<Option<Capture>> {
+        let fps = options.check_param("-capturefps")
+                         .and_then(|fps| if fps > 0 { Some(fps) } else { None })
+                         .unwrap_or(DEFAULT_CAPTURE_FPS);
+        if let Some(dir) = options.check_param::<String>("-capture") {
+            Capture::png_sequence(dir, fps).map(Some)
+        } else if let Some(path) = options.check_param::<String>("-capturevideo") {
+            Capture::y4m(path, width, height, fps).map(Some)
+        } else {
+            Ok(None)
         }
     }
 
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    /// Number of frames drawn so far.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
     fn frame(&mut self, stdout: &mut io::StdoutLock) {
         if let Some(timestep) = self.timer.step() {
             hprof::start_frame();
@@ -71,8 +178,10 @@ impl Host {
 
             self.draw();
             self.swap_buffers();
+            self.frame_count += 1;
             hprof::end_frame();
             if self.debug {
+                // Includes the counts of the renderer, see `RenderStats::profile`
                 hprof::profiler().print_timing();
             }
         }
@@ -81,38 +190,31 @@ impl Host {
     fn draw(&mut self) {
         hprof::enter("Host::draw()");
         self.framebuffer.fill(0);
-        let img = LmpImage::from_bytes(&self.image_bytes).unwrap();
-        self.framebuffer.draw_pic(0, 0, &img);
-    }
-
-    #[cfg(feature="nightly")]
-    fn swap_buffers(&mut self) {
-        hprof::enter("Host::swap_buffers()");
-        self.framebuffer.swap_buffers();
-        {
-            let mut surface = self.window.surface_mut(&self.event_pump).unwrap();
-            let mut pixels = surface.without_lock_mut().unwrap();
-            let bytes = self.framebuffer.color_buffer();
-            pixels.copy_from_slice(bytes);
+        match self.level {
+            Some(ref mut level) => {
+                let time = self.timer.elapsed().seconds() as f32;
+                level.renderer.render(&level.world, &level.camera, &mut self.framebuffer, time);
+            }
+            None => {
+                let img = LmpImage::from_bytes(&self.image_bytes).unwrap();
+                self.framebuffer.draw_pic(0, 0, &img);
+            }
         }
-        self.window.update_surface().unwrap();
     }
 
-    #[cfg(not(feature="nightly"))]
     fn swap_buffers(&mut self) {
         hprof::enter("Host::swap_buffers()");
         self.framebuffer.swap_buffers();
-        {
-            let mut surface = self.window.surface_mut(&self.event_pump).unwrap();
-            let mut pixels = surface.without_lock_mut().unwrap();
-            let bytes = self.framebuffer.color_buffer();
-            let src = bytes.as_ptr();
-            let dest = pixels.as_mut_ptr();
-            unsafe {
-                ptr::copy_nonoverlapping(src, dest, bytes.len());
-            }
+        self.platform.present(&self.framebuffer);
+
+        let failed = match self.capture {
+            Some(ref mut capture) => capture.write_frame(&self.framebuffer).err(),
+            None => None,
+        };
+        if let Some(err) = failed {
+            println!("Stopping capture: {}", err);
+            self.capture = None;
         }
-        self.window.update_surface().unwrap();
     }
 
     pub fn run(&mut self) {
@@ -123,10 +225,10 @@ impl Host {
         }
         'main: loop {
             let h = hprof::enter("Event loop");
-            for event in self.event_pump.poll_iter() {
+            for event in self.platform.poll_events() {
                 match event {
-                    Event::Quit { .. } |
-                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    HostEvent::Quit |
+                    HostEvent::KeyDown(Key::Escape) => {
                         break 'main;
                     }
                     _ => {}
@@ -135,8 +237,136 @@ impl Host {
             drop(h);
             self.frame(&mut lock);
         }
+        if let Some(ref mut capture) = self.capture {
+            if let Err(err) = capture.finish() {
+                println!("Couldn't finish capture: {}", err);
+            }
+        }
         if self.debug {
             println!("");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+    use files::PackContainer;
+    use files::testing::{floor_bsp, temp_path, write_pak};
+    use platform::{HeadlessPlatform, HostEvent, Key};
+    use util::Options;
+    use super::Host;
+
+    fn test_paks(name: &str) -> PackContainer {
+        let palette: Vec<u8> = (0..256).flat_map(|i| vec![i as u8; 3]).collect();
+        let pause = [2, 0, 0, 0, 1, 0, 0, 0, 7, 9];
+        let path = write_pak(name,
+                             &[("gfx/palette.lmp", &palette), ("gfx/pause.lmp", &pause)]);
+        let mut paks = PackContainer::new();
+        paks.read_pack(path).unwrap();
+        paks
+    }
+
+    fn options_with(extra: &[&str]) -> Options {
+        let args = ["-width", "16", "-height", "8"].iter().chain(extra);
+        Options::with_args(args.map(|&a| a.into()).collect())
+    }
+
+    fn options() -> Options {
+        options_with(&[])
+    }
+
+    #[test]
+    fn runs_headless_for_n_frames() {
+        let platform = HeadlessPlatform::new(Some(5));
+        let mut host = Host::with_platform(Box::new(platform), options(), test_paks("host_n"))
+                           .unwrap();
+        host.run();
+        assert_eq!(host.frame_count(), 5);
+        let fb = host.framebuffer();
+        assert_eq!((fb.width(), fb.height()), (16, 8));
+        assert_eq!(&fb.pixels()[..3], &[7, 9, 0]);
+        assert_eq!(&fb.color_buffer()[4..8], &[9, 9, 9, 0]);
+    }
+
+    #[test]
+    fn quits_on_scripted_escape() {
+        let mut platform = HeadlessPlatform::new(None);
+        platform.push_event(2, HostEvent::KeyDown(Key::Escape));
+        let mut host = Host::with_platform(Box::new(platform), options(), test_paks("host_esc"))
+                           .unwrap();
+        host.run();
+        assert_eq!(host.frame_count(), 2);
+    }
+
+    #[test]
+    fn captures_at_a_fixed_rate() {
+        let dir = temp_path("host-capture");
+        let options = Options::with_args(vec!["-width".into(),
+                                              "16".into(),
+                                              "-height".into(),
+                                              "8".into(),
+                                              "-capture".into(),
+                                              dir.to_str().unwrap().into(),
+                                              "-capturefps".into(),
+                                              "10".into()]);
+        let platform = HeadlessPlatform::new(Some(3));
+        let mut host = Host::with_platform(Box::new(platform), options, test_paks("host_cap"))
+                           .unwrap();
+        host.run();
+        assert_eq!(host.timer.elapsed(), Duration::from_millis(300));
+        assert!(dir.join("frame00002.png").exists());
+        assert!(!dir.join("frame00003.png").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn zero_capture_fps_uses_the_default() {
+        let dir = temp_path("host-capture-zero");
+        let options = Options::with_args(vec!["-width".into(),
+                                              "16".into(),
+                                              "-height".into(),
+                                              "8".into(),
+                                              "-capture".into(),
+                                              dir.to_str().unwrap().into(),
+                                              "-capturefps".into(),
+                                              "0".into()]);
+        let platform = HeadlessPlatform::new(Some(3));
+        let mut host = Host::with_platform(Box::new(platform), options, test_paks("host_cap0"))
+                           .unwrap();
+        host.run();
+        assert_eq!(host.timer.elapsed(), Duration::from_nanos(3 * 33_333_333));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn renders_a_map() {
+        let palette = [0; 768];
+        let pause = [1, 0, 0, 0, 1, 0, 0, 0, 9];
+        let colormap: Vec<u8> = (0..64 * 256).map(|i| (i % 256 + i / 256) as u8).collect();
+        let ent = b"{ \"classname\" \"worldspawn\" }\n\
+                    { \"classname\" \"info_player_start\" \"origin\" \"0 0 0\" \
+                    \"angles\" \"90 0 0\" }";
+        let path = write_pak("host_map",
+                             &[("gfx/palette.lmp", &palette),
+                               ("gfx/pause.lmp", &pause),
+                               ("gfx/colormap.lmp", &colormap),
+                               ("maps/floor.bsp", &floor_bsp().build()),
+                               ("maps/floor.ent", ent)]);
+
+        for extra in &[&["-map", "floor"][..],
+                       &["-map", "floor", "-noedges"][..],
+                       // Too small for the floor, which is then lit evenly
+                       &["-map", "floor", "-surfcachesize", "0"][..]] {
+            let mut paks = PackContainer::new();
+            paks.read_pack(&path).unwrap();
+            let platform = HeadlessPlatform::new(Some(1));
+            let mut host = Host::with_platform(Box::new(platform), options_with(extra), paks)
+                               .unwrap();
+            host.run();
+            // Floor texture color 7 at light level 12
+            assert!(host.framebuffer().pixels().iter().all(|&p| p == 19));
+        }
+    }
+}
@@ -10,13 +10,36 @@ extern crate hprof;
 #[cfg(feature="nightly")]
 extern crate test;
 
+use std::process;
+
+use files::PackContainer;
+use files::obj;
 use host::Host;
+use util::Options;
 
 mod drawing;
 mod files;
 mod util;
+mod capture;
 mod host;
+mod platform;
+mod render;
 
 fn main() {
-    Host::new().run();
+    let options = Options::new();
+    match options.check_params::<String>("-exportobj", 2) {
+        Some(args) => export_obj(&args[0], &args[1]),
+        None => Host::new().run(),
+    }
+}
+
+/// Exports a map or model with `-exportobj <file> <dir>`, without starting
+/// the engine. See `files::obj::export_file`.
+fn export_obj(file: &str, dir: &str) {
+    let mut paks = PackContainer::new();
+    paks.add_game_directory("Id1").unwrap();
+    if let Err(err) = obj::export_file(&mut paks, file, dir) {
+        println!("Couldn't export {}: {:?}", file, err);
+        process::exit(1);
+    }
 }
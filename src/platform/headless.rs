@@ -0,0 +1,83 @@
+use drawing::Framebuffer;
+use platform::{HostEvent, Platform};
+
+/// A platform without a window. Frames are only rendered into the
+/// framebuffer, and input comes from a script of events, each delivered
+/// before a given frame. Used for integration tests and dedicated servers.
+#[derive(Debug, Default)]
+pub struct HeadlessPlatform {
+    /// Frame number and event, sorted by frame.
+    script: Vec<(usize, HostEvent)>,
+    /// Quit after this many frames were presented.
+    max_frames: Option<usize>,
+    frames: usize,
+}
+
+impl HeadlessPlatform {
+    /// Creates a headless platform that asks the host to quit after
+    /// `max_frames` frames, or runs until a scripted `Quit` if it's `None`.
+    pub fn new(max_frames: Option<usize>) -> HeadlessPlatform {
+        HeadlessPlatform {
+            script: vec![],
+            max_frames: max_frames,
+            frames: 0,
+        }
+    }
+
+    /// Schedules `event` to be delivered before frame number `frame`
+    /// (counting from 0) is drawn.
+    pub fn push_event(&mut self, frame: usize, event: HostEvent) {
+        let idx = self.script.iter().position(|&(f, _)| f > frame).unwrap_or(self.script.len());
+        self.script.insert(idx, (frame, event));
+    }
+
+    /// Number of frames presented so far.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+}
+
+impl Platform for HeadlessPlatform {
+    fn poll_events(&mut self) -> Vec<HostEvent> {
+        let due = self.script.iter().take_while(|&&(f, _)| f <= self.frames).count();
+        let mut events: Vec<HostEvent> = self.script.drain(..due).map(|(_, e)| e).collect();
+        if self.max_frames.map_or(false, |max| self.frames >= max) {
+            events.push(HostEvent::Quit);
+        }
+        events
+    }
+
+    fn present(&mut self, _framebuffer: &Framebuffer) {
+        self.frames += 1;
+    }
+
+    fn is_realtime(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drawing::{Framebuffer, Palette};
+    use platform::{HostEvent, Key, Platform};
+    use super::HeadlessPlatform;
+
+    #[test]
+    fn scripted_events_and_frame_limit() {
+        let fb = Framebuffer::with_palette(1, 1, Palette::from_bytes(&[0; 768]));
+        let mut platform = HeadlessPlatform::new(Some(3));
+        platform.push_event(1, HostEvent::KeyUp(Key::Char('a')));
+        platform.push_event(1, HostEvent::KeyDown(Key::Char('b')));
+        platform.push_event(0, HostEvent::KeyDown(Key::Char('a')));
+
+        assert_eq!(platform.poll_events(), vec![HostEvent::KeyDown(Key::Char('a'))]);
+        platform.present(&fb);
+        assert_eq!(platform.poll_events(),
+                   vec![HostEvent::KeyUp(Key::Char('a')), HostEvent::KeyDown(Key::Char('b'))]);
+        platform.present(&fb);
+        assert_eq!(platform.poll_events(), vec![]);
+        platform.present(&fb);
+        assert_eq!(platform.poll_events(), vec![HostEvent::Quit]);
+        assert_eq!(platform.frames(), 3);
+    }
+}
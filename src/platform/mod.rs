@@ -0,0 +1,65 @@
+//! Abstraction over the windowing system. The host only talks to a
+//! `Platform` to get input events and show finished frames, so it can run
+//! with a real window (`SdlPlatform`) or without any display at all
+//! (`HeadlessPlatform`).
+
+pub mod headless;
+pub mod sdl;
+
+pub use self::headless::HeadlessPlatform;
+pub use self::sdl::SdlPlatform;
+
+use drawing::Framebuffer;
+
+/// A key, like the `K_*` key numbers of the original. Backends translate
+/// their own key codes to these.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// A key that types a printable ASCII character other than space, as it
+    /// is typed without shift (`'a'`, not `'A'`).
+    Char(char),
+    Space,
+    Tab,
+    Enter,
+    Escape,
+    Backspace,
+    Up,
+    Down,
+    Left,
+    Right,
+    Alt,
+    Ctrl,
+    Shift,
+    /// Function keys `F(1)` to `F(12)`.
+    F(u8),
+    Insert,
+    Delete,
+    PageDown,
+    PageUp,
+    Home,
+    End,
+    Pause,
+}
+
+/// Input events the host reacts to, independent of the backend.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HostEvent {
+    Quit,
+    KeyDown(Key),
+    KeyUp(Key),
+}
+
+pub trait Platform {
+    /// Returns all events that happened since the last call.
+    fn poll_events(&mut self) -> Vec<HostEvent>;
+
+    /// Shows the framebuffer's color buffer. Called once per frame, after
+    /// `Framebuffer::swap_buffers`.
+    fn present(&mut self, framebuffer: &Framebuffer);
+
+    /// Whether frames are shown to a user in real time. If not, the host
+    /// doesn't limit the framerate.
+    fn is_realtime(&self) -> bool {
+        true
+    }
+}
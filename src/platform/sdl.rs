@@ -0,0 +1,138 @@
+use sdl2;
+use sdl2::{EventPump, Sdl};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Renderer, Texture};
+
+use drawing::Framebuffer;
+use platform::{HostEvent, Key, Platform};
+
+/// SDL pixel format with the same memory layout as
+/// `Framebuffer::color_buffer` (blue, green, red, unused).
+#[cfg(target_endian = "little")]
+const COLOR_BUFFER_FORMAT: PixelFormatEnum = PixelFormatEnum::RGB888;
+#[cfg(target_endian = "big")]
+const COLOR_BUFFER_FORMAT: PixelFormatEnum = PixelFormatEnum::BGRX8888;
+
+/// Window, input and presentation through SDL2.
+pub struct SdlPlatform {
+    // Keeps SDL initialized for as long as the platform exists.
+    _context: Sdl,
+    event_pump: EventPump,
+    renderer: Renderer<'static>,
+    /// Streaming texture the color buffer is uploaded to every frame. SDL
+    /// converts it to whatever the window actually uses when presenting.
+    screen: Texture,
+}
+
+impl SdlPlatform {
+    pub fn new(title: &str, width: u32, height: u32) -> Result<SdlPlatform, String> {
+        let context = try!(sdl2::init());
+        let video = try!(context.video());
+        let window = try!(video.window(title, width, height).build().map_err(|e| e.to_string()));
+        let renderer = try!(window.renderer().build().map_err(|e| e.to_string()));
+        let screen = try!(renderer.create_texture_streaming(COLOR_BUFFER_FORMAT, width, height)
+                                  .map_err(|e| format!("{:?}", e)));
+        let event_pump = try!(context.event_pump());
+
+        Ok(SdlPlatform {
+            _context: context,
+            event_pump: event_pump,
+            renderer: renderer,
+            screen: screen,
+        })
+    }
+}
+
+impl Platform for SdlPlatform {
+    fn poll_events(&mut self) -> Vec<HostEvent> {
+        let mut events = vec![];
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => events.push(HostEvent::Quit),
+                Event::KeyDown { keycode: Some(keycode), .. } => {
+                    events.extend(translate_key(keycode).map(HostEvent::KeyDown))
+                }
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    events.extend(translate_key(keycode).map(HostEvent::KeyUp))
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+
+    fn present(&mut self, framebuffer: &Framebuffer) {
+        let pitch = framebuffer.width() * 4;
+        self.screen.update(None, framebuffer.color_buffer(), pitch).unwrap();
+        self.renderer.clear();
+        self.renderer.copy(&self.screen, None, None);
+        self.renderer.present();
+    }
+}
+
+/// The engine's key for an SDL key code, if it has one.
+fn translate_key(keycode: Keycode) -> Option<Key> {
+    let key = match keycode {
+        Keycode::Space => Key::Space,
+        Keycode::Tab => Key::Tab,
+        Keycode::Return | Keycode::KpEnter => Key::Enter,
+        Keycode::Escape => Key::Escape,
+        Keycode::Backspace => Key::Backspace,
+        Keycode::Up => Key::Up,
+        Keycode::Down => Key::Down,
+        Keycode::Left => Key::Left,
+        Keycode::Right => Key::Right,
+        Keycode::LAlt | Keycode::RAlt => Key::Alt,
+        Keycode::LCtrl | Keycode::RCtrl => Key::Ctrl,
+        Keycode::LShift | Keycode::RShift => Key::Shift,
+        Keycode::F1 => Key::F(1),
+        Keycode::F2 => Key::F(2),
+        Keycode::F3 => Key::F(3),
+        Keycode::F4 => Key::F(4),
+        Keycode::F5 => Key::F(5),
+        Keycode::F6 => Key::F(6),
+        Keycode::F7 => Key::F(7),
+        Keycode::F8 => Key::F(8),
+        Keycode::F9 => Key::F(9),
+        Keycode::F10 => Key::F(10),
+        Keycode::F11 => Key::F(11),
+        Keycode::F12 => Key::F(12),
+        Keycode::Insert => Key::Insert,
+        Keycode::Delete => Key::Delete,
+        Keycode::PageDown => Key::PageDown,
+        Keycode::PageUp => Key::PageUp,
+        Keycode::Home => Key::Home,
+        Keycode::End => Key::End,
+        Keycode::Pause => Key::Pause,
+        // The key codes of the other printable keys are their characters
+        _ => {
+            let code = keycode as i32;
+            if code > 32 && code < 127 {
+                Key::Char(code as u8 as char)
+            } else {
+                return None;
+            }
+        }
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::keyboard::Keycode;
+    use platform::Key;
+    use super::translate_key;
+
+    #[test]
+    fn translates_keys() {
+        assert_eq!(translate_key(Keycode::Escape), Some(Key::Escape));
+        assert_eq!(translate_key(Keycode::RShift), Some(Key::Shift));
+        assert_eq!(translate_key(Keycode::F10), Some(Key::F(10)));
+        assert_eq!(translate_key(Keycode::A), Some(Key::Char('a')));
+        assert_eq!(translate_key(Keycode::Num7), Some(Key::Char('7')));
+        assert_eq!(translate_key(Keycode::Slash), Some(Key::Char('/')));
+        assert_eq!(translate_key(Keycode::Kp7), None);
+    }
+}
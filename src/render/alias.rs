@@ -0,0 +1,428 @@
+//! Drawing of alias models, like `r_alias.c` and `d_polyse.c`. Triangles are
+//! drawn with affine mapped skins, Gouraud shaded from the precomputed vertex
+//! normals and depth tested against the world.
+
+use drawing::{Colormap, Framebuffer, Shading, Translation, Vertex};
+use files::PackContainer;
+use files::mdl::{Mdl, MdlResult, VERTEX_NORMALS};
+use render::View;
+use render::camera::NEAR_CLIP;
+use util::Vec3;
+use util::vector::angle_vectors;
+use hprof;
+
+/// Ambient light is at least this, so models in dark corners stay visible.
+const LIGHT_MIN: i32 = 5;
+/// Ambient and directed light together are limited to this.
+const LIGHT_MAX: i32 = 192;
+/// Direction the directed light shines in, in world space. It lights the
+/// sides of models that face the other way.
+const LIGHT_DIRECTION: [f32; 3] = [-1.0, 0.0, 0.0];
+/// Light values are scaled by the number of light levels, like `VID_GRADES`.
+const LIGHT_GRADES: i32 = 64;
+
+/// The light falling on a model, like `alight_t`. Both parts are on the
+/// scale of the lightmaps, from 0 (dark) to 255.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AliasLight {
+    /// Light on every vertex.
+    pub ambient: i32,
+    /// Light added to vertices facing the light direction.
+    pub shade: i32,
+}
+
+impl AliasLight {
+    /// The light of a model at a point with lightmap value `light`, which
+    /// the original uses for both parts.
+    pub fn at(light: i32) -> AliasLight {
+        AliasLight {
+            ambient: light,
+            shade: light,
+        }
+    }
+
+    /// The light level (colormap row) of a vertex whose normal has the
+    /// cosine `cos` with the light direction, like
+    /// `R_AliasTransformFinalVert`.
+    fn level(&self, cos: f32) -> f32 {
+        let ambient = self.ambient.max(LIGHT_MIN);
+        let shade = self.shade.min(LIGHT_MAX - ambient).max(0) * LIGHT_GRADES;
+        let mut light = ((255 - ambient) * LIGHT_GRADES).max(LIGHT_MIN) as f32;
+        if cos < 0.0 {
+            light = (light + shade as f32 * cos).max(0.0);
+        }
+        light / 256.0
+    }
+}
+
+/// Where and how an alias model is drawn.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AliasPose {
+    pub origin: Vec3,
+    /// Pitch, yaw and roll in degrees.
+    pub angles: Vec3,
+    pub frame: usize,
+    /// The frame the vertices are blended from, and how far: 0 shows
+    /// `previous_frame`, 1 shows `frame`.
+    pub previous_frame: usize,
+    pub blend: f32,
+    pub skin: usize,
+    pub light: AliasLight,
+    /// Player colors the skin is recolored with.
+    pub translation: Option<Translation>,
+}
+
+impl AliasPose {
+    /// A pose showing `frame` without blending.
+    pub fn new(origin: Vec3,
+               angles: Vec3,
+               frame: usize,
+               skin: usize,
+               light: AliasLight)
+               -> AliasPose {
+        AliasPose {
+            origin: origin,
+            angles: angles,
+            frame: frame,
+            previous_frame: frame,
+            blend: 1.0,
+            skin: skin,
+            light: light,
+            translation: None,
+        }
+    }
+}
+
+/// A transformed vertex, in view space.
+#[derive(Debug, Copy, Clone)]
+struct AliasVertex {
+    pos: Vec3,
+    s: f32,
+    t: f32,
+    light: f32,
+}
+
+impl AliasVertex {
+    fn lerp(&self, other: &AliasVertex, f: f32) -> AliasVertex {
+        AliasVertex {
+            pos: self.pos.lerp(other.pos, f),
+            s: self.s + (other.s - self.s) * f,
+            t: self.t + (other.t - self.t) * f,
+            light: self.light + (other.light - self.light) * f,
+        }
+    }
+}
+
+/// A loaded alias model.
+pub struct AliasModel {
+    pub mdl: Mdl,
+}
+
+impl AliasModel {
+    pub fn new(mdl: Mdl) -> AliasModel {
+        AliasModel { mdl: mdl }
+    }
+
+    /// Loads a model like `progs/player.mdl`.
+    pub fn load(paks: &mut PackContainer, name: &str) -> MdlResult<AliasModel> {
+        Mdl::load(paks, name).map(AliasModel::new)
+    }
+
+    /// Draws the model as seen from `view`, `time` seconds into the game
+    /// (for frame and skin groups). Models outside of the view volume are
+    /// skipped.
+    pub fn draw(&self,
+                framebuffer: &mut Framebuffer,
+                view: &View,
+                colormap: &Colormap,
+                pose: &AliasPose,
+                time: f32) {
+        let _guard = hprof::enter("AliasModel::draw");
+        let mdl = &self.mdl;
+        if !view.sphere_visible(pose.origin, mdl.radius) {
+            return;
+        }
+
+        // Alias models are pitched the wrong way round in the original
+        let angles = Vec3::new(-pose.angles.x, pose.angles.y, pose.angles.z);
+        let (forward, right, up) = angle_vectors(angles);
+        let direction = Vec3::new(LIGHT_DIRECTION[0], LIGHT_DIRECTION[1], LIGHT_DIRECTION[2]);
+        // The light direction in model space, where y points left
+        let light = Vec3::new(direction.dot(forward), -direction.dot(right), direction.dot(up));
+
+        let frame = mdl.frame(pose.frame, time);
+        let previous = mdl.frame(pose.previous_frame, time);
+        let blend = pose.blend.clamp(0.0, 1.0);
+        let vertices: Vec<(Vec3, f32)> =
+            previous.vertices
+                    .iter()
+                    .zip(&frame.vertices)
+                    .map(|(v0, v1)| {
+                        let p = v0.position + (v1.position - v0.position) * blend;
+                        let world = pose.origin + forward * p.x - right * p.y + up * p.z;
+                        // Normals can't be blended, so the nearer frame's is used
+                        let v = if blend < 0.5 { v0 } else { v1 };
+                        let n = VERTEX_NORMALS[v.normal];
+                        let cos = n[0] * light.x + n[1] * light.y + n[2] * light.z;
+                        (view.transform(world), pose.light.level(cos))
+                    })
+                    .collect();
+
+        let translated;
+        let mut skin = mdl.skin(pose.skin, time);
+        if let Some(ref translation) = pose.translation {
+            translated = translation.texture(skin);
+            skin = &translated;
+        }
+        let shading = Shading::Gouraud(skin, colormap);
+        let seam = (mdl.skin_width / 2) as f32;
+        for triangle in &mdl.triangles {
+            let polygon: Vec<AliasVertex> =
+                triangle.vertices
+                        .iter()
+                        .map(|&i| {
+                            let st = &mdl.st_verts[i];
+                            // Back facing triangles use the back half of the skin
+                            let s = if st.on_seam && !triangle.faces_front {
+                                st.s as f32 + seam
+                            } else {
+                                st.s as f32
+                            };
+                            AliasVertex {
+                                pos: vertices[i].0,
+                                s: s,
+                                t: st.t as f32,
+                                light: vertices[i].1,
+                            }
+                        })
+                        .collect();
+            let screen: Vec<Vertex> = clip_near(&polygon)
+                                          .iter()
+                                          .map(|v| {
+                                              let (x, y) = view.project(v.pos);
+                                              Vertex::new(x, y, v.pos.z, v.s, v.t)
+                                                  .with_light(v.light)
+                                          })
+                                          .collect();
+            if is_front_facing(&screen) {
+                framebuffer.polygon(&screen, shading);
+            }
+        }
+    }
+}
+
+/// Clips a polygon in view space to the near plane.
+fn clip_near(polygon: &[AliasVertex]) -> Vec<AliasVertex> {
+    let mut out = Vec::with_capacity(polygon.len() + 1);
+    for (i, a) in polygon.iter().enumerate() {
+        let b = &polygon[(i + 1) % polygon.len()];
+        let da = a.pos.z - NEAR_CLIP;
+        let db = b.pos.z - NEAR_CLIP;
+        if da >= 0.0 {
+            out.push(*a);
+        }
+        if (da >= 0.0) != (db >= 0.0) {
+            out.push(a.lerp(b, da / (da - db)));
+        }
+    }
+    out
+}
+
+/// Whether a projected polygon is wound clockwise on the screen, which the
+/// triangles of alias models are when seen from the front.
+fn is_front_facing(polygon: &[Vertex]) -> bool {
+    let area: f32 = polygon.iter()
+                           .zip(polygon.iter().cycle().skip(1))
+                           .map(|(a, b)| a.x * b.y - b.x * a.y)
+                           .sum();
+    polygon.len() >= 3 && area > 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use drawing::{Colormap, Framebuffer, Palette, Shading, Translation, Vertex};
+    use drawing::colormap::LIGHT_LEVELS;
+    use files::PackContainer;
+    use files::png;
+    use files::testing::{MdlBuilder, write_pak};
+    use render::{Camera, View};
+    use util::Vec3;
+    use super::{AliasLight, AliasModel, AliasPose};
+
+    /// The image `model_viewer` expects, relative to the crate root.
+    const PLAYER_REFERENCE: &'static str = "src/render/testdata/player.png";
+
+    /// A colormap where light level `l` maps color `c` to `c + l`.
+    fn colormap() -> Colormap {
+        let bytes: Vec<u8> = (0..LIGHT_LEVELS * 256).map(|i| (i % 256 + i / 256) as u8).collect();
+        Colormap::from_bytes(&bytes)
+    }
+
+    /// A 32x32 square in the yz plane facing -x, with a skin that is 10 on
+    /// its left half and 20 on the right. The normals on its left edge point
+    /// towards the camera, the ones on the right edge away from the light.
+    fn square() -> Vec<u8> {
+        let mut b = MdlBuilder::new([1.0, 1.0, 1.0], [-16.0, -16.0, -16.0], 8, 8);
+        b.skin(|x, _| if x < 4 { 10 } else { 20 })
+         .st_vert(false, 8, 8)
+         .st_vert(false, 0, 8)
+         .st_vert(false, 0, 0)
+         .st_vert(false, 8, 0)
+         .triangle(true, [0, 1, 2])
+         .triangle(true, [0, 2, 3])
+         // Normal 52 is +x, 143 is -x
+         .frame("square",
+                &[[16, 0, 0, 52], [16, 32, 0, 143], [16, 32, 32, 143], [16, 0, 32, 52]])
+         // Moved 16 units to the left
+         .frame("moved",
+                &[[16, 16, 0, 52], [16, 48, 0, 143], [16, 48, 32, 143], [16, 16, 32, 52]]);
+        b.build()
+    }
+
+    fn pose(light: i32) -> AliasPose {
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        AliasPose::new(zero, zero, 0, 0, AliasLight::at(light))
+    }
+
+    fn framebuffer() -> Framebuffer {
+        let mut fb = Framebuffer::with_palette(64, 48, Palette::from_bytes(&[0; 768]));
+        fb.fill(255);
+        fb.enable_zbuffer();
+        fb
+    }
+
+    fn draw(model: &AliasModel, fb: &mut Framebuffer, pose: &AliasPose) {
+        let camera = Camera::new(Vec3::new(-64.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let view = View::new(&camera, fb.width(), fb.height());
+        model.draw(fb, &view, &colormap(), pose, 0.0);
+    }
+
+    /// Renders the `progs/player.mdl` of `Id1/PAK0.PAK` and compares the
+    /// image with the reference in `src/render/testdata`. Ignored as the
+    /// game data isn't part of the repository, run it with `--ignored` from
+    /// a checkout that has it. After a deliberate change to the drawing, run
+    /// it with `QUAKE_BLESS=1` to write a new reference instead.
+    #[test]
+    #[ignore]
+    fn model_viewer() {
+        let mut paks = PackContainer::new();
+        paks.read_pack("Id1/PAK0.PAK").unwrap();
+        let model = AliasModel::load(&mut paks, "progs/player.mdl").unwrap();
+        let colormap = Colormap::new(&mut paks).unwrap();
+        let mut fb = Framebuffer::with_palette(160, 120, Palette::new(&mut paks).unwrap());
+        fb.fill(0);
+        fb.enable_zbuffer();
+
+        // Facing the camera, turned by 30 degrees, in its first frame
+        let camera = Camera::new(Vec3::new(-80.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let view = View::new(&camera, fb.width(), fb.height());
+        let pose = AliasPose::new(Vec3::new(0.0, 0.0, 0.0),
+                                  Vec3::new(0.0, 150.0, 0.0),
+                                  0,
+                                  0,
+                                  AliasLight::at(128));
+        model.draw(&mut fb, &view, &colormap, &pose, 0.0);
+
+        let mut image = vec![];
+        png::write_indexed(&mut image, 160, 120, &fb.palette().to_bytes(), fb.pixels()).unwrap();
+        if env::var_os("QUAKE_BLESS").is_some() {
+            fs::create_dir_all("src/render/testdata").unwrap();
+            fs::write(PLAYER_REFERENCE, &image).unwrap();
+            return;
+        }
+        let reference = fs::read(PLAYER_REFERENCE)
+                            .expect("no reference image, run with QUAKE_BLESS=1 to write it");
+        assert!(image == reference, "the image differs from {}", PLAYER_REFERENCE);
+    }
+
+    #[test]
+    fn lit_square() {
+        let path = write_pak("alias_square", &[("progs/square.mdl", &square())]);
+        let mut paks = PackContainer::new();
+        paks.read_pack(path).unwrap();
+        let model = AliasModel::load(&mut paks, "progs/square.mdl").unwrap();
+
+        let mut fb = framebuffer();
+        draw(&model, &mut fb, &pose(128));
+        // 32 units wide at a distance of 64 covers 16 pixels
+        for y in 0..48 {
+            for x in 0..64 {
+                let inside = (24..40).contains(&x) && (16..32).contains(&y);
+                assert_eq!(fb.get(x, y) != 255, inside, "pixel {} {}", x, y);
+            }
+        }
+        // Ambient light 128 is level 31, brightened to level 15 towards the
+        // right edge, whose normals face away from the light direction
+        assert_eq!(fb.get(24, 20), 10 + 31);
+        assert_eq!(fb.get(31, 20), 10 + 24);
+        assert_eq!(fb.get(32, 20), 20 + 23);
+        assert_eq!(fb.get(39, 20), 20 + 16);
+        assert!(fb.zbuffer().unwrap()[20 * 64 + 30] > 0);
+    }
+
+    #[test]
+    fn depth_tested_and_culled() {
+        let model = AliasModel::new(::files::mdl::Mdl::from_bytes(&square()).unwrap());
+        // A wall in front of the left half of the square
+        let mut fb = framebuffer();
+        let wall = [Vertex::new(0.0, 0.0, 32.0, 0.0, 0.0),
+                    Vertex::new(32.0, 0.0, 32.0, 0.0, 0.0),
+                    Vertex::new(32.0, 48.0, 32.0, 0.0, 0.0),
+                    Vertex::new(0.0, 48.0, 32.0, 0.0, 0.0)];
+        fb.polygon(&wall, Shading::Flat(1));
+        draw(&model, &mut fb, &pose(255));
+        assert_eq!((fb.get(30, 20), fb.get(33, 20)), (1, 20));
+
+        // Turned around, the back of the square isn't drawn
+        let mut fb = framebuffer();
+        let turned = AliasPose { angles: Vec3::new(0.0, 180.0, 0.0), ..pose(255) };
+        draw(&model, &mut fb, &turned);
+        assert!(fb.pixels().iter().all(|&p| p == 255));
+
+        // Out of view
+        let behind = AliasPose { origin: Vec3::new(-200.0, 0.0, 0.0), ..pose(255) };
+        draw(&model, &mut fb, &behind);
+        assert!(fb.pixels().iter().all(|&p| p == 255));
+    }
+
+    #[test]
+    fn blends_frames() {
+        let model = AliasModel::new(::files::mdl::Mdl::from_bytes(&square()).unwrap());
+        let mut fb = framebuffer();
+        let halfway = AliasPose {
+            frame: 1,
+            blend: 0.5,
+            ..pose(255)
+        };
+        draw(&model, &mut fb, &halfway);
+        // 4 pixels to the left
+        assert_eq!((fb.get(19, 20), fb.get(20, 20), fb.get(35, 20), fb.get(36, 20)),
+                   (255, 10, 20, 255));
+    }
+
+    #[test]
+    fn translated_skins() {
+        let model = AliasModel::new(::files::mdl::Mdl::from_bytes(&square()).unwrap());
+        let mut fb = framebuffer();
+        // Color 20 is a shirt color, 10 isn't
+        let pose = AliasPose { translation: Some(Translation::new(4, 0)), ..pose(255) };
+        draw(&model, &mut fb, &pose);
+        assert_eq!((fb.get(30, 20), fb.get(33, 20)), (10, 68));
+    }
+
+    #[test]
+    fn clipped_by_the_near_plane() {
+        let model = AliasModel::new(::files::mdl::Mdl::from_bytes(&square()).unwrap());
+        let mut fb = framebuffer();
+        // Rotated to recede from the camera, with one edge behind it
+        let pose = AliasPose {
+            origin: Vec3::new(-60.0, 0.0, 0.0),
+            angles: Vec3::new(0.0, 60.0, 0.0),
+            ..pose(255)
+        };
+        draw(&model, &mut fb, &pose);
+        assert!(fb.pixels().iter().any(|&p| p != 255));
+    }
+}
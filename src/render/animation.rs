@@ -0,0 +1,210 @@
+//! Interpolation of entities between updates. The game logic runs at 10 Hz,
+//! so models jump from frame to frame and position to position unless they
+//! are blended over the time between two updates, like the `lerpflags` of
+//! later engines.
+
+use files::mdl::{Frame, Mdl};
+use render::{AliasLight, AliasPose};
+use util::Vec3;
+
+/// Time over which changes are blended, one tick of the game logic.
+pub const LERP_TIME: f32 = 0.1;
+/// Entities moving further than this along an axis in one update are
+/// teleported and not interpolated, like in `CL_RelinkEntities`.
+pub const TELEPORT_DISTANCE: f32 = 100.0;
+
+/// What the game logic sets for an entity in an update.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EntityState {
+    pub origin: Vec3,
+    /// Pitch, yaw and roll in degrees.
+    pub angles: Vec3,
+    pub frame: usize,
+}
+
+/// The animation state of an entity with an alias model: its last two
+/// states and when they changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+    current: EntityState,
+    previous_frame: usize,
+    frame_start: f32,
+    previous_origin: Vec3,
+    previous_angles: Vec3,
+    move_start: f32,
+}
+
+impl Animation {
+    /// Starts at `state` at `time` seconds, without anything to blend from.
+    pub fn new(state: EntityState, time: f32) -> Animation {
+        // As if everything had finished blending already
+        let start = time - LERP_TIME;
+        Animation {
+            current: state,
+            previous_frame: state.frame,
+            frame_start: start,
+            previous_origin: state.origin,
+            previous_angles: state.angles,
+            move_start: start,
+        }
+    }
+
+    pub fn state(&self) -> EntityState {
+        self.current
+    }
+
+    /// Changes to the state of an update at `time`. Frame changes are
+    /// blended, except from or to frame groups, which animate by
+    /// themselves. Moves are blended, except teleports.
+    pub fn update(&mut self, model: &Mdl, state: EntityState, time: f32) {
+        if state.frame != self.current.frame {
+            let is_single = |frame| matches!(model.frames.get(frame), Some(&Frame::Single(_)));
+            self.previous_frame = if is_single(self.current.frame) && is_single(state.frame) {
+                self.current.frame
+            } else {
+                state.frame
+            };
+            self.frame_start = time;
+        }
+
+        if state.origin != self.current.origin || state.angles != self.current.angles {
+            let delta = state.origin - self.current.origin;
+            let teleported = [delta.x, delta.y, delta.z]
+                                 .iter()
+                                 .any(|d| d.abs() > TELEPORT_DISTANCE);
+            if teleported {
+                self.previous_origin = state.origin;
+                self.previous_angles = state.angles;
+            } else {
+                self.previous_origin = self.current.origin;
+                self.previous_angles = self.current.angles;
+            }
+            self.move_start = time;
+        }
+        self.current = state;
+    }
+
+    /// Jumps to `state` without blending, e.g. when the entity was
+    /// respawned or explicitly teleported.
+    pub fn reset(&mut self, state: EntityState, time: f32) {
+        *self = Animation::new(state, time);
+    }
+
+    /// The pose to draw the entity with at `time`, which is usually a bit
+    /// after the last update.
+    pub fn pose(&self, time: f32, skin: usize, light: AliasLight) -> AliasPose {
+        let frame_blend = blend_fraction(self.frame_start, time);
+        let move_blend = blend_fraction(self.move_start, time);
+        let (from, to) = (self.previous_angles, self.current.angles);
+        let moved = self.current.origin - self.previous_origin;
+        AliasPose {
+            origin: self.previous_origin + moved * move_blend,
+            angles: Vec3::new(lerp_angle(from.x, to.x, move_blend),
+                              lerp_angle(from.y, to.y, move_blend),
+                              lerp_angle(from.z, to.z, move_blend)),
+            frame: self.current.frame,
+            previous_frame: self.previous_frame,
+            blend: frame_blend,
+            skin: skin,
+            light: light,
+            translation: None,
+        }
+    }
+}
+
+/// How far a change that started at `start` is blended at `time`.
+fn blend_fraction(start: f32, time: f32) -> f32 {
+    ((time - start) / LERP_TIME).clamp(0.0, 1.0)
+}
+
+/// Interpolates between two angles in degrees the short way round.
+fn lerp_angle(from: f32, to: f32, f: f32) -> f32 {
+    let mut delta = to - from;
+    if delta > 180.0 {
+        delta -= 360.0;
+    } else if delta < -180.0 {
+        delta += 360.0;
+    }
+    from + delta * f
+}
+
+#[cfg(test)]
+mod tests {
+    use files::mdl::Mdl;
+    use files::testing::MdlBuilder;
+    use render::AliasLight;
+    use util::Vec3;
+    use super::{Animation, EntityState, lerp_angle};
+
+    /// A model with two single frames and a group.
+    fn model() -> Mdl {
+        let mut b = MdlBuilder::new([1.0, 1.0, 1.0], [0.0, 0.0, 0.0], 4, 4);
+        b.skin(|_, _| 0)
+         .st_vert(false, 0, 0)
+         .triangle(true, [0, 0, 0])
+         .frame("stand1", &[[0, 0, 0, 0]])
+         .frame("stand2", &[[10, 0, 0, 0]])
+         .frame_group(&[0.1], &[("flame", &[[20, 0, 0, 0]])]);
+        Mdl::from_bytes(&b.build()).unwrap()
+    }
+
+    fn state(x: f32, yaw: f32, frame: usize) -> EntityState {
+        EntityState {
+            origin: Vec3::new(x, 0.0, 0.0),
+            angles: Vec3::new(0.0, yaw, 0.0),
+            frame: frame,
+        }
+    }
+
+    #[test]
+    fn blends_frames_and_moves() {
+        let mdl = model();
+        let light = AliasLight::at(128);
+        let mut animation = Animation::new(state(0.0, 350.0, 0), 1.0);
+        let pose = animation.pose(1.0, 0, light);
+        assert_eq!((pose.previous_frame, pose.frame, pose.blend), (0, 0, 1.0));
+
+        animation.update(&mdl, state(10.0, 10.0, 1), 1.1);
+        let pose = animation.pose(1.125, 0, light);
+        assert_eq!((pose.previous_frame, pose.frame), (0, 1));
+        assert!((pose.blend - 0.25).abs() < 1e-4);
+        assert!((pose.origin.x - 2.5).abs() < 1e-4);
+        // The short way round, from 350 over 360 to 10
+        assert!((pose.angles.y - 355.0).abs() < 1e-3);
+        let pose = animation.pose(1.5, 0, light);
+        assert_eq!((pose.blend, pose.origin.x), (1.0, 10.0));
+
+        // Standing still while animating
+        animation.update(&mdl, state(10.0, 10.0, 0), 1.6);
+        let pose = animation.pose(1.65, 0, light);
+        assert_eq!((pose.previous_frame, pose.origin.x), (1, 10.0));
+        assert_eq!(animation.state(), state(10.0, 10.0, 0));
+    }
+
+    #[test]
+    fn teleports_and_groups_are_not_blended() {
+        let mdl = model();
+        let light = AliasLight::at(128);
+        let mut animation = Animation::new(state(0.0, 0.0, 0), 0.0);
+        animation.update(&mdl, state(500.0, 90.0, 2), 0.1);
+        let pose = animation.pose(0.12, 0, light);
+        assert_eq!(pose.origin.x, 500.0);
+        assert_eq!(pose.angles.y, 90.0);
+        assert_eq!((pose.previous_frame, pose.frame), (2, 2));
+
+        animation.update(&mdl, state(500.0, 90.0, 1), 0.2);
+        assert_eq!(animation.pose(0.22, 0, light).previous_frame, 1);
+
+        animation.update(&mdl, state(510.0, 90.0, 0), 0.3);
+        animation.reset(state(0.0, 0.0, 0), 0.35);
+        let pose = animation.pose(0.36, 0, light);
+        assert_eq!((pose.origin.x, pose.previous_frame, pose.blend), (0.0, 0, 1.0));
+    }
+
+    #[test]
+    fn angles_take_the_short_way() {
+        assert_eq!(lerp_angle(10.0, 350.0, 0.5), 0.0);
+        assert_eq!(lerp_angle(-90.0, 90.0, 0.5), 0.0);
+        assert_eq!(lerp_angle(0.0, 90.0, 0.5), 45.0);
+    }
+}
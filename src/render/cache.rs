@@ -0,0 +1,223 @@
+//! The surface cache of `d_surf.c`. Building a lit surface touches every
+//! texel of a face, so surfaces are kept from frame to frame and only
+//! rebuilt when their light changes.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use drawing::Texture;
+use files::bsp::MAX_LIGHTSTYLES;
+
+/// Size of the cache at 320x200, like `SURFCACHE_SIZE_AT_320X200`.
+const BASE_CACHE_SIZE: usize = 600 * 1024;
+
+/// A cache size that comfortably holds the surfaces visible at a
+/// resolution, like `D_SurfaceCacheForRes`.
+pub fn cache_size_for(width: usize, height: usize) -> usize {
+    let pixels = width * height;
+    BASE_CACHE_SIZE + pixels.saturating_sub(320 * 200) * 3
+}
+
+/// Everything a cached surface was built from, besides its face and mip
+/// level. A surface is rebuilt when any of it changes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SurfaceState {
+    /// Index of the texture, which changes for animated textures.
+    pub texture: usize,
+    /// Values of the face's light styles.
+    pub styles: [i32; MAX_LIGHTSTYLES],
+    /// Whether dynamic lights touch the face. Such surfaces are rebuilt
+    /// every frame, and once more after the lights are gone.
+    pub dynamic: bool,
+}
+
+impl SurfaceState {
+    fn is_current(&self, state: &SurfaceState) -> bool {
+        !self.dynamic && !state.dynamic && self == state
+    }
+}
+
+struct CachedSurface {
+    state: SurfaceState,
+    surface: Rc<Texture>,
+    last_used: u64,
+}
+
+/// Lit surfaces by face and mip level, limited to a number of bytes. When
+/// it's full, the least recently used surfaces are dropped.
+pub struct SurfaceCache {
+    max_size: usize,
+    size: usize,
+    frame: u64,
+    built: usize,
+    surfaces: HashMap<(usize, usize), CachedSurface>,
+}
+
+impl SurfaceCache {
+    pub fn new(max_size: usize) -> SurfaceCache {
+        SurfaceCache {
+            max_size: max_size,
+            size: 0,
+            frame: 0,
+            built: 0,
+            surfaces: HashMap::new(),
+        }
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Bytes used by the cached surfaces.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn len(&self) -> usize {
+        self.surfaces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.surfaces.is_empty()
+    }
+
+    /// Whether a surface of `size` bytes can be cached at all.
+    pub fn fits(&self, size: usize) -> bool {
+        size <= self.max_size
+    }
+
+    /// Starts a new frame, for the least recently used bookkeeping and the
+    /// `built` counter.
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+        self.built = 0;
+    }
+
+    /// Number of surfaces built since `begin_frame`.
+    pub fn built(&self) -> usize {
+        self.built
+    }
+
+    /// Returns the surface of `face` at mip level `mip`. It is built with
+    /// `build` if it isn't cached yet, or was built from a different state.
+    pub fn surface<F>(&mut self,
+                      face: usize,
+                      mip: usize,
+                      state: SurfaceState,
+                      build: F)
+                      -> Rc<Texture>
+        where F: FnOnce() -> Texture
+    {
+        let key = (face, mip);
+        if let Some(cached) = self.surfaces.get_mut(&key) {
+            if cached.state.is_current(&state) {
+                cached.last_used = self.frame;
+                return cached.surface.clone();
+            }
+        }
+
+        if let Some(old) = self.surfaces.remove(&key) {
+            self.size -= old.surface.pixels().len();
+        }
+        let surface = Rc::new(build());
+        let size = surface.pixels().len();
+        self.make_room(size);
+        self.size += size;
+        self.built += 1;
+        self.surfaces.insert(key,
+                             CachedSurface {
+                                 state: state,
+                                 surface: surface.clone(),
+                                 last_used: self.frame,
+                             });
+        surface
+    }
+
+    /// Drops the least recently used surfaces until `size` more bytes fit.
+    fn make_room(&mut self, size: usize) {
+        if self.size + size <= self.max_size {
+            return;
+        }
+        let mut keys: Vec<_> = self.surfaces.iter().map(|(&key, s)| (s.last_used, key)).collect();
+        keys.sort();
+        for (_, key) in keys {
+            if self.size + size <= self.max_size {
+                break;
+            }
+            let old = self.surfaces.remove(&key).unwrap();
+            self.size -= old.surface.pixels().len();
+        }
+    }
+
+    /// Drops all surfaces, e.g. after loading another map.
+    pub fn clear(&mut self) {
+        self.surfaces.clear();
+        self.size = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drawing::Texture;
+    use super::{SurfaceCache, SurfaceState, cache_size_for};
+
+    fn state(texture: usize, light: i32, dynamic: bool) -> SurfaceState {
+        SurfaceState {
+            texture: texture,
+            styles: [light, 0, 0, 0],
+            dynamic: dynamic,
+        }
+    }
+
+    fn texture(color: u8) -> Texture {
+        Texture::new(4, 4, vec![color; 16])
+    }
+
+    #[test]
+    fn rebuilds_only_when_the_state_changes() {
+        let mut cache = SurfaceCache::new(1024);
+        cache.begin_frame();
+        assert_eq!(cache.surface(0, 0, state(0, 264, false), || texture(1)).get(0, 0), 1);
+        assert_eq!(cache.surface(0, 0, state(0, 264, false), || texture(2)).get(0, 0), 1);
+        assert_eq!(cache.surface(0, 1, state(0, 264, false), || texture(3)).get(0, 0), 3);
+        assert_eq!(cache.built(), 2);
+
+        cache.begin_frame();
+        assert_eq!(cache.surface(0, 0, state(0, 100, false), || texture(4)).get(0, 0), 4);
+        assert_eq!(cache.surface(0, 0, state(1, 100, false), || texture(5)).get(0, 0), 5);
+        assert_eq!(cache.built(), 2);
+        assert_eq!((cache.len(), cache.size()), (2, 32));
+    }
+
+    #[test]
+    fn dynamic_surfaces_are_rebuilt() {
+        let mut cache = SurfaceCache::new(1024);
+        cache.surface(0, 0, state(0, 264, true), || texture(1));
+        cache.surface(0, 0, state(0, 264, true), || texture(2));
+        // Once more without the lights, then it's cached again
+        cache.surface(0, 0, state(0, 264, false), || texture(3));
+        let surface = cache.surface(0, 0, state(0, 264, false), || texture(4));
+        assert_eq!(surface.get(0, 0), 3);
+        assert_eq!(cache.built(), 3);
+    }
+
+    #[test]
+    fn drops_least_recently_used() {
+        let mut cache = SurfaceCache::new(40);
+        for face in 0..2 {
+            cache.begin_frame();
+            cache.surface(face, 0, state(0, 264, false), || texture(face as u8));
+        }
+        // Face 0 is used again, so face 1 goes
+        cache.begin_frame();
+        cache.surface(0, 0, state(0, 264, false), || texture(9));
+        cache.surface(2, 0, state(0, 264, false), || texture(2));
+        assert_eq!((cache.len(), cache.size()), (2, 32));
+        assert_eq!(cache.surface(0, 0, state(0, 264, false), || texture(9)).get(0, 0), 0);
+        assert_eq!(cache.surface(1, 0, state(0, 264, false), || texture(9)).get(0, 0), 9);
+
+        assert!(!cache.fits(41));
+        assert_eq!(cache_size_for(320, 200), 600 * 1024);
+        assert_eq!(cache_size_for(640, 480), 600 * 1024 + 243200 * 3);
+    }
+}
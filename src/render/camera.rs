@@ -0,0 +1,223 @@
+use render::frustum::Frustum;
+use util::Vec3;
+use util::vector::angle_vectors;
+
+/// Default horizontal field of view in degrees.
+pub const DEFAULT_FOV: f32 = 90.0;
+/// The aspect ratio the field of view is given for. Wider screens see more
+/// to the sides, narrower ones less at the top and bottom.
+pub const REFERENCE_ASPECT: f32 = 4.0 / 3.0;
+/// Distance of the near clipping plane.
+pub const NEAR_CLIP: f32 = 0.01;
+
+/// Where the world is viewed from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Camera {
+    pub origin: Vec3,
+    /// Pitch, yaw and roll in degrees.
+    pub angles: Vec3,
+    /// Horizontal field of view in degrees on a 4:3 screen.
+    pub fov: f32,
+}
+
+impl Camera {
+    pub fn new(origin: Vec3, angles: Vec3) -> Camera {
+        Camera {
+            origin: origin,
+            angles: angles,
+            fov: DEFAULT_FOV,
+        }
+    }
+}
+
+/// A vertex during clipping: a point in view space and its texture
+/// coordinates.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ClipVertex {
+    pub pos: Vec3,
+    pub s: f32,
+    pub t: f32,
+}
+
+impl ClipVertex {
+    fn lerp(&self, other: &ClipVertex, f: f32) -> ClipVertex {
+        ClipVertex {
+            pos: self.pos.lerp(other.pos, f),
+            s: self.s + (other.s - self.s) * f,
+            t: self.t + (other.t - self.t) * f,
+        }
+    }
+}
+
+/// A camera set up for a framebuffer of a given size. View space has x
+/// pointing right, y up and z into the screen.
+#[derive(Debug, Clone)]
+pub struct View {
+    pub origin: Vec3,
+    pub forward: Vec3,
+    pub right: Vec3,
+    pub up: Vec3,
+    /// Tangents of half the horizontal and vertical field of view.
+    pub tan_x: f32,
+    pub tan_y: f32,
+    /// Pixels per unit at a depth of 1.
+    pub scale: f32,
+    pub center_x: f32,
+    pub center_y: f32,
+    pub frustum: Frustum,
+}
+
+impl View {
+    pub fn new(camera: &Camera, width: usize, height: usize) -> View {
+        let (forward, right, up) = angle_vectors(camera.angles);
+        // Horizontal plus: the vertical field of view is that of a 4:3
+        // screen, and the horizontal one follows from the aspect ratio
+        let tan_y = (camera.fov.to_radians() / 2.0).tan() / REFERENCE_ASPECT;
+        let tan_x = tan_y * width as f32 / height as f32;
+        let center_x = width as f32 / 2.0;
+        let center_y = height as f32 / 2.0;
+        View {
+            origin: camera.origin,
+            forward: forward,
+            right: right,
+            up: up,
+            tan_x: tan_x,
+            tan_y: tan_y,
+            scale: center_x / tan_x,
+            center_x: center_x,
+            center_y: center_y,
+            frustum: Frustum::new(camera.origin, forward, right, up, tan_x, tan_y),
+        }
+    }
+
+    /// Transforms a point from world into view space.
+    pub fn transform(&self, point: Vec3) -> Vec3 {
+        let p = point - self.origin;
+        Vec3::new(p.dot(self.right), p.dot(self.up), p.dot(self.forward))
+    }
+
+    /// Projects a point in view space (in front of the camera) to pixel
+    /// coordinates.
+    pub fn project(&self, point: Vec3) -> (f32, f32) {
+        let iz = self.scale / point.z;
+        (self.center_x + point.x * iz, self.center_y - point.y * iz)
+    }
+
+    /// The near plane and the four sides of the view volume in view space,
+    /// as (normal, distance) pairs with the inside in front.
+    pub fn clip_planes(&self) -> [(Vec3, f32); 5] {
+        [(Vec3::new(0.0, 0.0, 1.0), NEAR_CLIP),
+         (Vec3::new(-1.0, 0.0, self.tan_x), 0.0),
+         (Vec3::new(1.0, 0.0, self.tan_x), 0.0),
+         (Vec3::new(0.0, -1.0, self.tan_y), 0.0),
+         (Vec3::new(0.0, 1.0, self.tan_y), 0.0)]
+    }
+
+    /// Whether the box from `mins` to `maxs` is at least partially inside
+    /// the view volume.
+    pub fn box_visible(&self, mins: Vec3, maxs: Vec3) -> bool {
+        self.frustum.box_visible(mins, maxs)
+    }
+
+    /// Whether the sphere around `center` is at least partially inside the
+    /// view volume.
+    pub fn sphere_visible(&self, center: Vec3, radius: f32) -> bool {
+        self.frustum.sphere_visible(center, radius)
+    }
+
+    /// Clips a convex polygon in view space against the view volume.
+    pub fn clip_polygon(&self, polygon: Vec<ClipVertex>) -> Vec<ClipVertex> {
+        self.clip_planes()
+            .iter()
+            .fold(polygon, |poly, &(normal, dist)| clip_polygon(&poly, normal, dist))
+    }
+}
+
+/// Clips a convex polygon against a plane, keeping the part in front of it.
+pub fn clip_polygon(polygon: &[ClipVertex], normal: Vec3, dist: f32) -> Vec<ClipVertex> {
+    let mut out = Vec::with_capacity(polygon.len() + 1);
+    for (i, a) in polygon.iter().enumerate() {
+        let b = &polygon[(i + 1) % polygon.len()];
+        let da = a.pos.dot(normal) - dist;
+        let db = b.pos.dot(normal) - dist;
+        if da >= 0.0 {
+            out.push(*a);
+        }
+        if (da >= 0.0) != (db >= 0.0) {
+            out.push(a.lerp(b, da / (da - db)));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use util::Vec3;
+    use super::{Camera, ClipVertex, View};
+
+    fn vertex(x: f32, y: f32, z: f32) -> ClipVertex {
+        ClipVertex {
+            pos: Vec3::new(x, y, z),
+            s: x,
+            t: y,
+        }
+    }
+
+    #[test]
+    fn transform_and_project() {
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, 0.0));
+        let view = View::new(&camera, 200, 150);
+        // Looking along +x, so +y is to the left
+        let p = view.transform(Vec3::new(50.0, -10.0, 15.0));
+        assert_eq!(p, Vec3::new(10.0, 5.0, 50.0));
+        assert_eq!(view.project(p), (120.0, 65.0));
+        assert_eq!(view.project(Vec3::new(50.0, 25.0, 50.0)), (200.0, 25.0));
+    }
+
+    #[test]
+    fn horizontal_plus_fov() {
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let standard = View::new(&camera, 320, 240);
+        assert_eq!((standard.tan_x, standard.tan_y), (1.0, 0.75));
+        // 16:9 sees more to the sides, but just as much at the top
+        let wide = View::new(&camera, 320, 180);
+        assert_eq!(wide.tan_y, 0.75);
+        assert!((wide.tan_x - 4.0 / 3.0).abs() < 1e-6);
+        assert!(wide.box_visible(Vec3::new(10.0, 11.0, -1.0), Vec3::new(10.0, 12.0, 1.0)));
+        assert!(!standard.box_visible(Vec3::new(10.0, 11.0, -1.0), Vec3::new(10.0, 12.0, 1.0)));
+        // The scale only depends on the height
+        assert!((wide.scale - 120.0).abs() < 1e-4);
+        assert_eq!(View::new(&camera, 640, 240).scale, standard.scale);
+    }
+
+    #[test]
+    fn box_visibility() {
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let view = View::new(&camera, 100, 75);
+        let visible = |x: f32, y: f32| {
+            view.box_visible(Vec3::new(x - 1.0, y - 1.0, -1.0), Vec3::new(x + 1.0, y + 1.0, 1.0))
+        };
+        assert!(visible(10.0, 0.0));
+        assert!(visible(10.0, 10.5));
+        assert!(!visible(10.0, 12.5));
+        assert!(!visible(-10.0, 0.0));
+    }
+
+    #[test]
+    fn clipping() {
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let view = View::new(&camera, 100, 75);
+        // A square at depth 10, twice as wide as the view
+        let square = vec![vertex(-20.0, -5.0, 10.0),
+                          vertex(20.0, -5.0, 10.0),
+                          vertex(20.0, 5.0, 10.0),
+                          vertex(-20.0, 5.0, 10.0)];
+        let clipped = view.clip_polygon(square);
+        assert_eq!(clipped.len(), 4);
+        assert!(clipped.iter().all(|v| v.pos.x.abs() <= 10.0 + 1e-4));
+        assert!(clipped.iter().any(|v| (v.s - 10.0).abs() < 1e-4));
+
+        let behind = vec![vertex(0.0, 0.0, -1.0), vertex(1.0, 0.0, -1.0), vertex(0.0, 1.0, -2.0)];
+        assert!(view.clip_polygon(behind).is_empty());
+    }
+}
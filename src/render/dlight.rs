@@ -0,0 +1,195 @@
+//! Dynamic lights, like `cl_dlights` and `R_MarkLights`. Muzzle flashes,
+//! rockets and explosions light up the world around them for a moment.
+//! Every frame, the faces a light may reach are found by walking the node
+//! tree, and their lightmaps get the light added before their surfaces are
+//! rebuilt.
+
+use files::bsp::{Bsp, NodeChild};
+use util::Vec3;
+
+/// Number of dynamic lights, like `MAX_DLIGHTS`. Every face keeps one bit
+/// per light.
+pub const MAX_DLIGHTS: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DynamicLight {
+    pub origin: Vec3,
+    /// Distance at which the light fades out.
+    pub radius: f32,
+    /// Time at which the light goes out.
+    pub die: f32,
+    /// How much the radius shrinks per second.
+    pub decay: f32,
+    /// Light below this is left out, which cuts off the edge of the light.
+    pub min_light: f32,
+    /// Entity the light belongs to, so it reuses its light every frame. 0
+    /// for lights without an entity.
+    pub key: i32,
+}
+
+impl DynamicLight {
+    fn empty() -> DynamicLight {
+        DynamicLight {
+            origin: Vec3::zero(),
+            radius: 0.0,
+            die: 0.0,
+            decay: 0.0,
+            min_light: 0.0,
+            key: 0,
+        }
+    }
+
+    /// Whether the light shines at `time`.
+    pub fn is_active(&self, time: f32) -> bool {
+        self.die >= time && self.radius > 0.0
+    }
+}
+
+/// The fixed set of dynamic light slots.
+#[derive(Debug, Clone)]
+pub struct DynamicLights {
+    lights: [DynamicLight; MAX_DLIGHTS],
+}
+
+impl Default for DynamicLights {
+    fn default() -> DynamicLights {
+        DynamicLights::new()
+    }
+}
+
+impl DynamicLights {
+    pub fn new() -> DynamicLights {
+        DynamicLights { lights: [DynamicLight::empty(); MAX_DLIGHTS] }
+    }
+
+    pub fn lights(&self) -> &[DynamicLight] {
+        &self.lights
+    }
+
+    /// Turns all lights off, e.g. when a new map is loaded.
+    pub fn clear(&mut self) {
+        self.lights = [DynamicLight::empty(); MAX_DLIGHTS];
+    }
+
+    /// Returns a cleared light for the entity `key`, like `CL_AllocDlight`.
+    /// It is the entity's previous light if it has one, otherwise one that
+    /// is out at `time`, or the first one if all are in use.
+    pub fn alloc(&mut self, key: i32, time: f32) -> &mut DynamicLight {
+        let index = self.lights
+                        .iter()
+                        .position(|l| key != 0 && l.key == key)
+                        .or_else(|| self.lights.iter().position(|l| l.die < time))
+                        .unwrap_or(0);
+        let light = &mut self.lights[index];
+        *light = DynamicLight::empty();
+        light.key = key;
+        light
+    }
+
+    /// Shrinks the lights by `frametime` seconds of decay, like
+    /// `CL_DecayLights`.
+    pub fn decay(&mut self, time: f32, frametime: f32) {
+        for light in self.lights.iter_mut().filter(|l| l.is_active(time)) {
+            light.radius = (light.radius - frametime * light.decay).max(0.0);
+        }
+    }
+
+    /// Finds the faces of the world the lights shining at `time` may reach,
+    /// like `R_PushDlights`. Returns a bit set per face, with bit `i` set for
+    /// light `i`.
+    pub fn mark_faces(&self, bsp: &Bsp, time: f32) -> Vec<u32> {
+        let mut bits = vec![0; bsp.faces.len()];
+        let root = NodeChild::Node(bsp.world().headnodes[0] as usize);
+        for (i, light) in self.lights.iter().enumerate() {
+            if light.is_active(time) {
+                mark_lights(bsp, light, 1 << i, root, &mut bits);
+            }
+        }
+        bits
+    }
+}
+
+/// Marks the faces on the nodes below `child` that are within the radius
+/// of `light`, like `R_MarkLights`.
+fn mark_lights(bsp: &Bsp, light: &DynamicLight, bit: u32, child: NodeChild, bits: &mut [u32]) {
+    let node = match child {
+        NodeChild::Node(n) => &bsp.nodes[n],
+        NodeChild::Leaf(_) => return,
+    };
+    let plane = &bsp.planes[node.plane];
+    let dist = light.origin.dot(plane.normal) - plane.dist;
+    if dist > light.radius {
+        mark_lights(bsp, light, bit, node.children[0], bits);
+    } else if dist < -light.radius {
+        mark_lights(bsp, light, bit, node.children[1], bits);
+    } else {
+        for face_bits in &mut bits[node.first_face..node.first_face + node.num_faces] {
+            *face_bits |= bit;
+        }
+        mark_lights(bsp, light, bit, node.children[0], bits);
+        mark_lights(bsp, light, bit, node.children[1], bits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use files::bsp::Bsp;
+    use files::testing::floor_bsp;
+    use util::Vec3;
+    use super::{DynamicLights, MAX_DLIGHTS};
+
+    #[test]
+    fn allocation_and_decay() {
+        let mut lights = DynamicLights::new();
+        {
+            let light = lights.alloc(5, 1.0);
+            light.radius = 200.0;
+            light.die = 1.5;
+            light.decay = 100.0;
+        }
+        // The same entity gets its light back, cleared
+        assert_eq!(lights.alloc(5, 1.0).radius, 0.0);
+        lights.alloc(5, 1.0).die = 1.5;
+        assert_eq!(lights.lights()[0].key, 5);
+        // Others get a free one
+        lights.alloc(0, 1.0).die = 1.5;
+        lights.alloc(0, 1.0).die = 1.5;
+        assert_eq!((lights.lights()[1].die, lights.lights()[2].die), (1.5, 1.5));
+        // With all in use, the first one
+        for _ in 3..MAX_DLIGHTS {
+            lights.alloc(0, 1.0).die = 1.5;
+        }
+        lights.alloc(7, 1.0);
+        assert_eq!(lights.lights()[0].key, 7);
+
+        let mut lights = DynamicLights::new();
+        {
+            let light = lights.alloc(1, 0.0);
+            light.radius = 200.0;
+            light.die = 1.0;
+            light.decay = 300.0;
+        }
+        lights.decay(0.5, 0.5);
+        assert_eq!(lights.lights()[0].radius, 50.0);
+        lights.decay(0.6, 0.5);
+        assert_eq!(lights.lights()[0].radius, 0.0);
+        assert!(!lights.lights()[0].is_active(0.6));
+    }
+
+    #[test]
+    fn marks_faces_in_reach() {
+        let bsp = Bsp::from_bytes(&floor_bsp().build()).unwrap();
+        let mut lights = DynamicLights::new();
+        assert_eq!(lights.mark_faces(&bsp, 1.0), vec![0]);
+        // Above the floor, out of reach and below it
+        for &z in &[50.0, 150.0, -80.0] {
+            let light = lights.alloc(0, 1.0);
+            light.origin = Vec3::new(0.0, 0.0, z);
+            light.radius = 100.0;
+            light.die = 2.0;
+        }
+        assert_eq!(lights.mark_faces(&bsp, 1.0), vec![0b101]);
+        // Out by then
+        assert_eq!(lights.mark_faces(&bsp, 3.0), vec![0]);
+    }
+}
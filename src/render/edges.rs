@@ -0,0 +1,298 @@
+//! Edge-sorted span generation, the hidden surface removal of Quake's
+//! `r_edge.c`.
+//!
+//! The edges of all projected polygons are put into a global edge table,
+//! bucketed by their first scanline. Every scanline, the edges crossing it
+//! are walked from left to right in the active edge list. A leading edge
+//! pushes its surface onto the surface stack and a trailing edge pops it; the
+//! nearest surface on the stack owns the pixels up to the next edge. The
+//! result is a list of spans per surface that don't overlap, so every pixel
+//! is drawn exactly once.
+
+use hprof;
+
+/// A quantity that varies linearly over the screen, like `1 / z` or `s / z`
+/// on a planar polygon.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Gradient {
+    /// Value at the pixel coordinates (0, 0), i.e. the corner of the screen.
+    pub origin: f32,
+    pub dx: f32,
+    pub dy: f32,
+}
+
+impl Gradient {
+    /// The value at the center of pixel (x, y).
+    #[inline]
+    pub fn at(&self, x: i32, y: i32) -> f32 {
+        self.origin + self.dx * (x as f32 + 0.5) + self.dy * (y as f32 + 0.5)
+    }
+}
+
+/// A horizontal run of `len` pixels starting at (x, y).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Span {
+    pub x: usize,
+    pub y: usize,
+    pub len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Edge {
+    /// X coordinate at the center of the current scanline.
+    x: f32,
+    dxdy: f32,
+    /// Last scanline (exclusive).
+    y_end: usize,
+    surface: usize,
+    /// Whether the surface starts (true) or ends at this edge.
+    leading: bool,
+}
+
+/// Counts of the work done for a frame.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct EdgeStats {
+    pub edges: usize,
+    pub surfaces: usize,
+    pub spans: usize,
+}
+
+/// The global edge table for one frame.
+pub struct EdgeList {
+    width: usize,
+    height: usize,
+    /// Edges by their first scanline.
+    new_edges: Vec<Vec<Edge>>,
+    /// The `1 / z` gradient of every surface, used to find the nearest one.
+    surfaces: Vec<Gradient>,
+    num_edges: usize,
+}
+
+impl EdgeList {
+    pub fn new(width: usize, height: usize) -> EdgeList {
+        EdgeList {
+            width: width,
+            height: height,
+            new_edges: vec![vec![]; height],
+            surfaces: vec![],
+            num_edges: 0,
+        }
+    }
+
+    /// Adds a convex polygon in screen coordinates and returns its surface
+    /// index. Polygons can have either winding.
+    pub fn add_polygon(&mut self, vertices: &[(f32, f32)], izi: Gradient) -> usize {
+        let surface = self.surfaces.len();
+        self.surfaces.push(izi);
+
+        // Twice the signed area; positive for clockwise polygons on screen
+        let n = vertices.len();
+        let area: f32 = (0..n)
+                            .map(|i| {
+                                let (a, b) = (vertices[i], vertices[(i + 1) % n]);
+                                a.0 * b.1 - b.0 * a.1
+                            })
+                            .sum();
+
+        for i in 0..n {
+            let (a, b) = (vertices[i], vertices[(i + 1) % n]);
+            let down = b.1 > a.1;
+            let (top, bottom) = if down { (a, b) } else { (b, a) };
+            let y_start = ((top.1 - 0.5).ceil().max(0.0) as usize).min(self.height);
+            let y_end = ((bottom.1 - 0.5).ceil().max(0.0) as usize).min(self.height);
+            if y_start >= y_end {
+                // Horizontal, or between two scanline centers
+                continue;
+            }
+
+            let dxdy = (bottom.0 - top.0) / (bottom.1 - top.1);
+            self.new_edges[y_start].push(Edge {
+                x: top.0 + (y_start as f32 + 0.5 - top.1) * dxdy,
+                dxdy: dxdy,
+                y_end: y_end,
+                surface: surface,
+                // In a clockwise polygon, the edges going down are on the right
+                leading: down != (area > 0.0),
+            });
+            self.num_edges += 1;
+        }
+        surface
+    }
+
+    pub fn num_surfaces(&self) -> usize {
+        self.surfaces.len()
+    }
+
+    /// Whether surface `a` is in front of surface `b` at pixel (x, y). Ties
+    /// (surfaces meeting at an edge) go to the surface that comes closer
+    /// towards the right, which owns the pixels after the edge.
+    fn in_front(&self, a: usize, b: usize, x: i32, y: i32) -> bool {
+        let (ga, gb) = (&self.surfaces[a], &self.surfaces[b]);
+        let (za, zb) = (ga.at(x, y), gb.at(x, y));
+        if (za - zb).abs() > za.abs().max(zb.abs()) * 1e-4 {
+            za > zb
+        } else {
+            ga.dx > gb.dx
+        }
+    }
+
+    /// Generates the visible spans of every surface, indexed like the
+    /// surfaces.
+    pub fn scan(self) -> (Vec<Vec<Span>>, EdgeStats) {
+        let _guard = hprof::enter("EdgeList::scan");
+        let mut spans = vec![vec![]; self.surfaces.len()];
+        let mut stats = EdgeStats {
+            edges: self.num_edges,
+            surfaces: 0,
+            spans: 0,
+        };
+        let mut active: Vec<Edge> = vec![];
+        let mut stack: Vec<usize> = vec![];
+        let width = self.width as i32;
+
+        for y in 0..self.height {
+            active.extend(self.new_edges[y].iter().cloned());
+            // Edges barely move between scanlines, so this is mostly sorted
+            active.sort_by(|a, b| {
+                a.x
+                 .partial_cmp(&b.x)
+                 .unwrap_or(::std::cmp::Ordering::Equal)
+                 .then(a.leading.cmp(&b.leading))
+            });
+
+            stack.clear();
+            let mut top: Option<usize> = None;
+            let mut span_start = 0;
+            {
+                let mut emit = |surface: usize, from: i32, to: i32| {
+                    if to > from {
+                        spans[surface].push(Span {
+                            x: from as usize,
+                            y: y,
+                            len: (to - from) as usize,
+                        });
+                    }
+                };
+
+                for edge in &active {
+                    let x = ((edge.x - 0.5).ceil() as i32).clamp(0, width);
+                    let surface = edge.surface;
+                    if edge.leading {
+                        stack.push(surface);
+                        match top {
+                            None => {
+                                top = Some(surface);
+                                span_start = x;
+                            }
+                            Some(t) if self.in_front(surface, t, x, y as i32) => {
+                                emit(t, span_start, x);
+                                top = Some(surface);
+                                span_start = x;
+                            }
+                            _ => {}
+                        }
+                    } else {
+                        if let Some(i) = stack.iter().position(|&s| s == surface) {
+                            stack.remove(i);
+                        }
+                        if top == Some(surface) {
+                            emit(surface, span_start, x);
+                            top = None;
+                            for &s in &stack {
+                                if top.map_or(true, |t| self.in_front(s, t, x, y as i32)) {
+                                    top = Some(s);
+                                }
+                            }
+                            span_start = x;
+                        }
+                    }
+                }
+            }
+
+            for edge in &mut active {
+                edge.x += edge.dxdy;
+            }
+            active.retain(|e| e.y_end > y + 1);
+        }
+
+        stats.surfaces = spans.iter().filter(|s| !s.is_empty()).count();
+        stats.spans = spans.iter().map(|s| s.len()).sum();
+        (spans, stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EdgeList, Gradient, Span};
+
+    fn rect(x0: f32, y0: f32, x1: f32, y1: f32) -> Vec<(f32, f32)> {
+        vec![(x0, y0), (x1, y0), (x1, y1), (x0, y1)]
+    }
+
+    fn depth(z: f32) -> Gradient {
+        Gradient {
+            origin: 1.0 / z,
+            dx: 0.0,
+            dy: 0.0,
+        }
+    }
+
+    /// Renders the spans into a grid of surface indices, checking that no
+    /// pixel is covered twice.
+    fn coverage(list: EdgeList) -> Vec<Vec<Option<usize>>> {
+        let (width, height) = (list.width, list.height);
+        let mut grid = vec![vec![None; width]; height];
+        let (spans, _) = list.scan();
+        for (surface, spans) in spans.iter().enumerate() {
+            for span in spans {
+                let row = &mut grid[span.y][span.x..span.x + span.len];
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    assert_eq!(*pixel, None, "pixel {} {} drawn twice", span.x + x, span.y);
+                    *pixel = Some(surface);
+                }
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn single_polygon() {
+        let mut list = EdgeList::new(8, 8);
+        // Counterclockwise on screen
+        let mut poly = rect(1.0, 2.0, 5.0, 4.0);
+        poly.reverse();
+        list.add_polygon(&poly, depth(1.0));
+        let (spans, stats) = list.scan();
+        assert_eq!(spans[0],
+                   vec![Span { x: 1, y: 2, len: 4 }, Span { x: 1, y: 3, len: 4 }]);
+        assert_eq!((stats.edges, stats.surfaces, stats.spans), (2, 1, 2));
+    }
+
+    #[test]
+    fn nearest_surface_wins() {
+        let mut list = EdgeList::new(12, 6);
+        list.add_polygon(&rect(0.0, 0.0, 8.0, 6.0), depth(4.0));
+        list.add_polygon(&rect(4.0, 2.0, 12.0, 4.0), depth(2.0));
+        list.add_polygon(&rect(2.0, 0.0, 6.0, 6.0), depth(8.0));
+        let grid = coverage(list);
+        assert_eq!(grid[0][..8], [Some(0); 8]);
+        assert_eq!(grid[0][8..], [None; 4]);
+        assert_eq!(grid[3][..4], [Some(0); 4]);
+        assert_eq!(grid[3][4..], [Some(1); 8]);
+        assert!(grid.iter().all(|row| !row.contains(&Some(2))));
+    }
+
+    #[test]
+    fn adjacent_polygons_leave_no_gaps() {
+        let mut list = EdgeList::new(8, 4);
+        list.add_polygon(&[(0.0, 0.0), (5.0, 0.0), (3.0, 4.0), (0.0, 4.0)], depth(2.0));
+        list.add_polygon(&[(5.0, 0.0), (8.0, 0.0), (8.0, 4.0), (3.0, 4.0)], depth(2.0));
+        let grid = coverage(list);
+        assert!(grid.iter().all(|row| row.iter().all(|p| p.is_some())));
+        // The shared edge crosses the scanline centers at x = 4.75 and 3.25
+        assert_eq!(grid[0][..5], [Some(0); 5]);
+        assert_eq!(grid[0][5], Some(1));
+        assert_eq!(grid[3][..3], [Some(0); 3]);
+        assert_eq!(grid[3][3], Some(1));
+    }
+}
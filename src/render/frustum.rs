@@ -0,0 +1,149 @@
+//! The view volume as world space planes, for culling nodes, leafs and
+//! entities before anything is transformed, like `R_SetFrustum`. Boxes are
+//! tested against the planes with the signbits trick of `BoxOnPlaneSide`,
+//! and planes a box is entirely in front of don't need to be tested again
+//! for anything inside it, like the `clipflags` of `R_RecursiveWorldNode`.
+
+use render::camera::NEAR_CLIP;
+use util::Vec3;
+use util::vector::{BoxSide, ClipPlane};
+
+/// The four sides and the near plane.
+pub const NUM_PLANES: usize = 5;
+/// Clip flags with a bit set for every plane.
+pub const ALL_PLANES: u8 = (1 << NUM_PLANES) - 1;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top and near, with the inside in front.
+    pub planes: [ClipPlane; NUM_PLANES],
+}
+
+impl Frustum {
+    /// The frustum of a camera at `origin` with the given axes, where
+    /// `tan_x` and `tan_y` are the tangents of half the field of view.
+    pub fn new(origin: Vec3,
+               forward: Vec3,
+               right: Vec3,
+               up: Vec3,
+               tan_x: f32,
+               tan_y: f32)
+               -> Frustum {
+        // Normalized, so that distances to the planes are true distances
+        let side = |normal: Vec3| {
+            let normal = normal.normalize();
+            ClipPlane::new(normal, normal.dot(origin))
+        };
+        Frustum {
+            planes: [side(forward * tan_x + right),
+                     side(forward * tan_x - right),
+                     side(forward * tan_y + up),
+                     side(forward * tan_y - up),
+                     ClipPlane::new(forward, forward.dot(origin) + NEAR_CLIP)],
+        }
+    }
+
+    /// Tests the box from `mins` to `maxs` against the planes set in
+    /// `clipflags`. Returns `None` if the box is outside, otherwise the
+    /// flags of the planes that still cut through it.
+    pub fn cull_box(&self, mins: Vec3, maxs: Vec3, clipflags: u8) -> Option<u8> {
+        let mut flags = clipflags;
+        for (i, plane) in self.planes.iter().enumerate() {
+            if flags & (1 << i) == 0 {
+                continue;
+            }
+            match plane.box_on_plane_side(mins, maxs) {
+                BoxSide::Back => return None,
+                BoxSide::Front => flags &= !(1 << i),
+                BoxSide::Both => {}
+            }
+        }
+        Some(flags)
+    }
+
+    /// Whether the box from `mins` to `maxs` is at least partially inside.
+    pub fn box_visible(&self, mins: Vec3, maxs: Vec3) -> bool {
+        self.cull_box(mins, maxs, ALL_PLANES).is_some()
+    }
+
+    /// Whether the sphere around `center` is at least partially inside.
+    pub fn sphere_visible(&self, center: Vec3, radius: f32) -> bool {
+        self.planes.iter().all(|plane| plane.distance(center) >= -radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use util::Vec3;
+    use super::{ALL_PLANES, Frustum};
+
+    /// At the origin looking along +x, 90 degrees wide and about 74 high.
+    fn frustum() -> Frustum {
+        Frustum::new(Vec3::new(0.0, 0.0, 0.0),
+                     Vec3::new(1.0, 0.0, 0.0),
+                     Vec3::new(0.0, -1.0, 0.0),
+                     Vec3::new(0.0, 0.0, 1.0),
+                     1.0,
+                     0.75)
+    }
+
+    fn cube(x: f32, y: f32, z: f32) -> (Vec3, Vec3) {
+        (Vec3::new(x - 1.0, y - 1.0, z - 1.0), Vec3::new(x + 1.0, y + 1.0, z + 1.0))
+    }
+
+    #[test]
+    fn planes_and_signbits() {
+        let f = frustum();
+        // The left plane faces right and forward
+        let left = f.planes[0].normal;
+        assert!(left.x > 0.0 && left.y < 0.0);
+        assert!((left.length() - 1.0).abs() < 1e-6);
+        assert_eq!(f.planes[0].signbits, 2);
+        assert_eq!(f.planes[3].signbits, 4);
+        assert_eq!(f.planes[4].dist, 0.01);
+    }
+
+    #[test]
+    fn boxes() {
+        let f = frustum();
+        let visible = |x: f32, y: f32, z: f32| {
+            let (mins, maxs) = cube(x, y, z);
+            f.box_visible(mins, maxs)
+        };
+        assert!(visible(10.0, 0.0, 0.0));
+        assert!(visible(10.0, 10.5, 0.0));
+        assert!(!visible(10.0, 12.5, 0.0));
+        assert!(!visible(10.0, -12.5, 0.0));
+        assert!(visible(10.0, 0.0, 8.0));
+        assert!(!visible(10.0, 0.0, 9.5));
+        assert!(!visible(-10.0, 0.0, 0.0));
+        // Around the camera
+        assert!(visible(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn clip_flags() {
+        let f = frustum();
+        // Entirely inside, nothing left to test
+        let (mins, maxs) = cube(10.0, 0.0, 0.0);
+        assert_eq!(f.cull_box(mins, maxs, ALL_PLANES), Some(0));
+        // Cut by the left plane only
+        let (mins, maxs) = cube(10.0, 10.5, 0.0);
+        assert_eq!(f.cull_box(mins, maxs, ALL_PLANES), Some(1));
+        // Planes that aren't set aren't tested
+        let (mins, maxs) = cube(10.0, 12.5, 0.0);
+        assert_eq!(f.cull_box(mins, maxs, ALL_PLANES), None);
+        assert_eq!(f.cull_box(mins, maxs, ALL_PLANES & !1), Some(0));
+    }
+
+    #[test]
+    fn spheres() {
+        let f = frustum();
+        assert!(f.sphere_visible(Vec3::new(10.0, 0.0, 0.0), 1.0));
+        // 1.41 units from the left plane
+        assert!(f.sphere_visible(Vec3::new(10.0, 12.0, 0.0), 1.5));
+        assert!(!f.sphere_visible(Vec3::new(10.0, 12.0, 0.0), 1.3));
+        assert!(!f.sphere_visible(Vec3::new(-10.0, 0.0, 0.0), 5.0));
+        assert!(f.sphere_visible(Vec3::new(-10.0, 0.0, 0.0), 10.5));
+    }
+}
@@ -0,0 +1,152 @@
+//! Animated light styles, like `R_AnimateLight`. A style is a string of
+//! brightness levels from `a` (dark) over `m` (normal) to `z` (twice as
+//! bright), stepped through ten times a second.
+
+use render::surface::NORMAL_STYLE_VALUE;
+
+/// Number of light styles.
+pub const MAX_STYLES: usize = 64;
+/// Light style frames per second.
+pub const STYLE_FPS: f32 = 10.0;
+/// Value of a style without a string.
+pub const UNSET_STYLE_VALUE: i32 = 256;
+
+/// The styles set up by `worldspawn` in `world.qc`. Styles 32 to 62 are
+/// assigned to switchable lights by the light tool.
+pub const DEFAULT_STYLES: [(usize, &'static str); 13] =
+    [// Normal
+     (0, "m"),
+     // Flicker (first variety)
+     (1, "mmnmmommommnonmmonqnmmo"),
+     // Slow strong pulse
+     (2, "abcdefghijklmnopqrstuvwxyzyxwvutsrqponmlkjihgfedcba"),
+     // Candle (first variety)
+     (3, "mmmmmaaaaammmmmaaaaaabcdefgabcdefg"),
+     // Fast strobe
+     (4, "mamamamamama"),
+     // Gentle pulse
+     (5, "jklmnopqrstuvwxyzyxwvutsrqponmlkj"),
+     // Flicker (second variety)
+     (6, "nmonqnmomnmomomno"),
+     // Candle (second variety)
+     (7, "mmmaaaabcdefgmmmmaaaaaaaabcdefgmmmmaaa"),
+     // Candle (third variety)
+     (8, "mmmaaammmaaammmabcdefaaaammmmabcdefmmmaaaa"),
+     // Slow strobe
+     (9, "aaaaaaaazzzzzzzz"),
+     // Fluorescent flicker
+     (10, "mmamammmmammamamaaamammma"),
+     // Slow pulse, not fading to black
+     (11, "abcdefghijklmnopqrrqponmlkjihgfedcba"),
+     // Testing
+     (63, "a")];
+
+/// The strings and current values of all light styles.
+///
+/// Cached surfaces remember the values of the styles they were built with,
+/// so a style changing its value makes every surface using it dirty, and
+/// they are rebuilt the next time they are drawn.
+#[derive(Debug, Clone)]
+pub struct LightStyles {
+    styles: Vec<String>,
+    values: Vec<i32>,
+}
+
+impl Default for LightStyles {
+    fn default() -> LightStyles {
+        LightStyles::new()
+    }
+}
+
+impl LightStyles {
+    /// Creates the default styles, at time 0.
+    pub fn new() -> LightStyles {
+        let mut styles = LightStyles {
+            styles: vec![String::new(); MAX_STYLES],
+            values: vec![NORMAL_STYLE_VALUE; MAX_STYLES],
+        };
+        for &(style, pattern) in &DEFAULT_STYLES {
+            styles.set(style, pattern);
+        }
+        styles.animate(0.0);
+        styles
+    }
+
+    /// Sets the string of a style, like the `lightstyle` builtin. It takes
+    /// effect with the next `animate`. Styles out of range are ignored.
+    pub fn set(&mut self, style: usize, pattern: &str) {
+        if let Some(s) = self.styles.get_mut(style) {
+            *s = pattern.to_string();
+        }
+    }
+
+    /// The string of a style, empty if it isn't set.
+    pub fn get(&self, style: usize) -> &str {
+        self.styles.get(style).map_or("", |s| s.as_str())
+    }
+
+    /// Updates the values of all styles for `time` seconds into the game.
+    pub fn animate(&mut self, time: f32) {
+        let frame = (time.max(0.0) * STYLE_FPS) as usize;
+        for (value, pattern) in self.values.iter_mut().zip(&self.styles) {
+            let pattern = pattern.as_bytes();
+            *value = if pattern.is_empty() {
+                UNSET_STYLE_VALUE
+            } else {
+                (pattern[frame % pattern.len()] as i32 - b'a' as i32) * 22
+            };
+        }
+    }
+
+    /// The current value of a style. `NORMAL_STYLE_VALUE` is normal
+    /// brightness; faces referring to styles that don't exist get that.
+    pub fn value(&self, style: u8) -> i32 {
+        self.values.get(style as usize).cloned().unwrap_or(NORMAL_STYLE_VALUE)
+    }
+
+    /// The current values of all styles.
+    pub fn values(&self) -> &[i32] {
+        &self.values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use render::surface::NORMAL_STYLE_VALUE;
+    use super::{LightStyles, UNSET_STYLE_VALUE};
+
+    #[test]
+    fn default_styles() {
+        let styles = LightStyles::new();
+        assert_eq!(styles.value(0), NORMAL_STYLE_VALUE);
+        assert_eq!(styles.get(4), "mamamamamama");
+        assert_eq!(styles.value(2), 0);
+        assert_eq!(styles.value(12), UNSET_STYLE_VALUE);
+        assert_eq!(styles.value(63), 0);
+        assert_eq!(styles.value(200), NORMAL_STYLE_VALUE);
+    }
+
+    #[test]
+    fn animates_at_ten_hertz() {
+        let mut styles = LightStyles::new();
+        styles.animate(0.25);
+        // Third letter of the slow pulse and the fast strobe
+        assert_eq!(styles.value(2), 2 * 22);
+        assert_eq!(styles.value(4), 12 * 22);
+        styles.animate(0.35);
+        assert_eq!(styles.value(4), 0);
+        // Wraps around after 12 frames
+        styles.animate(1.25);
+        assert_eq!(styles.value(4), 12 * 22);
+
+        styles.set(4, "z");
+        assert_eq!(styles.value(4), 12 * 22);
+        styles.animate(1.25);
+        assert_eq!(styles.value(4), 25 * 22);
+        styles.set(4, "");
+        styles.set(64, "a");
+        styles.animate(1.25);
+        assert_eq!(styles.values()[4], UNSET_STYLE_VALUE);
+        assert_eq!(styles.values().len(), 64);
+    }
+}
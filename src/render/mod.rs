@@ -0,0 +1,23 @@
+//! The software renderer for the 3D world.
+
+pub mod alias;
+pub mod animation;
+pub mod cache;
+pub mod camera;
+pub mod dlight;
+pub mod edges;
+pub mod frustum;
+pub mod lightstyle;
+pub mod particles;
+pub mod renderer;
+pub mod sky;
+pub mod sprite;
+pub mod surface;
+pub mod world;
+
+pub use self::alias::{AliasLight, AliasModel, AliasPose};
+pub use self::camera::{Camera, ClipVertex, View};
+pub use self::particles::ParticleSystem;
+pub use self::renderer::{DrawMode, Renderer};
+pub use self::sprite::{SpriteModel, SpritePose};
+pub use self::world::World;
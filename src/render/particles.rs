@@ -0,0 +1,610 @@
+//! Particle effects, like `r_part.c` and `d_part.c`: explosions, blood,
+//! trails and splashes. Particles are points that move, fall and cycle
+//! through color ramps until they die, drawn as small squares that shrink
+//! with distance and are depth tested against the world.
+//!
+//! Random numbers come from a generator seeded by the caller, so the same
+//! seed and calls always give the same particles.
+
+use drawing::Framebuffer;
+use drawing::raster::depth_value;
+use files::mdl::{NUM_VERTEX_NORMALS, VERTEX_NORMALS};
+use render::View;
+use util::Vec3;
+use hprof;
+
+/// Default maximum number of particles, like `MAX_PARTICLES`.
+pub const MAX_PARTICLES: usize = 2048;
+/// Default gravity, like `sv_gravity`.
+pub const GRAVITY: f32 = 800.0;
+
+/// Colors of the fast explosion particles.
+const RAMP1: [u8; 8] = [0x6f, 0x6d, 0x6b, 0x69, 0x67, 0x65, 0x63, 0x61];
+/// Colors of the slow explosion particles.
+const RAMP2: [u8; 8] = [0x6f, 0x6e, 0x6d, 0x6c, 0x6b, 0x6a, 0x68, 0x66];
+/// Colors of fire and smoke.
+const RAMP3: [u8; 6] = [0x6d, 0x6b, 6, 5, 4, 3];
+
+/// Particles closer than this to the camera aren't drawn.
+const PARTICLE_Z_CLIP: f32 = 8.0;
+/// The size of a particle in world units, roughly.
+const PARTICLE_SIZE: f32 = 1.6;
+/// Particles are drawn at most this many pixels wide per 320 pixels of
+/// screen width.
+const MAX_PIXELS_PER_320: usize = 4;
+/// Distance between trail particles.
+const TRAIL_SPACING: f32 = 3.0;
+
+/// How a particle moves and changes its color.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ParticleKind {
+    /// Stays where it is.
+    Static,
+    /// Falls.
+    Grav,
+    /// Falls, for splashes and bullet impacts.
+    SlowGrav,
+    /// Rises and turns from fire into smoke.
+    Fire,
+    /// Speeds up and fades quickly, for explosions.
+    Explode,
+    /// Slows down and fades slowly, for explosions.
+    Explode2,
+    /// Speeds up, for the blob explosions of tarbabies.
+    Blob,
+    /// Slows down horizontally.
+    Blob2,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Particle {
+    pub origin: Vec3,
+    pub velocity: Vec3,
+    pub color: u8,
+    /// Position in the color ramp, for fire and explosions.
+    pub ramp: f32,
+    /// Time at which the particle is removed.
+    pub die: f32,
+    pub kind: ParticleKind,
+}
+
+impl Particle {
+    fn new(origin: Vec3, color: u8, die: f32, kind: ParticleKind) -> Particle {
+        Particle {
+            origin: origin,
+            velocity: Vec3::zero(),
+            color: color,
+            ramp: 0.0,
+            die: die,
+            kind: kind,
+        }
+    }
+}
+
+/// The kinds of trails left by moving entities, in the order of the model
+/// flags they belong to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TrailKind {
+    Rocket,
+    /// Grenades.
+    Smoke,
+    /// Gibs.
+    Blood,
+    /// Scrag spit.
+    Tracer,
+    /// Zombie gibs.
+    SlightBlood,
+    /// Hell knight spikes.
+    Tracer2,
+    /// Vore balls.
+    VoorTrail,
+}
+
+/// A linear congruential generator like the `rand` of the C library, giving
+/// numbers from 0 to 32767.
+#[derive(Debug, Clone)]
+struct Random {
+    state: u32,
+}
+
+impl Random {
+    fn new(seed: u32) -> Random {
+        Random { state: seed }
+    }
+
+    fn next(&mut self) -> i32 {
+        self.state = self.state.wrapping_mul(1103515245).wrapping_add(12345);
+        ((self.state >> 16) & 0x7fff) as i32
+    }
+
+    /// A vector with every component from `-spread / 2` to below `spread / 2`.
+    fn spread(&mut self, spread: i32) -> Vec3 {
+        let mut v = Vec3::zero();
+        for i in 0..3 {
+            v[i] = (self.next() % spread - spread / 2) as f32;
+        }
+        v
+    }
+}
+
+/// All live particles.
+#[derive(Debug, Clone)]
+pub struct ParticleSystem {
+    particles: Vec<Particle>,
+    max: usize,
+    random: Random,
+    /// Angular velocities of the particles around entities with
+    /// `EF_BRIGHTFIELD`, one per vertex normal, chosen on first use.
+    avelocities: Vec<Vec3>,
+    /// Alternates the colors and directions of tracers.
+    tracer_count: u32,
+}
+
+impl ParticleSystem {
+    /// A system of at most `max` particles, with random numbers from
+    /// `seed`. New particles are dropped while it is full.
+    pub fn new(max: usize, seed: u32) -> ParticleSystem {
+        ParticleSystem {
+            particles: Vec::with_capacity(max),
+            max: max,
+            random: Random::new(seed),
+            avelocities: Vec::new(),
+            tracer_count: 0,
+        }
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+    }
+
+    fn add(&mut self, particle: Particle) {
+        if self.particles.len() < self.max {
+            self.particles.push(particle);
+        }
+    }
+
+    /// The burst of a rocket or grenade exploding at `time`, like
+    /// `R_ParticleExplosion`.
+    pub fn explosion(&mut self, origin: Vec3, time: f32) {
+        for i in 0..1024 {
+            let kind = if i & 1 == 0 { ParticleKind::Explode } else { ParticleKind::Explode2 };
+            let mut p = Particle::new(Vec3::zero(), RAMP1[0], time + 5.0, kind);
+            p.ramp = (self.random.next() & 3) as f32;
+            p.origin = origin + self.random.spread(32);
+            p.velocity = self.random.spread(512);
+            self.add(p);
+        }
+    }
+
+    /// The purple and yellow explosion of a tarbaby, like
+    /// `R_BlobExplosion`.
+    pub fn blob_explosion(&mut self, origin: Vec3, time: f32) {
+        for i in 0..1024 {
+            let die = time + 1.0 + (self.random.next() & 8) as f32 * 0.05;
+            let (kind, base) = if i & 1 == 0 {
+                (ParticleKind::Blob, 66)
+            } else {
+                (ParticleKind::Blob2, 150)
+            };
+            let color = base + (self.random.next() % 6) as u8;
+            let mut p = Particle::new(Vec3::zero(), color, die, kind);
+            p.origin = origin + self.random.spread(32);
+            p.velocity = self.random.spread(512);
+            self.add(p);
+        }
+    }
+
+    /// `count` particles of `color` flying in `direction`, for blood and
+    /// bullet impacts, like `R_RunParticleEffect`. A count of 1024 is an
+    /// explosion.
+    pub fn effect(&mut self, origin: Vec3, direction: Vec3, color: u8, count: usize, time: f32) {
+        if count == 1024 {
+            self.explosion(origin, time);
+            return;
+        }
+        for _ in 0..count {
+            let die = time + 0.1 * (self.random.next() % 5) as f32;
+            let color = (color & !7) + (self.random.next() & 7) as u8;
+            let mut p = Particle::new(Vec3::zero(), color, die, ParticleKind::SlowGrav);
+            let mut offset = Vec3::zero();
+            for i in 0..3 {
+                offset[i] = ((self.random.next() & 15) - 8) as f32;
+            }
+            p.origin = origin + offset;
+            p.velocity = direction * 15.0;
+            self.add(p);
+        }
+    }
+
+    /// Lava bursting up around `origin`, like `R_LavaSplash`.
+    pub fn lava_splash(&mut self, origin: Vec3, time: f32) {
+        for i in -16..16 {
+            for j in -16..16 {
+                let die = time + 2.0 + (self.random.next() & 31) as f32 * 0.02;
+                let color = 224 + (self.random.next() & 7) as u8;
+                let mut p = Particle::new(Vec3::zero(), color, die, ParticleKind::SlowGrav);
+                let dir = Vec3::new((j * 8 + (self.random.next() & 7)) as f32,
+                                    (i * 8 + (self.random.next() & 7)) as f32,
+                                    256.0);
+                p.origin = Vec3::new(origin.x + dir.x,
+                                     origin.y + dir.y,
+                                     origin.z + (self.random.next() & 63) as f32);
+                let speed = (50 + (self.random.next() & 63)) as f32;
+                p.velocity = dir.normalize() * speed;
+                self.add(p);
+            }
+        }
+    }
+
+    /// The sparkles of a teleporter, like `R_TeleportSplash`.
+    pub fn teleport_splash(&mut self, origin: Vec3, time: f32) {
+        for i in (-16..16).step_by(4) {
+            for j in (-16..16).step_by(4) {
+                for k in (-24..32).step_by(4) {
+                    let die = time + 0.2 + (self.random.next() & 7) as f32 * 0.02;
+                    let color = 7 + (self.random.next() & 7) as u8;
+                    let mut p = Particle::new(Vec3::zero(), color, die, ParticleKind::SlowGrav);
+                    let dir = Vec3::new((j * 8) as f32, (i * 8) as f32, (k * 8) as f32);
+                    let mut jitter = Vec3::zero();
+                    for n in 0..3 {
+                        jitter[n] = (self.random.next() & 3) as f32;
+                    }
+                    p.origin = origin + Vec3::new(i as f32, j as f32, k as f32) + jitter;
+                    let speed = (50 + (self.random.next() & 63)) as f32;
+                    p.velocity = dir.normalize() * speed;
+                    self.add(p);
+                }
+            }
+        }
+    }
+
+    /// A trail from `start` to `end`, where an entity moved in the last
+    /// frame, like `R_RocketTrail`.
+    pub fn trail(&mut self, start: Vec3, end: Vec3, kind: TrailKind, time: f32) {
+        let delta = end - start;
+        let mut length = delta.length();
+        let dir = delta.normalize();
+        let mut pos = start;
+        while length > 0.0 {
+            length -= TRAIL_SPACING;
+            let mut p = Particle::new(pos, 0, time + 2.0, ParticleKind::Static);
+            match kind {
+                TrailKind::Rocket | TrailKind::Smoke => {
+                    let first = if kind == TrailKind::Rocket { 0 } else { 2 };
+                    let ramp = first + (self.random.next() & 3) as usize;
+                    p.ramp = ramp as f32;
+                    p.color = RAMP3[ramp];
+                    p.kind = ParticleKind::Fire;
+                    p.origin = pos + self.random.spread(6);
+                }
+                TrailKind::Blood | TrailKind::SlightBlood => {
+                    p.kind = ParticleKind::Grav;
+                    p.color = 67 + (self.random.next() & 3) as u8;
+                    p.origin = pos + self.random.spread(6);
+                    if kind == TrailKind::SlightBlood {
+                        length -= TRAIL_SPACING;
+                    }
+                }
+                TrailKind::Tracer | TrailKind::Tracer2 => {
+                    p.die = time + 0.5;
+                    let base = if kind == TrailKind::Tracer { 52 } else { 230 };
+                    p.color = base + ((self.tracer_count & 4) << 1) as u8;
+                    self.tracer_count += 1;
+                    // Alternately to the left and right
+                    let side = Vec3::new(30.0 * dir.y, -30.0 * dir.x, 0.0);
+                    p.velocity = if self.tracer_count & 1 != 0 { side } else { -side };
+                }
+                TrailKind::VoorTrail => {
+                    p.color = 9 * 16 + 8 + (self.random.next() & 3) as u8;
+                    p.die = time + 0.3;
+                    let mut offset = Vec3::zero();
+                    for i in 0..3 {
+                        offset[i] = ((self.random.next() & 15) - 8) as f32;
+                    }
+                    p.origin = pos + offset;
+                }
+            }
+            self.add(p);
+            pos += dir * TRAIL_SPACING;
+        }
+    }
+
+    /// The swirling field around an entity with `EF_BRIGHTFIELD`, like
+    /// `R_EntityParticles`. The particles only live for this frame.
+    pub fn entity_field(&mut self, origin: Vec3, time: f32) {
+        const DISTANCE: f32 = 64.0;
+        const BEAM_LENGTH: f32 = 16.0;
+        if self.avelocities.is_empty() {
+            for _ in 0..NUM_VERTEX_NORMALS {
+                let mut v = Vec3::zero();
+                for i in 0..3 {
+                    v[i] = (self.random.next() & 255) as f32 * 0.01;
+                }
+                self.avelocities.push(v);
+            }
+        }
+        let positions: Vec<Vec3> =
+            self.avelocities
+                .iter()
+                .zip(VERTEX_NORMALS.iter())
+                .map(|(av, n)| {
+                    let (sy, cy) = (time * av.x).sin_cos();
+                    let (sp, cp) = (time * av.y).sin_cos();
+                    let forward = Vec3::new(cp * cy, cp * sy, -sp);
+                    let normal = Vec3::new(n[0], n[1], n[2]);
+                    origin + normal * DISTANCE + forward * BEAM_LENGTH
+                })
+                .collect();
+        for position in positions {
+            self.add(Particle::new(position, RAMP1[0], time + 0.01, ParticleKind::Explode));
+        }
+    }
+
+    /// Removes the particles that died before `time` and moves the others
+    /// by `frametime` seconds, like the second half of `R_DrawParticles`.
+    pub fn update(&mut self, time: f32, frametime: f32, gravity: f32) {
+        let _guard = hprof::enter("ParticleSystem::update");
+        self.particles.retain(|p| p.die >= time);
+
+        let time1 = frametime * 5.0;
+        let time2 = frametime * 10.0;
+        let time3 = frametime * 15.0;
+        let grav = frametime * gravity * 0.05;
+        let dvel = frametime * 4.0;
+        for p in &mut self.particles {
+            p.origin += p.velocity * frametime;
+            match p.kind {
+                ParticleKind::Static => {}
+                ParticleKind::Fire => {
+                    p.ramp += time1;
+                    if p.ramp >= RAMP3.len() as f32 {
+                        p.die = -1.0;
+                    } else {
+                        p.color = RAMP3[p.ramp as usize];
+                    }
+                    p.velocity.z += grav;
+                }
+                ParticleKind::Explode => {
+                    p.ramp += time2;
+                    if p.ramp >= RAMP1.len() as f32 {
+                        p.die = -1.0;
+                    } else {
+                        p.color = RAMP1[p.ramp as usize];
+                    }
+                    p.velocity += p.velocity * dvel;
+                    p.velocity.z -= grav;
+                }
+                ParticleKind::Explode2 => {
+                    p.ramp += time3;
+                    if p.ramp >= RAMP2.len() as f32 {
+                        p.die = -1.0;
+                    } else {
+                        p.color = RAMP2[p.ramp as usize];
+                    }
+                    p.velocity -= p.velocity * frametime;
+                    p.velocity.z -= grav;
+                }
+                ParticleKind::Blob => {
+                    p.velocity += p.velocity * dvel;
+                    p.velocity.z -= grav;
+                }
+                ParticleKind::Blob2 => {
+                    p.velocity.x -= p.velocity.x * dvel;
+                    p.velocity.y -= p.velocity.y * dvel;
+                    p.velocity.z -= grav;
+                }
+                ParticleKind::Grav | ParticleKind::SlowGrav => p.velocity.z -= grav,
+            }
+        }
+    }
+
+    /// Draws the particles as squares, depth tested against and written
+    /// into the z-buffer, like `D_DrawParticle`.
+    pub fn draw(&self, framebuffer: &mut Framebuffer, view: &View) {
+        let _guard = hprof::enter("ParticleSystem::draw");
+        let (width, height) = (framebuffer.width(), framebuffer.height());
+        let min_size = (width / 320).max(1);
+        let max_size = (width * MAX_PIXELS_PER_320 / 320).max(1);
+        let (pixels, mut zbuffer) = framebuffer.buffers_mut();
+        for p in &self.particles {
+            let pos = view.transform(p.origin);
+            if pos.z < PARTICLE_Z_CLIP {
+                continue;
+            }
+            let (x, y) = view.project(pos);
+            if x < 0.0 || y < 0.0 || x >= width as f32 || y >= height as f32 {
+                continue;
+            }
+            let size = ((PARTICLE_SIZE * view.scale / pos.z) as usize).clamp(min_size, max_size);
+            let depth = depth_value(1.0 / pos.z);
+            let x0 = (x as usize).saturating_sub(size / 2);
+            let y0 = (y as usize).saturating_sub(size / 2);
+            for py in y0..(y0 + size).min(height) {
+                for px in x0..(x0 + size).min(width) {
+                    let i = py * width + px;
+                    if let Some(ref mut z) = zbuffer {
+                        if depth < z[i] {
+                            continue;
+                        }
+                        z[i] = depth;
+                    }
+                    pixels[i] = p.color;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drawing::{Framebuffer, Palette, Shading, Vertex};
+    use render::{Camera, View};
+    use util::Vec3;
+    use super::{GRAVITY, Particle, ParticleKind, ParticleSystem, RAMP1, RAMP3, TrailKind};
+
+    fn system() -> ParticleSystem {
+        ParticleSystem::new(4096, 1)
+    }
+
+    #[test]
+    fn deterministic_and_limited() {
+        let origin = Vec3::new(10.0, 20.0, 30.0);
+        let mut a = system();
+        let mut b = system();
+        a.explosion(origin, 1.0);
+        b.explosion(origin, 1.0);
+        assert_eq!(a.len(), 1024);
+        assert_eq!(a.particles(), b.particles());
+        let mut c = ParticleSystem::new(4096, 2);
+        c.explosion(origin, 1.0);
+        assert!(a.particles() != c.particles());
+
+        for p in a.particles() {
+            let offset = p.origin - origin;
+            assert!((0..3).all(|i| offset[i] >= -16.0 && offset[i] < 16.0));
+            assert_eq!((p.color, p.die), (RAMP1[0], 6.0));
+        }
+        assert_eq!(a.particles()[0].kind, ParticleKind::Explode);
+        assert_eq!(a.particles()[1].kind, ParticleKind::Explode2);
+
+        // New particles are dropped while full
+        let mut full = ParticleSystem::new(100, 1);
+        full.explosion(origin, 0.0);
+        full.lava_splash(origin, 0.0);
+        assert_eq!(full.len(), 100);
+        full.clear();
+        assert!(full.is_empty());
+    }
+
+    #[test]
+    fn effects() {
+        let origin = Vec3::zero();
+        let mut s = system();
+        s.effect(origin, Vec3::new(0.0, 0.0, 1.0), 73, 20, 0.0);
+        assert_eq!(s.len(), 20);
+        assert!(s.particles().iter().all(|p| p.color >= 72 && p.color < 80));
+        assert!(s.particles().iter().all(|p| p.velocity == Vec3::new(0.0, 0.0, 15.0)));
+        s.effect(origin, Vec3::zero(), 0, 1024, 0.0);
+        assert_eq!(s.len(), 20 + 1024);
+
+        let mut s = system();
+        s.lava_splash(origin, 0.0);
+        assert_eq!(s.len(), 1024);
+        assert!(s.particles().iter().all(|p| p.velocity.z > 0.0 && p.color >= 224));
+        let mut s = system();
+        s.teleport_splash(origin, 0.0);
+        assert_eq!(s.len(), 8 * 8 * 14);
+        let mut s = system();
+        s.blob_explosion(origin, 0.0);
+        assert_eq!(s.len(), 1024);
+        let mut s = system();
+        s.entity_field(origin, 0.5);
+        assert_eq!(s.len(), 162);
+        // 64 units out along the normal, and 16 more in some direction
+        let d = s.particles()[0].origin.length();
+        assert!((48.0 - 1e-3..=80.0 + 1e-3).contains(&d));
+    }
+
+    #[test]
+    fn trails() {
+        let start = Vec3::zero();
+        let end = Vec3::new(30.0, 0.0, 0.0);
+        let mut s = system();
+        s.trail(start, end, TrailKind::Rocket, 0.0);
+        assert_eq!(s.len(), 10);
+        assert!(s.particles().iter().all(|p| p.kind == ParticleKind::Fire));
+        s.clear();
+        s.trail(start, end, TrailKind::Smoke, 0.0);
+        assert!(s.particles().iter().all(|p| p.ramp >= 2.0 && RAMP3[p.ramp as usize] == p.color));
+        s.clear();
+        s.trail(start, end, TrailKind::SlightBlood, 0.0);
+        assert_eq!(s.len(), 5);
+
+        s.clear();
+        s.trail(start, end, TrailKind::Tracer, 0.0);
+        let p = s.particles();
+        assert_eq!((p[0].origin, p[1].origin), (start, Vec3::new(3.0, 0.0, 0.0)));
+        assert_eq!((p[0].velocity, p[1].velocity),
+                   (Vec3::new(0.0, -30.0, 0.0), Vec3::new(0.0, 30.0, 0.0)));
+        assert_eq!((p[0].color, p[4].color), (52, 60));
+    }
+
+    #[test]
+    fn ramps_gravity_and_death() {
+        let mut s = system();
+        s.add(Particle::new(Vec3::zero(), 0, 1.0, ParticleKind::Grav));
+        s.add(Particle::new(Vec3::zero(), RAMP3[0], 1.0, ParticleKind::Fire));
+        s.add(Particle::new(Vec3::zero(), RAMP1[0], 1.0, ParticleKind::Explode));
+        s.add(Particle::new(Vec3::zero(), 0, 0.05, ParticleKind::Static));
+
+        s.update(0.0, 0.1, GRAVITY);
+        let p = s.particles();
+        assert_eq!(p[0].velocity.z, -4.0);
+        assert_eq!(p[1].velocity.z, 4.0);
+        assert_eq!((p[1].ramp, p[1].color), (0.5, RAMP3[0]));
+        assert_eq!((p[2].ramp, p[2].color), (1.0, RAMP1[1]));
+
+        s.update(0.1, 0.1, GRAVITY);
+        // The static particle died, the others fell
+        assert_eq!(s.len(), 3);
+        assert!((s.particles()[0].origin.z + 0.4).abs() < 1e-6);
+
+        // Past the end of their ramps, fire and explosions die
+        for i in 0..12 {
+            s.update(0.2 + i as f32 * 0.05, 0.1, GRAVITY);
+        }
+        assert_eq!(s.len(), 1);
+        assert_eq!(s.particles()[0].kind, ParticleKind::Grav);
+        s.update(1.5, 0.1, GRAVITY);
+        assert!(s.is_empty());
+    }
+
+    #[test]
+    fn drawn_as_depth_tested_squares() {
+        let camera = Camera::new(Vec3::zero(), Vec3::zero());
+        let mut fb = Framebuffer::with_palette(320, 240, Palette::from_bytes(&[0; 768]));
+        fb.enable_zbuffer();
+        let view = View::new(&camera, fb.width(), fb.height());
+        // A wall at depth 100 on the right half of the screen
+        let wall = [Vertex::new(160.0, 0.0, 100.0, 0.0, 0.0),
+                    Vertex::new(320.0, 0.0, 100.0, 0.0, 0.0),
+                    Vertex::new(320.0, 240.0, 100.0, 0.0, 0.0),
+                    Vertex::new(160.0, 240.0, 100.0, 0.0, 0.0)];
+        fb.polygon(&wall, Shading::Flat(1));
+
+        let mut s = system();
+        // In front of the wall, behind it, close by and too close
+        s.add(Particle::new(Vec3::new(50.0, -20.0, 0.0), 10, 1.0, ParticleKind::Static));
+        s.add(Particle::new(Vec3::new(200.0, -100.0, 0.0), 20, 1.0, ParticleKind::Static));
+        s.add(Particle::new(Vec3::new(50.0, 40.0, 0.0), 30, 1.0, ParticleKind::Static));
+        s.add(Particle::new(Vec3::new(16.0, 0.0, 0.0), 40, 1.0, ParticleKind::Static));
+        s.add(Particle::new(Vec3::new(4.0, 0.0, 0.0), 50, 1.0, ParticleKind::Static));
+        s.draw(&mut fb, &view);
+
+        let count = |color: u8| fb.pixels().iter().filter(|&&p| p == color).count();
+        // 1.6 units at a distance of 50 are 5 pixels, but at most 4
+        assert_eq!(count(10), 16);
+        assert_eq!(fb.get(224, 120), 10);
+        assert_eq!(count(20), 0);
+        assert_eq!(count(30), 16);
+        assert_eq!(count(40), 16);
+        assert_eq!(count(50), 0);
+        // Far away particles are a single pixel
+        let mut s = system();
+        s.add(Particle::new(Vec3::new(1000.0, 300.0, 0.0), 60, 1.0, ParticleKind::Static));
+        s.draw(&mut fb, &view);
+        assert_eq!(fb.pixels().iter().filter(|&&p| p == 60).count(), 1);
+    }
+}
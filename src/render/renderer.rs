@@ -0,0 +1,949 @@
+use std::fmt;
+use std::rc::Rc;
+
+use drawing::{Colormap, Framebuffer, Shading, Texture, Vertex};
+use drawing::raster::{ScreenShader, depth_value};
+use files::bsp::{Bsp, MAX_LIGHTSTYLES, NodeChild, TexInfo, TextureKind, WorldTexture};
+use render::{AliasModel, AliasPose, Camera, ClipVertex, ParticleSystem, SpriteModel, SpritePose,
+             View, World};
+use render::cache::{SurfaceCache, SurfaceState, cache_size_for};
+use render::dlight::DynamicLights;
+use render::edges::{EdgeList, EdgeStats, Gradient, Span};
+use render::frustum::{ALL_PLANES, Frustum};
+use render::lightstyle::LightStyles;
+use render::sky::SkyView;
+use render::surface::{SurfaceExtents, add_dynamic_lights, average_level, blocklights,
+                      build_surface};
+use util::Vec3;
+use hprof;
+
+/// Minimum number of screen pixels per texel for each mip level, like
+/// `d_scalemip` in the original renderer.
+const MIP_SCALES: [f32; 3] = [1.0, 0.4, 0.2];
+
+/// How world surfaces are rasterized.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DrawMode {
+    /// Every face is drawn as a z-buffered polygon. Simple, but pixels
+    /// hidden behind nearer faces are drawn too.
+    Polygons,
+    /// Faces are sorted into non-overlapping spans first, so every pixel is
+    /// drawn once.
+    EdgeSorted,
+}
+
+/// Counts of the work done for the last frame.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct RenderStats {
+    pub edges: usize,
+    pub surfaces: usize,
+    /// Spans drawn, only counted in `DrawMode::EdgeSorted`.
+    pub spans: usize,
+    /// Surfaces that weren't in the surface cache and had to be built.
+    pub built: usize,
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{} edges, {} surfaces, {} spans, {} surfaces built",
+               self.edges,
+               self.surfaces,
+               self.spans,
+               self.built)
+    }
+}
+
+impl RenderStats {
+    /// Reports the counts to the hprof profiler, so they are printed by
+    /// `print_timing` with the timings. Every count is a node below the
+    /// current one that is called once per counted item, e.g.
+    /// `RenderStats::edges - 1234 * ...`. The calls are nested and then left
+    /// again, but that still reads the clock twice per counted item, so the
+    /// renderer only does it when asked to, see `Renderer::set_profile_stats`.
+    pub fn profile(&self) {
+        let profiler = hprof::profiler();
+        let counts = [("RenderStats::edges", self.edges),
+                      ("RenderStats::surfaces", self.surfaces),
+                      ("RenderStats::spans", self.spans),
+                      ("RenderStats::built", self.built)];
+        for &(name, count) in &counts {
+            for _ in 0..count {
+                profiler.enter_noguard(name);
+            }
+            for _ in 0..count {
+                profiler.leave();
+            }
+        }
+    }
+}
+
+impl From<EdgeStats> for RenderStats {
+    fn from(stats: EdgeStats) -> RenderStats {
+        RenderStats {
+            edges: stats.edges,
+            surfaces: stats.surfaces,
+            spans: stats.spans,
+            built: 0,
+        }
+    }
+}
+
+/// What a face is textured with.
+enum FaceTexture<'a> {
+    /// A lit surface from the surface cache.
+    Surface(Rc<Texture>),
+    /// A texture shaded with a single light level, for faces too large for
+    /// the surface cache.
+    Lit(&'a Texture, usize),
+    /// Water, slime and lava: unlit and warped.
+    Turbulent(&'a Texture),
+    /// The sky layers, as seen through the face.
+    Sky,
+    /// An unlit texture, for sky faces in maps without sky layers.
+    Unlit(&'a Texture),
+}
+
+impl<'a> FaceTexture<'a> {
+    fn shading<'b>(&'b self, colormap: &'b Colormap, frame: &'b Frame) -> Shading<'b> {
+        match *self {
+            FaceTexture::Surface(ref surface) => Shading::Surface(surface),
+            FaceTexture::Lit(texture, level) => Shading::Lit(texture, colormap.row(level)),
+            FaceTexture::Turbulent(texture) => Shading::Turbulent(texture, frame.time),
+            FaceTexture::Sky => {
+                match frame.sky {
+                    Some(ref sky) => Shading::Screen(sky),
+                    None => Shading::Flat(0),
+                }
+            }
+            FaceTexture::Unlit(texture) => Shading::Perspective(texture),
+        }
+    }
+
+    /// The color of pixel (x, y), which shows texture coordinates (s, t).
+    #[inline]
+    fn sample(&self, colormap: &Colormap, frame: &Frame, x: usize, y: usize, s: f32, t: f32) -> u8 {
+        match *self {
+            FaceTexture::Surface(ref surface) => surface.sample_clamped(s, t),
+            FaceTexture::Lit(texture, level) => colormap.shade(texture.sample(s, t), level),
+            FaceTexture::Turbulent(texture) => texture.sample_turbulent(s, t, frame.time),
+            FaceTexture::Sky => frame.sky.as_ref().map_or(0, |sky| sky.color(x, y)),
+            FaceTexture::Unlit(texture) => texture.sample(s, t),
+        }
+    }
+
+    fn is_turbulent(&self) -> bool {
+        matches!(*self, FaceTexture::Turbulent(_))
+    }
+}
+
+/// What special surfaces are drawn with in a frame.
+struct Frame<'a> {
+    time: f32,
+    sky: Option<SkyView<'a>>,
+}
+
+/// A face that survived culling and clipping, ready to be rasterized.
+struct PreparedFace<'a> {
+    /// Screen space vertices, with texture coordinates at the mip level.
+    vertices: Vec<Vertex>,
+    texture: FaceTexture<'a>,
+    /// `1 / z`, `s / z` and `t / z` over the screen.
+    izi: Gradient,
+    sz: Gradient,
+    tz: Gradient,
+}
+
+/// Draws the world from a camera into a framebuffer.
+///
+/// Only the faces of leafs in the potentially visible set of the camera's
+/// leaf that intersect the view volume are drawn. Faces are clipped to the
+/// view volume and drawn with perspective correct textures, lit by their
+/// lightmaps and the dynamic lights through the surface cache. The z-buffer
+/// is filled for the models drawn afterwards.
+pub struct Renderer {
+    colormap: Colormap,
+    mode: DrawMode,
+    stats: RenderStats,
+    surfaces: SurfaceCache,
+    light_styles: LightStyles,
+    dynamic_lights: DynamicLights,
+    /// The dynamic lights reaching each face in the current frame.
+    dlight_bits: Vec<u32>,
+    water_alpha: f32,
+    profile_stats: bool,
+}
+
+impl Renderer {
+    pub fn new(colormap: Colormap) -> Renderer {
+        Renderer {
+            colormap: colormap,
+            mode: DrawMode::EdgeSorted,
+            stats: RenderStats::default(),
+            surfaces: SurfaceCache::new(cache_size_for(320, 200)),
+            light_styles: LightStyles::new(),
+            dynamic_lights: DynamicLights::new(),
+            dlight_bits: Vec::new(),
+            water_alpha: 1.0,
+            profile_stats: false,
+        }
+    }
+
+    pub fn colormap(&self) -> &Colormap {
+        &self.colormap
+    }
+
+    pub fn mode(&self) -> DrawMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: DrawMode) {
+        self.mode = mode;
+    }
+
+    /// Statistics of the last rendered frame.
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    /// Whether the statistics of every frame are reported to the profiler,
+    /// see `RenderStats::profile`. Off by default.
+    pub fn set_profile_stats(&mut self, profile: bool) {
+        self.profile_stats = profile;
+    }
+
+    pub fn water_alpha(&self) -> f32 {
+        self.water_alpha
+    }
+
+    /// Sets the opacity of water, slime and lava, like `r_wateralpha`. Below
+    /// 1 they are drawn after the rest of the world, blended over it.
+    pub fn set_water_alpha(&mut self, alpha: f32) {
+        self.water_alpha = alpha.clamp(0.0, 1.0);
+    }
+
+    pub fn surface_cache(&self) -> &SurfaceCache {
+        &self.surfaces
+    }
+
+    /// Replaces the surface cache with an empty one of `size` bytes.
+    pub fn set_surface_cache_size(&mut self, size: usize) {
+        self.surfaces = SurfaceCache::new(size);
+    }
+
+    pub fn light_styles(&self) -> &LightStyles {
+        &self.light_styles
+    }
+
+    /// The light styles, e.g. to change them like the server does.
+    pub fn light_styles_mut(&mut self) -> &mut LightStyles {
+        &mut self.light_styles
+    }
+
+    pub fn dynamic_lights(&self) -> &DynamicLights {
+        &self.dynamic_lights
+    }
+
+    /// The dynamic lights, e.g. to add muzzle flashes and explosions.
+    pub fn dynamic_lights_mut(&mut self) -> &mut DynamicLights {
+        &mut self.dynamic_lights
+    }
+
+    /// Renders the world as seen from `camera`, `time` seconds into the
+    /// game (for animated textures and light styles). The z-buffer of `framebuffer` is
+    /// enabled and cleared; the pixels are only drawn over, so areas outside
+    /// of the world keep their previous contents.
+    pub fn render(&mut self,
+                  world: &World,
+                  camera: &Camera,
+                  framebuffer: &mut Framebuffer,
+                  time: f32) {
+        let _guard = hprof::enter("Renderer::render");
+        let view = View::new(camera, framebuffer.width(), framebuffer.height());
+        framebuffer.enable_zbuffer();
+        framebuffer.clear_zbuffer();
+        self.surfaces.begin_frame();
+        self.light_styles.animate(time);
+        self.dlight_bits = self.dynamic_lights.mark_faces(&world.bsp, time);
+
+        let faces: Vec<PreparedFace> = self.visible_faces(world, &view)
+                                           .into_iter()
+                                           .filter_map(|f| self.prepare_face(world, &view, f, time))
+                                           .collect();
+        let translucent = self.water_alpha < 1.0;
+        let (liquids, faces): (Vec<_>, Vec<_>) =
+            faces.into_iter().partition(|f| translucent && f.texture.is_turbulent());
+        let frame = Frame {
+            time: time,
+            sky: world.sky.as_ref().map(|sky| {
+                SkyView {
+                    sky: sky,
+                    view: &view,
+                    time: time,
+                }
+            }),
+        };
+        match self.mode {
+            DrawMode::Polygons => self.draw_polygons(&faces, &frame, framebuffer),
+            DrawMode::EdgeSorted => self.draw_edge_sorted(&faces, &frame, framebuffer),
+        }
+        self.draw_liquids(&liquids, &frame, framebuffer);
+        self.stats.built = self.surfaces.built();
+        if self.profile_stats {
+            self.stats.profile();
+        }
+    }
+
+    /// Draws an alias model into a framebuffer the world was just rendered
+    /// into, depth tested against it.
+    pub fn draw_model(&self,
+                      model: &AliasModel,
+                      pose: &AliasPose,
+                      camera: &Camera,
+                      framebuffer: &mut Framebuffer,
+                      time: f32) {
+        let view = View::new(camera, framebuffer.width(), framebuffer.height());
+        model.draw(framebuffer, &view, &self.colormap, pose, time);
+    }
+
+    /// Draws a sprite into a framebuffer the world was just rendered into,
+    /// depth tested against it.
+    pub fn draw_sprite(&self,
+                       sprite: &SpriteModel,
+                       pose: &SpritePose,
+                       camera: &Camera,
+                       framebuffer: &mut Framebuffer,
+                       time: f32) {
+        let view = View::new(camera, framebuffer.width(), framebuffer.height());
+        sprite.draw(framebuffer, &view, pose, time);
+    }
+
+    /// Draws particles into a framebuffer the world was just rendered into,
+    /// depth tested against it.
+    pub fn draw_particles(&self,
+                          particles: &ParticleSystem,
+                          camera: &Camera,
+                          framebuffer: &mut Framebuffer) {
+        let view = View::new(camera, framebuffer.width(), framebuffer.height());
+        particles.draw(framebuffer, &view);
+    }
+
+    /// Returns the indices of the world faces in visible leafs, in ascending
+    /// order.
+    fn visible_faces(&self, world: &World, view: &View) -> Vec<usize> {
+        let _guard = hprof::enter("Renderer::visible_faces");
+        let bsp = &world.bsp;
+        let view_leaf = bsp.point_in_leaf(view.origin);
+        let pvs = world.pvs.visible_from(view_leaf);
+
+        let mut leafs = Vec::new();
+        let root = NodeChild::Node(bsp.world().headnodes[0] as usize);
+        frustum_leafs(bsp, &view.frustum, root, ALL_PLANES, &mut leafs);
+
+        let mut marked = vec![false; bsp.faces.len()];
+        for &i in leafs.iter().filter(|&&i| i == view_leaf || pvs.contains(i)) {
+            let leaf = &bsp.leafs[i];
+            let marks = leaf.first_marksurface..leaf.first_marksurface + leaf.num_marksurfaces;
+            for &face in &bsp.marksurfaces[marks] {
+                marked[face] = true;
+            }
+        }
+
+        let world_model = bsp.world();
+        (world_model.first_face..world_model.first_face + world_model.num_faces)
+            .filter(|&f| marked[f])
+            .collect()
+    }
+
+    /// Culls back faces and invisible textures, clips the face to the view
+    /// volume and projects it.
+    fn prepare_face<'a>(&mut self,
+                        world: &'a World,
+                        view: &View,
+                        index: usize,
+                        time: f32)
+                        -> Option<PreparedFace<'a>> {
+        let bsp = &world.bsp;
+        let face = &bsp.faces[index];
+        let plane = &bsp.planes[face.plane];
+        let mut dist = view.origin.dot(plane.normal) - plane.dist;
+        if face.back_side {
+            dist = -dist;
+        }
+        if dist <= 0.0 {
+            return None;
+        }
+
+        let texinfo = &bsp.texinfo[face.texinfo];
+        let frame = world.textures.animation_frame(texinfo.miptex, time, false);
+        let texture = match world.textures.get(frame) {
+            Some(texture) if !texture.kind.is_invisible() => texture,
+            _ => return None,
+        };
+
+        let polygon = bsp.face_vertices(face)
+                         .into_iter()
+                         .map(|v| {
+                             ClipVertex {
+                                 pos: view.transform(v),
+                                 s: v.dot(texinfo.s) + texinfo.s_offset,
+                                 t: v.dot(texinfo.t) + texinfo.t_offset,
+                             }
+                         })
+                         .collect();
+        let polygon = view.clip_polygon(polygon);
+        if polygon.len() < 3 {
+            return None;
+        }
+
+        let nearest = polygon.iter().fold(f32::MAX, |z, v| z.min(v.pos.z));
+        let mip = match texture.kind {
+            // The turbulence is in texels of the full size texture
+            TextureKind::Turbulent => 0,
+            _ => mip_level(view.scale / nearest),
+        };
+        let mip_scale = 1.0 / (1 << mip) as f32;
+        let (face_texture, corner) = match texture.kind {
+            TextureKind::Normal => self.lit_surface(world, index, frame, texture, mip),
+            TextureKind::Turbulent => (FaceTexture::Turbulent(texture.mip(0)), [0.0, 0.0]),
+            TextureKind::Sky if world.sky.is_some() => (FaceTexture::Sky, [0.0, 0.0]),
+            _ => (FaceTexture::Unlit(texture.mip(mip)), [0.0, 0.0]),
+        };
+
+        let vertices = polygon.iter()
+                              .map(|v| {
+                                  let (x, y) = view.project(v.pos);
+                                  let s = (v.s - corner[0]) * mip_scale;
+                                  let t = (v.t - corner[1]) * mip_scale;
+                                  Vertex::new(x, y, v.pos.z, s, t)
+                              })
+                              .collect();
+        let (izi, sz, tz) = surface_gradients(view,
+                                              plane.normal,
+                                              plane.dist,
+                                              texinfo,
+                                              corner,
+                                              mip_scale);
+        Some(PreparedFace {
+            vertices: vertices,
+            texture: face_texture,
+            izi: izi,
+            sz: sz,
+            tz: tz,
+        })
+    }
+
+    /// Gets the lit surface of a face from the surface cache, building it if
+    /// needed. Also returns the texture coordinates of the surface's corner.
+    fn lit_surface<'a>(&mut self,
+                       world: &World,
+                       index: usize,
+                       frame: usize,
+                       texture: &'a WorldTexture,
+                       mip: usize)
+                       -> (FaceTexture<'a>, [f32; 2]) {
+        let bsp = &world.bsp;
+        let face = &bsp.faces[index];
+        let extents = SurfaceExtents::new(bsp, face);
+        let size = ((extents.extents[0] >> mip) * (extents.extents[1] >> mip)) as usize;
+        let light_styles = &self.light_styles;
+        let dynamic_lights = self.dynamic_lights.lights();
+        let dlight_bits = self.dlight_bits.get(index).cloned().unwrap_or(0);
+        let light = || {
+            let mut light = blocklights(bsp, face, &extents, light_styles.values());
+            if dlight_bits != 0 {
+                add_dynamic_lights(bsp, face, &extents, dynamic_lights, dlight_bits, &mut light);
+            }
+            light
+        };
+        if !self.surfaces.fits(size) {
+            return (FaceTexture::Lit(texture.mip(mip), average_level(&light())), [0.0, 0.0]);
+        }
+
+        let mut styles = [0; MAX_LIGHTSTYLES];
+        for (value, &style) in styles.iter_mut().zip(&face.styles) {
+            *value = light_styles.value(style);
+        }
+        let state = SurfaceState {
+            texture: frame,
+            styles: styles,
+            dynamic: dlight_bits != 0,
+        };
+        let colormap = &self.colormap;
+        let surface = self.surfaces.surface(index, mip, state, || {
+            let _guard = hprof::enter("Renderer::build_surface");
+            build_surface(texture.mip(mip), &extents, mip, &light(), colormap)
+        });
+        let corner = [extents.texture_mins[0] as f32, extents.texture_mins[1] as f32];
+        (FaceTexture::Surface(surface), corner)
+    }
+
+    fn draw_polygons(&mut self,
+                     faces: &[PreparedFace],
+                     frame: &Frame,
+                     framebuffer: &mut Framebuffer) {
+        let _guard = hprof::enter("Renderer::draw_polygons");
+        self.stats = RenderStats {
+            edges: faces.iter().map(|f| f.vertices.len()).sum(),
+            surfaces: faces.len(),
+            spans: 0,
+            built: 0,
+        };
+        for face in faces {
+            framebuffer.polygon(&face.vertices, face.texture.shading(&self.colormap, frame));
+        }
+    }
+
+    fn draw_edge_sorted(&mut self,
+                        faces: &[PreparedFace],
+                        frame: &Frame,
+                        framebuffer: &mut Framebuffer) {
+        let mut edges = EdgeList::new(framebuffer.width(), framebuffer.height());
+        {
+            let _guard = hprof::enter("Renderer::build_edges");
+            for face in faces {
+                let screen: Vec<(f32, f32)> = face.vertices.iter().map(|v| (v.x, v.y)).collect();
+                edges.add_polygon(&screen, face.izi);
+            }
+        }
+        let (spans, stats) = edges.scan();
+        self.stats = RenderStats::from(stats);
+
+        let _guard = hprof::enter("Renderer::draw_spans");
+        for (face, spans) in faces.iter().zip(&spans) {
+            draw_spans(framebuffer, spans, face, &self.colormap, frame);
+        }
+    }
+
+    /// Blends translucent liquids over the opaque faces, like
+    /// `R_DrawWaterSurfaces`. They still write the z-buffer, so models below
+    /// the surface stay hidden like in the software renderer.
+    fn draw_liquids(&mut self,
+                    faces: &[PreparedFace],
+                    frame: &Frame,
+                    framebuffer: &mut Framebuffer) {
+        if faces.is_empty() {
+            return;
+        }
+        let _guard = hprof::enter("Renderer::draw_liquids");
+        let table = framebuffer.blend_table(self.water_alpha);
+        for face in faces {
+            if let FaceTexture::Turbulent(texture) = face.texture {
+                framebuffer.polygon(&face.vertices,
+                                    Shading::TurbulentBlended(texture, frame.time, &table));
+            }
+        }
+        self.stats.edges += faces.iter().map(|f| f.vertices.len()).sum::<usize>();
+        self.stats.surfaces += faces.len();
+    }
+}
+
+/// Collects the leafs below `child` that intersect the frustum, skipping
+/// whole nodes outside of it. `clipflags` are the planes the parent node
+/// isn't entirely in front of, like in `R_RecursiveWorldNode`.
+fn frustum_leafs(bsp: &Bsp,
+                 frustum: &Frustum,
+                 child: NodeChild,
+                 clipflags: u8,
+                 leafs: &mut Vec<usize>) {
+    match child {
+        NodeChild::Node(n) => {
+            let node = &bsp.nodes[n];
+            if let Some(flags) = frustum.cull_box(node.mins, node.maxs, clipflags) {
+                for &child in &node.children {
+                    frustum_leafs(bsp, frustum, child, flags, leafs);
+                }
+            }
+        }
+        // Leaf 0 is the shared solid leaf
+        NodeChild::Leaf(0) => {}
+        NodeChild::Leaf(l) => {
+            let leaf = &bsp.leafs[l];
+            if frustum.cull_box(leaf.mins, leaf.maxs, clipflags).is_some() {
+                leafs.push(l);
+            }
+        }
+    }
+}
+
+/// Computes how `1 / z`, `s / z` and `t / z` of a plane vary over the
+/// screen, like `D_CalcGradients`. The texture coordinates are relative to
+/// `corner` and scaled by `mip_scale`.
+fn surface_gradients(view: &View,
+                     normal: Vec3,
+                     dist: f32,
+                     texinfo: &TexInfo,
+                     corner: [f32; 2],
+                     mip_scale: f32)
+                     -> (Gradient, Gradient, Gradient) {
+    // Screen position (x, y) looks along the view space direction
+    // ((x - cx) / scale, -(y - cy) / scale, 1), which is linear in x and y.
+    let direction_gradient = |v: Vec3| {
+        Gradient {
+            origin: v.z - v.x * view.center_x / view.scale + v.y * view.center_y / view.scale,
+            dx: v.x / view.scale,
+            dy: -v.y / view.scale,
+        }
+    };
+    let to_view = |v: Vec3| Vec3::new(v.dot(view.right), v.dot(view.up), v.dot(view.forward));
+
+    // On the plane n.p = d, 1 / z = (n.direction) / d
+    let plane_dist = dist - normal.dot(view.origin);
+    let n = direction_gradient(to_view(normal));
+    let izi = Gradient {
+        origin: n.origin / plane_dist,
+        dx: n.dx / plane_dist,
+        dy: n.dy / plane_dist,
+    };
+
+    // s = s_vec.p + s_offset, so s / z = s_vec.direction + s_0 / z
+    let texture_gradient = |axis: Vec3, offset: f32| {
+        let axis = axis * mip_scale;
+        let offset = axis.dot(view.origin) + offset * mip_scale;
+        let g = direction_gradient(to_view(axis));
+        Gradient {
+            origin: g.origin + offset * izi.origin,
+            dx: g.dx + offset * izi.dx,
+            dy: g.dy + offset * izi.dy,
+        }
+    };
+    (izi,
+     texture_gradient(texinfo.s, texinfo.s_offset - corner[0]),
+     texture_gradient(texinfo.t, texinfo.t_offset - corner[1]))
+}
+
+/// Draws the spans of a surface and writes its depth into the z-buffer.
+fn draw_spans(framebuffer: &mut Framebuffer,
+              spans: &[Span],
+              face: &PreparedFace,
+              colormap: &Colormap,
+              frame: &Frame) {
+    let width = framebuffer.width();
+    let (pixels, mut zbuffer) = framebuffer.buffers_mut();
+    for span in spans {
+        let y = span.y as i32;
+        let row = span.y * width;
+        for x in span.x..span.x + span.len {
+            let izi = face.izi.at(x as i32, y);
+            let z = 1.0 / izi;
+            let (s, t) = (face.sz.at(x as i32, y) * z, face.tz.at(x as i32, y) * z);
+            pixels[row + x] = face.texture.sample(colormap, frame, x, span.y, s, t);
+            if let Some(ref mut zbuffer) = zbuffer {
+                zbuffer[row + x] = depth_value(izi);
+            }
+        }
+    }
+}
+
+/// Chooses the mip level for a surface drawn at `scale` pixels per texel.
+fn mip_level(scale: f32) -> usize {
+    MIP_SCALES.iter().position(|&s| scale >= s).unwrap_or(MIP_SCALES.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use drawing::{Colormap, Framebuffer, Palette};
+    use drawing::colormap::LIGHT_LEVELS;
+    use files::bsp::Bsp;
+    use files::bsp::entities::parse_entities;
+    use files::mdl::Mdl;
+    use files::testing::{BspBuilder, MdlBuilder, floor_bsp, floor_bsp_with};
+    use files::bsp::NodeChild;
+    use render::{AliasLight, AliasModel, AliasPose, Camera, View, World};
+    use render::frustum::ALL_PLANES;
+    use util::Vec3;
+    use hprof;
+    use super::{DrawMode, RenderStats, Renderer, frustum_leafs, mip_level};
+
+    /// A colormap where light level `l` maps color `c` to `c + l`.
+    fn colormap() -> Colormap {
+        let bytes: Vec<u8> = (0..LIGHT_LEVELS * 256).map(|i| (i % 256 + i / 256) as u8).collect();
+        Colormap::from_bytes(&bytes)
+    }
+
+    fn world(builder: &BspBuilder) -> World {
+        let bsp = Bsp::from_bytes(&builder.build()).unwrap();
+        let entities = parse_entities(&bsp.entities).unwrap();
+        World::new(bsp, entities).unwrap()
+    }
+
+    fn floor_world() -> World {
+        world(&floor_bsp())
+    }
+
+    fn render_world(renderer: &mut Renderer,
+                    world: &World,
+                    camera: &Camera,
+                    time: f32)
+                    -> Framebuffer {
+        let mut fb = Framebuffer::with_palette(64, 48, Palette::from_bytes(&[0; 768]));
+        fb.fill(255);
+        renderer.render(world, camera, &mut fb, time);
+        fb
+    }
+
+    fn render_at(renderer: &mut Renderer, camera: &Camera, time: f32) -> Framebuffer {
+        render_world(renderer, &floor_world(), camera, time)
+    }
+
+    fn render_with(renderer: &mut Renderer, camera: &Camera) -> Framebuffer {
+        render_at(renderer, camera, 0.0)
+    }
+
+    fn render(camera: &Camera) -> Framebuffer {
+        render_with(&mut Renderer::new(colormap()), camera)
+    }
+
+    #[test]
+    fn mip_levels() {
+        assert_eq!(mip_level(2.0), 0);
+        assert_eq!(mip_level(0.5), 1);
+        assert_eq!(mip_level(0.25), 2);
+        assert_eq!(mip_level(0.1), 3);
+    }
+
+    #[test]
+    fn looking_down_at_the_floor() {
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 24.0), Vec3::new(90.0, 0.0, 0.0));
+        let fb = render(&camera);
+        // Texture color 7 at light 200 and the normal style value (level 12)
+        assert!(fb.pixels().iter().all(|&p| p == 19));
+    }
+
+    #[test]
+    fn horizon_and_determinism() {
+        let world = floor_world();
+        assert_eq!(world.player_start(),
+                   Some((Vec3::new(0.0, 0.0, 46.0), Vec3::new(0.0, 0.0, 0.0))));
+
+        // Near the edge of the floor looking along it, so the floor ends
+        // 124 units away, just below the horizon
+        let camera = Camera::new(Vec3::new(-60.0, 0.0, 8.0), Vec3::new(0.0, 0.0, 0.0));
+        let fb = render(&camera);
+        let row = |y: usize| &fb.pixels()[y * 64..(y + 1) * 64];
+        assert!((0..26).all(|y| row(y).iter().all(|&p| p == 255)));
+        assert!((30..48).all(|y| row(y).iter().all(|&p| p == 19)));
+        assert!(row(27).contains(&255) && row(27).contains(&19));
+        assert_eq!(fb.pixels(), render(&camera).pixels());
+    }
+
+    #[test]
+    fn culls_back_faces_and_solid_leafs() {
+        // Below the floor, looking up: the back of the floor isn't drawn
+        let camera = Camera::new(Vec3::new(0.0, 0.0, -24.0), Vec3::new(-90.0, 0.0, 0.0));
+        assert!(render(&camera).pixels().iter().all(|&p| p == 255));
+        // Above the floor, looking up
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 24.0), Vec3::new(-90.0, 0.0, 0.0));
+        assert!(render(&camera).pixels().iter().all(|&p| p == 255));
+    }
+
+    #[test]
+    fn culls_nodes_outside_the_frustum() {
+        let world = floor_world();
+        let leafs = |camera: &Camera| {
+            let view = View::new(camera, 64, 48);
+            let mut leafs = Vec::new();
+            frustum_leafs(&world.bsp, &view.frustum, NodeChild::Node(0), ALL_PLANES, &mut leafs);
+            leafs
+        };
+        // The solid leaf below the floor is never collected
+        let above = Camera::new(Vec3::new(0.0, 0.0, 24.0), Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(leafs(&above), vec![1]);
+        // Outside of the map, looking away from it
+        let outside = Camera::new(Vec3::new(100.0, 0.0, 24.0), Vec3::new(0.0, 0.0, 0.0));
+        assert!(leafs(&outside).is_empty());
+        let turned = Camera::new(Vec3::new(100.0, 0.0, 24.0), Vec3::new(0.0, 180.0, 0.0));
+        assert_eq!(leafs(&turned), vec![1]);
+    }
+
+    #[test]
+    fn edge_sorting_matches_polygons() {
+        let camera = Camera::new(Vec3::new(-60.0, 0.0, 8.0), Vec3::new(0.0, 0.0, 0.0));
+        let mut renderer = Renderer::new(colormap());
+        assert_eq!(renderer.mode(), DrawMode::EdgeSorted);
+        let sorted = render_with(&mut renderer, &camera);
+        renderer.set_mode(DrawMode::Polygons);
+        let polygons = render_with(&mut renderer, &camera);
+
+        assert_eq!(sorted.pixels(), polygons.pixels());
+        let depths = sorted.zbuffer().unwrap().iter().zip(polygons.zbuffer().unwrap());
+        assert!(depths.into_iter().all(|(&a, &b)| (a as i32 - b as i32).abs() <= 1));
+    }
+
+    #[test]
+    fn counts_edges_surfaces_and_spans() {
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 24.0), Vec3::new(90.0, 0.0, 0.0));
+        let mut renderer = Renderer::new(colormap());
+        render_with(&mut renderer, &camera);
+        // The floor covers the screen: one span per row between two edges
+        let stats = RenderStats {
+            edges: 2,
+            surfaces: 1,
+            spans: 48,
+            built: 1,
+        };
+        assert_eq!(renderer.stats(), stats);
+        assert_eq!(stats.to_string(), "2 edges, 1 surfaces, 48 spans, 1 surfaces built");
+
+        // The surface is cached until its light style changes
+        render_with(&mut renderer, &camera);
+        assert_eq!(renderer.stats().built, 0);
+        renderer.light_styles_mut().set(0, "a");
+        assert!(render_with(&mut renderer, &camera).pixels().iter().all(|&p| p == 7 + 63));
+        assert_eq!(renderer.stats().built, 1);
+        assert_eq!(renderer.surface_cache().len(), 1);
+    }
+
+    #[test]
+    fn counts_are_profiled() {
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 24.0), Vec3::new(90.0, 0.0, 0.0));
+        // Every test runs on its own thread, with its own profiler
+        let profiled_counts = |renderer: &mut Renderer| {
+            hprof::start_frame();
+            render_with(renderer, &camera);
+            hprof::end_frame();
+            let root = hprof::profiler().root();
+            let children = root.children.borrow();
+            let render = children.iter().find(|n| n.name == "Renderer::render").unwrap();
+            let counts: Vec<_> = render.children
+                                       .borrow()
+                                       .iter()
+                                       .filter(|n| n.name.starts_with("RenderStats::"))
+                                       .map(|n| (n.name, n.calls.get()))
+                                       .collect();
+            counts
+        };
+        assert_eq!(profiled_counts(&mut Renderer::new(colormap())), vec![]);
+
+        let mut renderer = Renderer::new(colormap());
+        renderer.set_profile_stats(true);
+        assert_eq!(profiled_counts(&mut renderer),
+                   vec![("RenderStats::edges", 2),
+                        ("RenderStats::surfaces", 1),
+                        ("RenderStats::spans", 48),
+                        ("RenderStats::built", 1)]);
+    }
+
+    #[test]
+    fn animated_light_styles() {
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 24.0), Vec3::new(90.0, 0.0, 0.0));
+        let mut renderer = Renderer::new(colormap());
+        renderer.light_styles_mut().set(0, "mmaz");
+        let frames: Vec<u8> = [0.0, 0.15, 0.2, 0.3, 0.45]
+                                  .iter()
+                                  .map(|&time| render_at(&mut renderer, &camera, time).get(5, 5))
+                                  .collect();
+        // Normal, normal, dark, bright (level 0) and normal again
+        assert_eq!(frames, vec![19, 19, 7 + 63, 7, 19]);
+        assert_eq!(renderer.light_styles().value(0), 264);
+        // Rebuilt only when the value changed
+        render_at(&mut renderer, &camera, 0.5);
+        assert_eq!(renderer.stats().built, 0);
+    }
+
+    #[test]
+    fn dynamic_lights() {
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 24.0), Vec3::new(90.0, 0.0, 0.0));
+        let mut renderer = Renderer::new(colormap());
+        assert_eq!(render_at(&mut renderer, &camera, 0.0).get(32, 24), 19);
+        {
+            let light = renderer.dynamic_lights_mut().alloc(1, 0.0);
+            light.origin = Vec3::new(0.0, 0.0, 24.0);
+            light.radius = 200.0;
+            light.die = 0.5;
+        }
+        // Bright enough for level 0 below the light, rebuilt every frame
+        for &time in &[0.1, 0.2] {
+            let fb = render_at(&mut renderer, &camera, time);
+            assert_eq!(fb.get(32, 24), 7);
+            assert_eq!(renderer.stats().built, 1);
+        }
+        // Rebuilt once more after the light went out
+        assert_eq!(render_at(&mut renderer, &camera, 0.6).get(32, 24), 19);
+        assert_eq!(renderer.stats().built, 1);
+        render_at(&mut renderer, &camera, 0.7);
+        assert_eq!(renderer.stats().built, 0);
+    }
+
+    /// Renders `world` looking down from above the center of the floor, at
+    /// `time`, in both draw modes. Returns the color at the center.
+    fn center_looking_down(world: &World, time: f32) -> u8 {
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 24.0), Vec3::new(90.0, 0.0, 0.0));
+        let mut renderer = Renderer::new(colormap());
+        let sorted = render_world(&mut renderer, world, &camera, time);
+        renderer.set_mode(DrawMode::Polygons);
+        let polygons = render_world(&mut renderer, world, &camera, time);
+        assert_eq!(sorted.pixels(), polygons.pixels());
+        sorted.get(32, 24)
+    }
+
+    #[test]
+    fn turbulent_water() {
+        // Every texel holds its s coordinate. The center pixel shows the
+        // floor at (-0.375, -0.375), shifted sideways by the turbulence.
+        let water = world(&floor_bsp_with("*water", 64, 64, |_, x, _| x as u8));
+        assert_eq!(center_looking_down(&water, 0.0), 7);
+        assert_eq!(center_looking_down(&water, 1.6), 15);
+        assert_eq!(center_looking_down(&water, 1.6 + 6.4), 15);
+    }
+
+    #[test]
+    fn translucent_water() {
+        let grey: Vec<u8> = (0..256).flat_map(|i| vec![i as u8; 3]).collect();
+        let lava = world(&floor_bsp_with("*lava1", 16, 16, |_, _, _| 100));
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 24.0), Vec3::new(90.0, 0.0, 0.0));
+        let mut renderer = Renderer::new(colormap());
+        renderer.set_water_alpha(0.5);
+        for &mode in &[DrawMode::EdgeSorted, DrawMode::Polygons] {
+            renderer.set_mode(mode);
+            let mut fb = Framebuffer::with_palette(64, 48, Palette::from_bytes(&grey));
+            fb.fill(200);
+            renderer.render(&lava, &camera, &mut fb, 0.0);
+            assert!(fb.pixels().iter().all(|&c| c == 150));
+            assert_eq!(renderer.stats().surfaces, 1);
+        }
+    }
+
+    #[test]
+    fn scrolling_sky() {
+        // The front layer is transparent on its left half
+        let sky = world(&floor_bsp_with("sky1", 256, 128, |_, x, _| {
+            match x {
+                0..=63 => 0,
+                64..=127 => 50,
+                _ => 100,
+            }
+        }));
+        assert!(sky.sky.is_some());
+        assert_eq!(center_looking_down(&sky, 0.0), 50);
+        assert_eq!(center_looking_down(&sky, 4.0), 100);
+        assert_eq!(center_looking_down(&sky, 8.0), 50);
+    }
+
+    #[test]
+    fn models_are_hidden_by_the_world() {
+        // A 32x32 square of color 30 standing upright at the origin, half
+        // of it below the floor
+        let mut b = MdlBuilder::new([1.0, 1.0, 1.0], [-16.0, -16.0, -16.0], 4, 4);
+        b.skin(|_, _| 30)
+         .st_vert(false, 0, 0)
+         .st_vert(false, 0, 0)
+         .st_vert(false, 0, 0)
+         .st_vert(false, 0, 0)
+         .triangle(true, [0, 1, 2])
+         .triangle(true, [0, 2, 3])
+         .frame("square",
+                &[[16, 0, 0, 143], [16, 32, 0, 143], [16, 32, 32, 143], [16, 0, 32, 143]]);
+        let model = AliasModel::new(Mdl::from_bytes(&b.build()).unwrap());
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        let pose = AliasPose::new(zero, zero, 0, 0, AliasLight::at(255));
+
+        let camera = Camera::new(Vec3::new(-64.0, 0.0, 8.0), Vec3::new(0.0, 0.0, 0.0));
+        let renderer = &mut Renderer::new(colormap());
+        let mut fb = render_with(renderer, &camera);
+        renderer.draw_model(&model, &pose, &camera, &mut fb, 0.0);
+        // The top edge is at row 20, the floor under it at row 28
+        assert_eq!((fb.get(30, 19), fb.get(30, 20), fb.get(30, 27)), (255, 30, 30));
+        assert_eq!((fb.get(30, 28), fb.get(30, 40)), (19, 19));
+    }
+}
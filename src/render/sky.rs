@@ -0,0 +1,106 @@
+use drawing::Texture;
+use drawing::raster::ScreenShader;
+use render::View;
+use util::Vec3;
+
+/// Speed of the back layer in texels per second. The front layer moves
+/// twice as fast.
+const SKY_SPEED: f32 = 8.0;
+/// Size of the sky dome in texels: a direction straight ahead and
+/// horizontal lands this far from the texture origin.
+const SKY_DISTANCE: f32 = 6.0 * 63.0;
+
+/// The two scrolling layers of a `sky*` texture.
+#[derive(Debug, Clone)]
+pub struct Sky {
+    front: Texture,
+    back: Texture,
+}
+
+impl Sky {
+    /// Splits a sky texture (256x128 in the original maps) into its layers:
+    /// the left half is the front layer, where color 0 is transparent, and
+    /// the right half the back layer.
+    pub fn new(texture: &Texture) -> Sky {
+        let width = texture.width() / 2;
+        let height = texture.height();
+        let half = |offset: usize| {
+            let pixels = texture.pixels()
+                                .chunks(texture.width())
+                                .flat_map(|row| row[offset..offset + width].iter().cloned())
+                                .collect();
+            Texture::new(width, height, pixels)
+        };
+        Sky {
+            front: half(0),
+            back: half(width),
+        }
+    }
+
+    /// The color of the sky in the direction `dir`, `time` seconds into the
+    /// game. The sky is a flattened dome, like in `R_DrawSkyChain`.
+    pub fn color(&self, dir: Vec3, time: f32) -> u8 {
+        let dir = Vec3::new(dir.x, dir.y, dir.z * 3.0);
+        let scale = SKY_DISTANCE / dir.length();
+        let (s, t) = (dir.x * scale, dir.y * scale);
+
+        let shift = time * SKY_SPEED;
+        match self.front.sample(s + shift * 2.0, t + shift * 2.0) {
+            0 => self.back.sample(s + shift, t + shift),
+            color => color,
+        }
+    }
+}
+
+/// Draws the sky behind the pixels of sky surfaces, as seen from a view.
+#[derive(Debug)]
+pub struct SkyView<'a> {
+    pub sky: &'a Sky,
+    pub view: &'a View,
+    pub time: f32,
+}
+
+impl<'a> ScreenShader for SkyView<'a> {
+    fn color(&self, x: usize, y: usize) -> u8 {
+        let view = self.view;
+        let right = (x as f32 + 0.5 - view.center_x) / view.scale;
+        let up = (view.center_y - y as f32 - 0.5) / view.scale;
+        let dir = view.forward + view.right * right + view.up * up;
+        self.sky.color(dir, self.time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drawing::Texture;
+    use util::Vec3;
+    use super::Sky;
+
+    /// A sky whose front layer is transparent on its left half and 50 on the
+    /// right, in front of a back layer of 100.
+    fn test_sky() -> Texture {
+        let pixels = (0..256 * 128)
+                         .map(|i| {
+                             match i % 256 {
+                                 0..=63 => 0,
+                                 64..=127 => 50,
+                                 _ => 100,
+                             }
+                         })
+                         .collect();
+        Texture::new(256, 128, pixels)
+    }
+
+    #[test]
+    fn scrolling_layers() {
+        let sky = Sky::new(&test_sky());
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        assert_eq!(sky.color(up, 0.0), 100);
+        // The front layer moves 16 texels per second
+        assert_eq!(sky.color(up, 3.9), 100);
+        assert_eq!(sky.color(up, 4.0), 50);
+        assert_eq!(sky.color(up, 8.0), 100);
+        // Looking almost straight ahead, far out on the dome
+        assert_eq!(sky.color(Vec3::new(1.0, 0.0, 0.01), 0.0), 50);
+    }
+}
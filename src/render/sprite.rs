@@ -0,0 +1,188 @@
+//! Drawing of sprites, like `r_sprite.c`. A sprite frame is drawn on a quad
+//! around the entity's origin, turned according to the sprite's kind, and
+//! depth tested like the world. Transparent pixels are skipped.
+
+use drawing::{Framebuffer, Shading, Vertex};
+use files::PackContainer;
+use files::spr::{Spr, SprResult, SpriteKind};
+use render::{ClipVertex, View};
+use util::Vec3;
+use util::vector::angle_vectors;
+use hprof;
+
+/// Upright sprites seen from closer to straight above or below than this
+/// (the cosine of one degree) would degenerate, and aren't drawn.
+const UPRIGHT_LIMIT: f32 = 0.999848;
+
+/// Where a sprite is drawn.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SpritePose {
+    pub origin: Vec3,
+    /// Pitch, yaw and roll in degrees, for oriented sprites.
+    pub angles: Vec3,
+    pub frame: usize,
+}
+
+/// A loaded sprite.
+pub struct SpriteModel {
+    pub spr: Spr,
+}
+
+impl SpriteModel {
+    pub fn new(spr: Spr) -> SpriteModel {
+        SpriteModel { spr: spr }
+    }
+
+    /// Loads a sprite like `progs/s_explod.spr`.
+    pub fn load(paks: &mut PackContainer, name: &str) -> SprResult<SpriteModel> {
+        Spr::load(paks, name).map(SpriteModel::new)
+    }
+
+    /// Draws the sprite as seen from `view`, `time` seconds into the game
+    /// (for frame groups).
+    pub fn draw(&self, framebuffer: &mut Framebuffer, view: &View, pose: &SpritePose, time: f32) {
+        let _guard = hprof::enter("SpriteModel::draw");
+        let spr = &self.spr;
+        if !view.sphere_visible(pose.origin, spr.radius) {
+            return;
+        }
+        let (right, up) = match sprite_axes(spr.kind, view, pose) {
+            Some(axes) => axes,
+            None => return,
+        };
+
+        let frame = spr.frame(pose.frame, time);
+        let (left_edge, right_edge, top, bottom) = frame.edges();
+        let (width, height) = (frame.image.width() as f32, frame.image.height() as f32);
+        let corners = [(left_edge, top, 0.0, 0.0),
+                       (right_edge, top, width, 0.0),
+                       (right_edge, bottom, width, height),
+                       (left_edge, bottom, 0.0, height)];
+        let polygon = corners.iter()
+                             .map(|&(r, u, s, t)| {
+                                 ClipVertex {
+                                     pos: view.transform(pose.origin + right * r + up * u),
+                                     s: s,
+                                     t: t,
+                                 }
+                             })
+                             .collect();
+        let vertices: Vec<Vertex> = view.clip_polygon(polygon)
+                                        .iter()
+                                        .map(|v| {
+                                            let (x, y) = view.project(v.pos);
+                                            Vertex::new(x, y, v.pos.z, v.s, v.t)
+                                        })
+                                        .collect();
+        framebuffer.polygon(&vertices, Shading::Sprite(&frame.image));
+    }
+}
+
+/// The right and up axes of a sprite's quad in world space, or `None` if an
+/// upright sprite is seen from straight above or below.
+fn sprite_axes(kind: SpriteKind, view: &View, pose: &SpritePose) -> Option<(Vec3, Vec3)> {
+    let vertical = Vec3::new(0.0, 0.0, 1.0);
+    // The right axis of an upright sprite facing along `dir`
+    let upright = |dir: Vec3| {
+        if dir.z.abs() > UPRIGHT_LIMIT {
+            None
+        } else {
+            Some((Vec3::new(dir.y, -dir.x, 0.0).normalize(), vertical))
+        }
+    };
+    match kind {
+        SpriteKind::ParallelUpright => upright(view.forward),
+        SpriteKind::FacingUpright => upright((pose.origin - view.origin).normalize()),
+        SpriteKind::Parallel => Some((view.right, view.up)),
+        SpriteKind::Oriented => {
+            let (_, right, up) = angle_vectors(pose.angles);
+            Some((right, up))
+        }
+        SpriteKind::ParallelOriented => {
+            let (sr, cr) = pose.angles.z.to_radians().sin_cos();
+            Some((view.right * cr + view.up * sr, view.up * cr - view.right * sr))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drawing::{Framebuffer, Palette, Shading, Vertex};
+    use files::spr::{Spr, SpriteKind};
+    use files::testing::SprBuilder;
+    use render::{Camera, View};
+    use util::Vec3;
+    use super::{SpriteModel, SpritePose, sprite_axes};
+
+    fn near(a: Vec3, b: Vec3) -> bool {
+        (a.x - b.x).abs() + (a.y - b.y).abs() + (a.z - b.z).abs() < 1e-4
+    }
+
+    fn pose(origin: Vec3, angles: Vec3) -> SpritePose {
+        SpritePose {
+            origin: origin,
+            angles: angles,
+            frame: 0,
+        }
+    }
+
+    #[test]
+    fn orientations() {
+        // Looking down at 45 degrees along +x
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(45.0, 0.0, 0.0));
+        let view = View::new(&camera, 64, 48);
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        let left = pose(Vec3::new(0.0, 100.0, 0.0), zero);
+        let axes = |kind, pose: &SpritePose| sprite_axes(kind, &view, pose).unwrap();
+
+        let (right, up) = axes(SpriteKind::Parallel, &left);
+        assert!(near(right, view.right) && near(up, view.up));
+        let (right, up) = axes(SpriteKind::ParallelUpright, &left);
+        assert!(near(right, Vec3::new(0.0, -1.0, 0.0)) && near(up, Vec3::new(0.0, 0.0, 1.0)));
+        // Facing the camera, to its left
+        let (right, up) = axes(SpriteKind::FacingUpright, &left);
+        assert!(near(right, Vec3::new(1.0, 0.0, 0.0)) && near(up, Vec3::new(0.0, 0.0, 1.0)));
+        let (right, up) = axes(SpriteKind::Oriented, &pose(zero, Vec3::new(0.0, 90.0, 0.0)));
+        assert!(near(right, Vec3::new(1.0, 0.0, 0.0)) && near(up, Vec3::new(0.0, 0.0, 1.0)));
+        let rolled = pose(zero, Vec3::new(0.0, 0.0, 90.0));
+        let (right, up) = axes(SpriteKind::ParallelOriented, &rolled);
+        assert!(near(right, view.up) && near(up, -view.right));
+
+        // Upright sprites can't be seen from straight above
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 100.0), Vec3::new(90.0, 0.0, 0.0));
+        let above = View::new(&camera, 64, 48);
+        assert!(sprite_axes(SpriteKind::ParallelUpright, &above, &left).is_none());
+        assert!(sprite_axes(SpriteKind::FacingUpright, &above, &pose(zero, zero)).is_none());
+        assert!(sprite_axes(SpriteKind::Parallel, &above, &left).is_some());
+    }
+
+    #[test]
+    fn draws_transparent_billboards() {
+        // 16x16 pixels around the origin, transparent on the left half
+        let mut b = SprBuilder::new(2, 16, 16);
+        b.frame([-8, 8], 16, 16, |x, _| if x < 8 { 255 } else { 40 });
+        let sprite = SpriteModel::new(Spr::from_bytes(&b.build()).unwrap());
+
+        let mut fb = Framebuffer::with_palette(64, 48, Palette::from_bytes(&[0; 768]));
+        fb.enable_zbuffer();
+        // A wall in front of the bottom half
+        let wall = [Vertex::new(0.0, 24.0, 32.0, 0.0, 0.0),
+                    Vertex::new(64.0, 24.0, 32.0, 0.0, 0.0),
+                    Vertex::new(64.0, 48.0, 32.0, 0.0, 0.0),
+                    Vertex::new(0.0, 48.0, 32.0, 0.0, 0.0)];
+        fb.polygon(&wall, Shading::Flat(1));
+
+        let camera = Camera::new(Vec3::new(-64.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let view = View::new(&camera, 64, 48);
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        sprite.draw(&mut fb, &view, &pose(zero, zero), 0.0);
+        // 16 units at a distance of 64 cover 8 pixels
+        assert_eq!((fb.get(27, 21), fb.get(31, 21), fb.get(32, 21), fb.get(35, 21)),
+                   (0, 0, 40, 40));
+        assert_eq!((fb.get(36, 21), fb.get(32, 19), fb.get(32, 20)), (0, 0, 40));
+        assert_eq!(fb.get(33, 25), 1);
+        // Behind the camera
+        sprite.draw(&mut fb, &view, &pose(Vec3::new(-128.0, 0.0, 0.0), zero), 0.0);
+        assert_eq!(fb.get(33, 21), 40);
+    }
+}
@@ -0,0 +1,284 @@
+use drawing::{Colormap, Texture};
+use files::bsp::{Bsp, Face, LIGHTMAP_SAMPLE, NO_LIGHTSTYLE};
+use render::dlight::DynamicLight;
+
+/// Value of a light style at normal brightness, `m` in the style strings.
+pub const NORMAL_STYLE_VALUE: i32 = 264;
+
+/// The area of a face in texture space, rounded out to whole lightmap
+/// samples, like `CalcSurfaceExtents`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SurfaceExtents {
+    /// Texture coordinates of the top left corner.
+    pub texture_mins: [i32; 2],
+    /// Width and height in texels.
+    pub extents: [i32; 2],
+}
+
+impl SurfaceExtents {
+    /// The extents of a face. `Bsp` rejects lightmapped faces whose extents
+    /// are larger than `MAX_SURFACE_EXTENT` when it is loaded.
+    pub fn new(bsp: &Bsp, face: &Face) -> SurfaceExtents {
+        let (mins, maxs) = bsp.texture_bounds(face);
+        let sample = LIGHTMAP_SAMPLE as f64;
+        let mut texture_mins = [0; 2];
+        let mut extents = [0; 2];
+        for i in 0..2 {
+            let bmin = (mins[i] / sample).floor() as i32;
+            let bmax = (maxs[i] / sample).ceil() as i32;
+            texture_mins[i] = bmin * LIGHTMAP_SAMPLE;
+            extents[i] = (bmax - bmin) * LIGHTMAP_SAMPLE;
+        }
+        SurfaceExtents {
+            texture_mins: texture_mins,
+            extents: extents,
+        }
+    }
+
+    /// Width and height of the face's lightmap in samples.
+    pub fn lightmap_size(&self) -> (usize, usize) {
+        ((self.extents[0] / LIGHTMAP_SAMPLE + 1) as usize,
+         (self.extents[1] / LIGHTMAP_SAMPLE + 1) as usize)
+    }
+}
+
+/// The average brightness of the first light style of a face (0 to 255).
+/// Faces without a lightmap are dark, unless the whole map is unlit.
+pub fn average_light(bsp: &Bsp, face: &Face) -> u8 {
+    if bsp.lighting.is_empty() {
+        return 255;
+    }
+    let offset = match face.light_offset {
+        Some(offset) => offset,
+        None => return 0,
+    };
+    let (w, h) = SurfaceExtents::new(bsp, face).lightmap_size();
+    let end = (offset + w * h).min(bsp.lighting.len());
+    let samples = &bsp.lighting[offset..end];
+    if samples.is_empty() {
+        return 0;
+    }
+    (samples.iter().map(|&l| l as usize).sum::<usize>() / samples.len()) as u8
+}
+
+/// Adds up the light styles of a face's lightmap, like the first half of
+/// `R_BuildLightMap`. `style_values` holds the current value of every light
+/// style, so the samples are 256 times the brightness at normal light.
+/// Dynamic lights can be added to the result before building the surface.
+pub fn blocklights(bsp: &Bsp,
+                   face: &Face,
+                   extents: &SurfaceExtents,
+                   style_values: &[i32])
+                   -> Vec<i32> {
+    let (w, h) = extents.lightmap_size();
+    let size = w * h;
+    if bsp.lighting.is_empty() {
+        // Unlit maps are fullbright
+        return vec![255 * 256; size];
+    }
+
+    let mut blocklights = vec![0; size];
+    if let Some(offset) = face.light_offset {
+        let maps = face.styles.iter().take_while(|&&style| style != NO_LIGHTSTYLE);
+        for (map, &style) in maps.enumerate() {
+            let scale = style_values.get(style as usize).cloned().unwrap_or(NORMAL_STYLE_VALUE);
+            let start = (offset + map * size).min(bsp.lighting.len());
+            let end = (start + size).min(bsp.lighting.len());
+            for (light, &sample) in blocklights.iter_mut().zip(&bsp.lighting[start..end]) {
+                *light += sample as i32 * scale;
+            }
+        }
+    }
+    blocklights
+}
+
+/// Adds the lights of `lights` whose bit is set in `bits` to the
+/// `blocklights` of a face, like `R_AddDynamicLights`. The light falls off
+/// with the distance from the point of the face's plane nearest to it.
+pub fn add_dynamic_lights(bsp: &Bsp,
+                          face: &Face,
+                          extents: &SurfaceExtents,
+                          lights: &[DynamicLight],
+                          bits: u32,
+                          blocklights: &mut [i32]) {
+    let (w, h) = extents.lightmap_size();
+    let plane = &bsp.planes[face.plane];
+    let texinfo = &bsp.texinfo[face.texinfo];
+    for (i, light) in lights.iter().enumerate() {
+        if bits & (1 << i) == 0 {
+            continue;
+        }
+        let dist = light.origin.dot(plane.normal) - plane.dist;
+        let radius = light.radius - dist.abs();
+        if radius < light.min_light {
+            continue;
+        }
+        let reach = radius - light.min_light;
+        // Where the light hits the plane, in lightmap texels
+        let impact = light.origin - plane.normal * dist;
+        let local = [impact.dot(texinfo.s) + texinfo.s_offset - extents.texture_mins[0] as f32,
+                     impact.dot(texinfo.t) + texinfo.t_offset - extents.texture_mins[1] as f32];
+        for t in 0..h {
+            let td = ((local[1] - (t as i32 * LIGHTMAP_SAMPLE) as f32) as i32).abs();
+            for s in 0..w {
+                let sd = ((local[0] - (s as i32 * LIGHTMAP_SAMPLE) as f32) as i32).abs();
+                // An approximation of the distance
+                let dist = if sd > td { sd + (td >> 1) } else { td + (sd >> 1) } as f32;
+                if dist < reach {
+                    blocklights[t * w + s] += ((radius - dist) * 256.0) as i32;
+                }
+            }
+        }
+    }
+}
+
+/// Converts a light sample into a colormap light level times 256, keeping
+/// the fraction for interpolation. Even the brightest light stays a little
+/// above level 0, like in the original.
+#[inline]
+fn light_value(blocklight: i32) -> i32 {
+    ((255 * 256 - blocklight) >> 2).max(1 << 6)
+}
+
+/// The average light level of a face's light samples, for drawing it
+/// without a surface.
+pub fn average_level(blocklights: &[i32]) -> usize {
+    if blocklights.is_empty() {
+        return 0;
+    }
+    let total: i32 = blocklights.iter().map(|&light| light_value(light)).sum();
+    ((total / blocklights.len() as i32) >> 8) as usize
+}
+
+/// Builds the lit surface of a face at a mip level, like `R_DrawSurface`:
+/// the texture is tiled over the extents of the face and every texel is
+/// shaded through the colormap, with the light interpolated bilinearly
+/// between the samples of `blocklights`.
+///
+/// The surface's texel (0, 0) is at `texture_mins` (scaled to the mip
+/// level).
+pub fn build_surface(texture: &Texture,
+                     extents: &SurfaceExtents,
+                     mip: usize,
+                     blocklights: &[i32],
+                     colormap: &Colormap)
+                     -> Texture {
+    let (light_width, _) = extents.lightmap_size();
+    let block = LIGHTMAP_SAMPLE >> mip;
+    let width = extents.extents[0] >> mip;
+    let height = extents.extents[1] >> mip;
+    let s0 = extents.texture_mins[0] >> mip;
+    let t0 = extents.texture_mins[1] >> mip;
+    let light = |x: i32, y: i32| light_value(blocklights[y as usize * light_width + x as usize]);
+
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for v in 0..height {
+        let (ly, fy) = (v / block, v % block);
+        for u in 0..width {
+            let (lx, fx) = (u / block, u % block);
+            let top = light(lx, ly) * (block - fx) + light(lx + 1, ly) * fx;
+            let bottom = light(lx, ly + 1) * (block - fx) + light(lx + 1, ly + 1) * fx;
+            let value = (top * (block - fy) + bottom * fy) / (block * block);
+            pixels.push(colormap.shade(texture.get(s0 + u, t0 + v), (value >> 8) as usize));
+        }
+    }
+    Texture::new(width as usize, height as usize, pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use drawing::Colormap;
+    use drawing::colormap::LIGHT_LEVELS;
+    use files::bsp::{Bsp, TextureSet};
+    use files::testing::floor_bsp;
+    use render::dlight::DynamicLights;
+    use util::Vec3;
+    use super::{NORMAL_STYLE_VALUE, SurfaceExtents, add_dynamic_lights, average_level,
+                average_light, blocklights, build_surface};
+
+    #[test]
+    fn floor_extents() {
+        let bsp = Bsp::from_bytes(&floor_bsp().build()).unwrap();
+        let extents = SurfaceExtents::new(&bsp, &bsp.faces[0]);
+        assert_eq!(extents.texture_mins, [-64, -64]);
+        assert_eq!(extents.extents, [128, 128]);
+        assert_eq!(extents.lightmap_size(), (9, 9));
+        assert_eq!(average_light(&bsp, &bsp.faces[0]), 200);
+    }
+
+    #[test]
+    fn lit_surfaces() {
+        let bytes: Vec<u8> = (0..LIGHT_LEVELS * 256).map(|i| (i % 256 + i / 256) as u8).collect();
+        let colormap = Colormap::from_bytes(&bytes);
+        let mut bsp = Bsp::from_bytes(&floor_bsp().build()).unwrap();
+        // Gets brighter towards +x
+        bsp.lighting = (0..81).map(|i| 100 + 10 * (i % 9) as u8).collect();
+        let textures = TextureSet::new(&bsp).unwrap();
+        let face = &bsp.faces[0];
+        let extents = SurfaceExtents::new(&bsp, face);
+        let styles = [NORMAL_STYLE_VALUE; 64];
+
+        let light = blocklights(&bsp, face, &extents, &styles);
+        assert_eq!(light[1], 110 * 264);
+        assert_eq!(average_level(&light), 27);
+        let texture = textures.get(0).unwrap();
+        let surface = build_surface(texture.mip(0), &extents, 0, &light, &colormap);
+        assert_eq!((surface.width(), surface.height()), (128, 128));
+        // Level 37 on the first sample, 36.6 halfway to the next one
+        assert_eq!(surface.get(0, 0), 7 + 37);
+        assert_eq!(surface.get(8, 5), 7 + 36);
+        assert_eq!(surface.get(127, 127), 7 + 17);
+        let mip = build_surface(texture.mip(2), &extents, 2, &light, &colormap);
+        assert_eq!((mip.width(), mip.height()), (32, 32));
+        assert_eq!(mip.get(2, 0), 7 + 36);
+
+        // A dark style, and a map without lighting
+        let dark = blocklights(&bsp, face, &extents, &[0; 64]);
+        assert!(build_surface(texture.mip(0), &extents, 0, &dark, &colormap)
+                    .pixels()
+                    .iter()
+                    .all(|&p| p == 7 + 63));
+        bsp.lighting.clear();
+        let bright = blocklights(&bsp, &bsp.faces[0], &extents, &styles);
+        assert_eq!(build_surface(texture.mip(0), &extents, 0, &bright, &colormap).get(9, 9),
+                   7);
+    }
+
+    #[test]
+    fn dynamic_lights() {
+        let bsp = Bsp::from_bytes(&floor_bsp().build()).unwrap();
+        let face = &bsp.faces[0];
+        let extents = SurfaceExtents::new(&bsp, face);
+        let mut lights = DynamicLights::new();
+        {
+            // 16 units above the center of the floor
+            let light = lights.alloc(0, 0.0);
+            light.origin = Vec3::new(0.0, 0.0, 16.0);
+            light.radius = 100.0;
+            light.die = 1.0;
+        }
+        let normal = blocklights(&bsp, face, &extents, &[NORMAL_STYLE_VALUE; 64]);
+        let mut light = normal.clone();
+        add_dynamic_lights(&bsp, face, &extents, lights.lights(), 0, &mut light);
+        assert_eq!(light, normal);
+
+        add_dynamic_lights(&bsp, face, &extents, lights.lights(), 1, &mut light);
+        let added = |light: &[i32], s: usize, t: usize| light[t * 9 + s] - normal[t * 9 + s];
+        assert_eq!(added(&light, 4, 4), 84 * 256);
+        assert_eq!(added(&light, 0, 4), 20 * 256);
+        assert_eq!(added(&light, 2, 2), (84 - 48) * 256);
+        assert_eq!((added(&light, 0, 0), added(&light, 8, 8)), (0, 0));
+
+        // The minimum light cuts off the edge
+        let mut edge = lights.lights()[0];
+        edge.min_light = 30.0;
+        let mut light = normal.clone();
+        add_dynamic_lights(&bsp, face, &extents, &[edge], 1, &mut light);
+        assert_eq!((added(&light, 4, 4), added(&light, 2, 2), added(&light, 0, 4)),
+                   (84 * 256, 36 * 256, 0));
+        edge.min_light = 90.0;
+        let mut light = normal.clone();
+        add_dynamic_lights(&bsp, face, &extents, &[edge], 1, &mut light);
+        assert_eq!(light, normal);
+    }
+}
@@ -0,0 +1,56 @@
+use files::PackContainer;
+use files::bsp::{Bsp, BspResult, Entity, Pvs, TextureKind, TextureSet, load_entities};
+use render::sky::Sky;
+use util::Vec3;
+
+/// Height of the player's eyes above the origin.
+pub const VIEW_HEIGHT: f32 = 22.0;
+
+/// A loaded map with the data derived from it that the renderer needs.
+pub struct World {
+    pub bsp: Bsp,
+    pub textures: TextureSet,
+    pub pvs: Pvs,
+    pub entities: Vec<Entity>,
+    /// The layers of the first sky texture.
+    pub sky: Option<Sky>,
+}
+
+impl World {
+    pub fn new(bsp: Bsp, entities: Vec<Entity>) -> BspResult<World> {
+        let textures = try!(TextureSet::new(&bsp));
+        let pvs = Pvs::new(&bsp);
+        let sky = (0..textures.len())
+                      .filter_map(|i| textures.get(i))
+                      .find(|texture| texture.kind == TextureKind::Sky)
+                      .map(|texture| Sky::new(texture.mip(0)));
+        Ok(World {
+            bsp: bsp,
+            textures: textures,
+            pvs: pvs,
+            entities: entities,
+            sky: sky,
+        })
+    }
+
+    /// Loads `maps/<name>.bsp`, with the entities of `maps/<name>.ent` if
+    /// there is one.
+    pub fn load(paks: &mut PackContainer, name: &str) -> BspResult<World> {
+        let path = format!("maps/{}.bsp", name);
+        let bsp = try!(Bsp::load(paks, &path));
+        let entities = try!(load_entities(paks, &path, &bsp));
+        World::new(bsp, entities)
+    }
+
+    /// The eye position and view angles of the first `info_player_start`.
+    pub fn player_start(&self) -> Option<(Vec3, Vec3)> {
+        self.entities
+            .iter()
+            .find(|e| e.classname() == Some("info_player_start"))
+            .and_then(|e| e.get_vec3("origin").map(|o| (e, o)))
+            .map(|(e, origin)| {
+                let eye = origin + Vec3::new(0.0, 0.0, VIEW_HEIGHT);
+                (eye, e.angles().unwrap_or(Vec3::new(0.0, 0.0, 0.0)))
+            })
+    }
+}
@@ -24,19 +24,35 @@ impl Options {
     /// argument is itself a commandline option (starts with '-').
     pub fn check_param<T>(&self, argument: &str) -> Option<T>
         where T: FromStr
+    {
+        self.check_params(argument, 1).and_then(|mut values| values.pop())
+    }
+
+    /// Like `check_param`, for arguments followed by `count` parameters
+    /// (e.g. "-exportobj maps/e1m1.bsp out"). Returns None unless all of
+    /// them are there and can be parsed.
+    pub fn check_params<T>(&self, argument: &str, count: usize) -> Option<Vec<T>>
+        where T: FromStr
     {
         // Find the index of the argument in the argument list
         self.args.iter().position(|s| s == argument).and_then(|idx| {
-            // Get the value occurring after that index
-            self.args.get(idx + 1).and_then(|arg| {
-                // Don't return other commandline options
-                if arg.starts_with('-') {
-                    None
-                } else {
-                    // Try to parse the value to the desired type
-                    arg.parse::<T>().ok()
-                }
-            })
+            // Get the values occurring after that index
+            let values = &self.args[idx + 1..];
+            if values.len() < count {
+                return None;
+            }
+            values[..count]
+                .iter()
+                .map(|arg| {
+                    // Don't return other commandline options
+                    if arg.starts_with('-') {
+                        None
+                    } else {
+                        // Try to parse the value to the desired type
+                        arg.parse::<T>().ok()
+                    }
+                })
+                .collect()
         })
     }
 
@@ -63,4 +79,15 @@ mod tests {
         let alpha: Option<u32> = options.check_param("-alpha");
         assert_eq!(alpha, Some(50));
     }
+
+    #[test]
+    fn test_parse_multiple_args() {
+        let args = ["-export", "a", "b", "-missing", "c", "-option"];
+        let options = Options::with_args(args.iter().map(|&a| a.into()).collect());
+        let export: Option<Vec<String>> = options.check_params("-export", 2);
+        assert_eq!(export, Some(vec!["a".into(), "b".into()]));
+        assert_eq!(options.check_params::<String>("-export", 3), None);
+        assert_eq!(options.check_params::<String>("-missing", 2), None);
+        assert_eq!(options.check_params::<String>("-option", 1), None);
+    }
 }
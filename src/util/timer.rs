@@ -8,6 +8,9 @@ pub struct Timer {
     total: Duration,
     frame_duration: Duration,
     unlocked: bool,
+    /// If set, time doesn't follow the wall clock but advances by exactly
+    /// this much on every step.
+    fixed_step: Option<Duration>,
 }
 
 impl Timer {
@@ -18,10 +21,23 @@ impl Timer {
             total: Duration::from_millis(0),
             frame_duration: Duration::new(0, (1e9 / MAX_FRAMERATE) as u32),
             unlocked: unlocked,
+            fixed_step: None,
         }
     }
 
+    /// Creates a timer with simulated time: every call to `step` succeeds
+    /// and advances the elapsed time by `step`, however long the frame
+    /// really took.
+    pub fn fixed(step: Duration) -> Timer {
+        Timer { fixed_step: Some(step), ..Timer::new(true) }
+    }
+
     pub fn step(&mut self) -> Option<Duration> {
+        if let Some(step) = self.fixed_step {
+            self.total += step;
+            return Some(step);
+        }
+
         let now = Instant::now();
         let timestep = now.duration_since(self.last_frame);
         self.total = self.start.elapsed();
@@ -52,4 +68,13 @@ mod tests {
         thread::sleep(Duration::from_millis(9));
         assert_eq!(timer.step().is_some(), true);
     }
+
+    #[test]
+    fn test_fixed_step() {
+        let step = Duration::from_millis(40);
+        let mut timer = Timer::fixed(step);
+        assert_eq!(timer.step(), Some(step));
+        assert_eq!(timer.step(), Some(step));
+        assert_eq!(timer.elapsed(), Duration::from_millis(80));
+    }
 }
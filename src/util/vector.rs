@@ -27,6 +27,28 @@ impl ops::Add for Vec2 {
     }
 }
 
+impl ops::Sub for Vec2 {
+    type Output = Vec2;
+
+    fn sub(self, other: Vec2) -> Vec2 {
+        Vec2 {
+            x: self.x - other.x,
+            y: self.y - other.y,
+        }
+    }
+}
+
+impl ops::Neg for Vec2 {
+    type Output = Vec2;
+
+    fn neg(self) -> Vec2 {
+        Vec2 {
+            x: -self.x,
+            y: -self.y,
+        }
+    }
+}
+
 impl ops::Mul<f32> for Vec2 {
     type Output = Vec2;
 
@@ -50,9 +72,66 @@ impl Vec3 {
         Vec3 { x: x, y: y, z: z }
     }
 
+    pub fn zero() -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
     pub fn dot(self, other: Vec3) -> f32 {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
+
+    pub fn cross(self, other: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn length(self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    /// The vector scaled to a length of 1. The zero vector stays zero, like
+    /// in `VectorNormalize`.
+    pub fn normalize(self) -> Vec3 {
+        let length = self.length();
+        if length == 0.0 {
+            self
+        } else {
+            self * (1.0 / length)
+        }
+    }
+
+    /// Component-wise minimum.
+    pub fn min(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x.min(other.x), self.y.min(other.y), self.z.min(other.z))
+    }
+
+    /// Component-wise maximum.
+    pub fn max(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x.max(other.x), self.y.max(other.y), self.z.max(other.z))
+    }
+
+    /// Linear interpolation, `self` at 0 and `other` at 1.
+    pub fn lerp(self, other: Vec3, f: f32) -> Vec3 {
+        self + (other - self) * f
+    }
+}
+
+/// Returns the forward, right and up vectors for Quake angles in degrees
+/// (pitch, yaw, roll). A positive pitch looks down.
+pub fn angle_vectors(angles: Vec3) -> (Vec3, Vec3, Vec3) {
+    let (sp, cp) = angles.x.to_radians().sin_cos();
+    let (sy, cy) = angles.y.to_radians().sin_cos();
+    let (sr, cr) = angles.z.to_radians().sin_cos();
+
+    let forward = Vec3::new(cp * cy, cp * sy, -sp);
+    let right = Vec3::new(-sr * sp * cy + cr * sy,
+                          -sr * sp * sy - cr * cy,
+                          -sr * cp);
+    let up = Vec3::new(cr * sp * cy + sr * sy, cr * sp * sy - sr * cy, cr * cp);
+    (forward, right, up)
 }
 
 impl ops::Add for Vec3 {
@@ -67,6 +146,30 @@ impl ops::Add for Vec3 {
     }
 }
 
+impl ops::Sub for Vec3 {
+    type Output = Vec3;
+
+    fn sub(self, other: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.x - other.x,
+            y: self.y - other.y,
+            z: self.z - other.z,
+        }
+    }
+}
+
+impl ops::Neg for Vec3 {
+    type Output = Vec3;
+
+    fn neg(self) -> Vec3 {
+        Vec3 {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
 impl ops::Mul<f32> for Vec3 {
     type Output = Vec3;
 
@@ -79,9 +182,378 @@ impl ops::Mul<f32> for Vec3 {
     }
 }
 
+impl ops::Div<f32> for Vec3 {
+    type Output = Vec3;
+
+    fn div(self, t: f32) -> Vec3 {
+        Vec3 {
+            x: self.x / t,
+            y: self.y / t,
+            z: self.z / t,
+        }
+    }
+}
+
+impl ops::AddAssign for Vec3 {
+    fn add_assign(&mut self, other: Vec3) {
+        *self = *self + other;
+    }
+}
+
+impl ops::SubAssign for Vec3 {
+    fn sub_assign(&mut self, other: Vec3) {
+        *self = *self - other;
+    }
+}
+
+impl ops::MulAssign<f32> for Vec3 {
+    fn mul_assign(&mut self, t: f32) {
+        *self = *self * t;
+    }
+}
+
+/// Components by index, x being 0, like the `vec3_t` arrays of the original.
+impl ops::Index<usize> for Vec3 {
+    type Output = f32;
+
+    fn index(&self, i: usize) -> &f32 {
+        match i {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range: {}", i),
+        }
+    }
+}
+
+impl ops::IndexMut<usize> for Vec3 {
+    fn index_mut(&mut self, i: usize) -> &mut f32 {
+        match i {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            2 => &mut self.z,
+            _ => panic!("Vec3 index out of range: {}", i),
+        }
+    }
+}
+
+/// A 3x3 matrix of rows, for rotations.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mat3 {
+    pub rows: [[f32; 3]; 3],
+}
+
+impl Mat3 {
+    pub fn new(rows: [[f32; 3]; 3]) -> Mat3 {
+        Mat3 { rows: rows }
+    }
+
+    pub fn identity() -> Mat3 {
+        Mat3::new([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+    }
+
+    /// The matrix with the given vectors as rows.
+    pub fn from_rows(a: Vec3, b: Vec3, c: Vec3) -> Mat3 {
+        Mat3::new([[a.x, a.y, a.z], [b.x, b.y, b.z], [c.x, c.y, c.z]])
+    }
+
+    /// Rotation by `degrees` counter-clockwise about `axis` (looking
+    /// against it), like `RotatePointAroundVector`.
+    pub fn rotation(axis: Vec3, degrees: f32) -> Mat3 {
+        let a = axis.normalize();
+        let (s, c) = degrees.to_radians().sin_cos();
+        let t = 1.0 - c;
+        Mat3::new([[t * a.x * a.x + c, t * a.x * a.y - s * a.z, t * a.x * a.z + s * a.y],
+                   [t * a.x * a.y + s * a.z, t * a.y * a.y + c, t * a.y * a.z - s * a.x],
+                   [t * a.x * a.z - s * a.y, t * a.y * a.z + s * a.x, t * a.z * a.z + c]])
+    }
+
+    pub fn row(&self, i: usize) -> Vec3 {
+        let r = self.rows[i];
+        Vec3::new(r[0], r[1], r[2])
+    }
+
+    pub fn transpose(&self) -> Mat3 {
+        let m = &self.rows;
+        Mat3::new([[m[0][0], m[1][0], m[2][0]],
+                   [m[0][1], m[1][1], m[2][1]],
+                   [m[0][2], m[1][2], m[2][2]]])
+    }
+
+    pub fn determinant(&self) -> f32 {
+        self.row(0).dot(self.row(1).cross(self.row(2)))
+    }
+}
+
+impl ops::Mul for Mat3 {
+    type Output = Mat3;
+
+    fn mul(self, other: Mat3) -> Mat3 {
+        let mut rows = [[0.0; 3]; 3];
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| self.rows[i][k] * other.rows[k][j]).sum();
+            }
+        }
+        Mat3::new(rows)
+    }
+}
+
+impl ops::Mul<Vec3> for Mat3 {
+    type Output = Vec3;
+
+    fn mul(self, v: Vec3) -> Vec3 {
+        Vec3::new(self.row(0).dot(v), self.row(1).dot(v), self.row(2).dot(v))
+    }
+}
+
+/// A 4x4 matrix of rows, for affine transforms of points.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mat4 {
+    pub rows: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn new(rows: [[f32; 4]; 4]) -> Mat4 {
+        Mat4 { rows: rows }
+    }
+
+    pub fn identity() -> Mat4 {
+        Mat4::from_mat3(&Mat3::identity())
+    }
+
+    /// The rotation or scale `m` without translation.
+    pub fn from_mat3(m: &Mat3) -> Mat4 {
+        let r = &m.rows;
+        Mat4::new([[r[0][0], r[0][1], r[0][2], 0.0],
+                   [r[1][0], r[1][1], r[1][2], 0.0],
+                   [r[2][0], r[2][1], r[2][2], 0.0],
+                   [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn translation(offset: Vec3) -> Mat4 {
+        let mut m = Mat4::identity();
+        m.rows[0][3] = offset.x;
+        m.rows[1][3] = offset.y;
+        m.rows[2][3] = offset.z;
+        m
+    }
+
+    pub fn rotation(axis: Vec3, degrees: f32) -> Mat4 {
+        Mat4::from_mat3(&Mat3::rotation(axis, degrees))
+    }
+
+    pub fn scale(factors: Vec3) -> Mat4 {
+        let mut m = Mat4::identity();
+        m.rows[0][0] = factors.x;
+        m.rows[1][1] = factors.y;
+        m.rows[2][2] = factors.z;
+        m
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut rows = [[0.0; 4]; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.rows[j][i];
+            }
+        }
+        Mat4::new(rows)
+    }
+
+    /// Transforms a point, including the translation.
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.rows;
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        let row = |r: &[f32; 4]| (r[0] * p.x + r[1] * p.y + r[2] * p.z + r[3]) / w;
+        Vec3::new(row(&m[0]), row(&m[1]), row(&m[2]))
+    }
+
+    /// Transforms a direction, ignoring the translation.
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let row = |r: &[f32; 4]| r[0] * v.x + r[1] * v.y + r[2] * v.z;
+        Vec3::new(row(&self.rows[0]), row(&self.rows[1]), row(&self.rows[2]))
+    }
+}
+
+impl ops::Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut rows = [[0.0; 4]; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.rows[i][k] * other.rows[k][j]).sum();
+            }
+        }
+        Mat4::new(rows)
+    }
+}
+
+/// Which side of a plane a box is on, like the 1, 2 and 3 returned by
+/// `BoxOnPlaneSide`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BoxSide {
+    Front,
+    Back,
+    /// The plane goes through the box.
+    Both,
+}
+
+/// A plane of the points `p` with `normal.dot(p) == dist`, for clipping and
+/// culling. Unlike `files::bsp::Plane` it keeps the sign bits that
+/// `Aabb::plane_side` needs.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ClipPlane {
+    pub normal: Vec3,
+    pub dist: f32,
+    /// Bit `i` is set if component `i` of the normal is negative, to find
+    /// the box corners nearest and furthest from the plane quickly.
+    pub signbits: u8,
+}
+
+impl ClipPlane {
+    pub fn new(normal: Vec3, dist: f32) -> ClipPlane {
+        let signbits = (0..3).filter(|&i| normal[i] < 0.0).fold(0, |bits, i| bits | 1 << i);
+        ClipPlane {
+            normal: normal,
+            dist: dist,
+            signbits: signbits,
+        }
+    }
+
+    /// The plane through three points, facing the side from which they
+    /// appear counter-clockwise.
+    pub fn from_points(a: Vec3, b: Vec3, c: Vec3) -> ClipPlane {
+        let normal = (b - a).cross(c - a).normalize();
+        ClipPlane::new(normal, normal.dot(a))
+    }
+
+    /// Signed distance of a point, positive in front.
+    pub fn distance(&self, point: Vec3) -> f32 {
+        self.normal.dot(point) - self.dist
+    }
+
+    /// Which side of the plane the box from `mins` to `maxs` is on, like
+    /// `BoxOnPlaneSide`. Boxes touching the plane from the front are in
+    /// front.
+    pub fn box_on_plane_side(&self, mins: Vec3, maxs: Vec3) -> BoxSide {
+        // The corners furthest in front and behind
+        let mut front = maxs;
+        let mut back = mins;
+        for i in 0..3 {
+            if self.signbits & (1 << i) != 0 {
+                front[i] = mins[i];
+                back[i] = maxs[i];
+            }
+        }
+        match (self.distance(front) >= 0.0, self.distance(back) < 0.0) {
+            (true, true) => BoxSide::Both,
+            (false, _) => BoxSide::Back,
+            (true, false) => BoxSide::Front,
+        }
+    }
+}
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub mins: Vec3,
+    pub maxs: Vec3,
+}
+
+impl Aabb {
+    pub fn new(mins: Vec3, maxs: Vec3) -> Aabb {
+        Aabb {
+            mins: mins,
+            maxs: maxs,
+        }
+    }
+
+    /// A box containing nothing, which grows to the first point added.
+    pub fn empty() -> Aabb {
+        let inf = f32::INFINITY;
+        Aabb::new(Vec3::new(inf, inf, inf), Vec3::new(-inf, -inf, -inf))
+    }
+
+    /// The smallest box around all `points`.
+    pub fn from_points(points: &[Vec3]) -> Aabb {
+        let mut aabb = Aabb::empty();
+        for &p in points {
+            aabb.add_point(p);
+        }
+        aabb
+    }
+
+    /// A cube around a sphere.
+    pub fn around(center: Vec3, radius: f32) -> Aabb {
+        let r = Vec3::new(radius, radius, radius);
+        Aabb::new(center - r, center + r)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mins.x > self.maxs.x || self.mins.y > self.maxs.y || self.mins.z > self.maxs.z
+    }
+
+    pub fn add_point(&mut self, p: Vec3) {
+        self.mins = self.mins.min(p);
+        self.maxs = self.maxs.max(p);
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(self.mins.min(other.mins), self.maxs.max(other.maxs))
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.mins + self.maxs) * 0.5
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.maxs - self.mins
+    }
+
+    /// Whether a point is inside or on the box.
+    pub fn contains(&self, p: Vec3) -> bool {
+        (0..3).all(|i| p[i] >= self.mins[i] && p[i] <= self.maxs[i])
+    }
+
+    /// Whether two boxes overlap or touch.
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (0..3).all(|i| self.mins[i] <= other.maxs[i] && other.mins[i] <= self.maxs[i])
+    }
+
+    /// The box moved by `offset`.
+    pub fn translate(&self, offset: Vec3) -> Aabb {
+        Aabb::new(self.mins + offset, self.maxs + offset)
+    }
+
+    /// The eight corners, with bit `i` of the index choosing `maxs` for
+    /// component `i`.
+    pub fn corners(&self) -> [Vec3; 8] {
+        let mut corners = [self.mins; 8];
+        for (n, corner) in corners.iter_mut().enumerate() {
+            for i in 0..3 {
+                if n & (1 << i) != 0 {
+                    corner[i] = self.maxs[i];
+                }
+            }
+        }
+        corners
+    }
+
+    /// Which side of `plane` the box is on.
+    pub fn plane_side(&self, plane: &ClipPlane) -> BoxSide {
+        plane.box_on_plane_side(self.mins, self.maxs)
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{Vec2, Vec3};
+    use super::{Aabb, BoxSide, ClipPlane, Mat3, Mat4, Vec2, Vec3, angle_vectors};
+
+    fn near(a: Vec3, b: Vec3) -> bool {
+        (a.x - b.x).abs() + (a.y - b.y).abs() + (a.z - b.z).abs() < 1e-6
+    }
 
     #[test]
     fn test_vec2_add() {
@@ -128,4 +600,187 @@ mod test {
         let result = 30.0;
         assert_eq!(v1.dot(v1), result);
     }
+
+    #[test]
+    fn test_vec3_sub() {
+        let v1 = Vec3::new(5.0, 3.0, 2.0);
+        let v2 = Vec3::new(1.0, 4.0, 2.0);
+        let result = Vec3::new(4.0, -1.0, 0.0);
+        assert_eq!(v1 - v2, result);
+    }
+
+    #[test]
+    fn test_vec3_neg() {
+        let v1 = Vec3::new(5.0, -3.0, 0.0);
+        let result = Vec3::new(-5.0, 3.0, 0.0);
+        assert_eq!(-v1, result);
+    }
+
+    #[test]
+    fn test_angle_vectors() {
+        let (f, r, u) = angle_vectors(Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!((f, r, u),
+                   (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)));
+        let (f, r, _) = angle_vectors(Vec3::new(0.0, 90.0, 0.0));
+        assert!(near(f, Vec3::new(0.0, 1.0, 0.0)));
+        assert!(near(r, Vec3::new(1.0, 0.0, 0.0)));
+        let (f, _, u) = angle_vectors(Vec3::new(90.0, 0.0, 0.0));
+        assert!(near(f, Vec3::new(0.0, 0.0, -1.0)));
+        assert!(near(u, Vec3::new(1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_vec2_sub_neg() {
+        assert_eq!(Vec2::new(5.0, 2.0) - Vec2::new(1.0, 3.0), Vec2::new(4.0, -1.0));
+        assert_eq!(-Vec2::new(5.0, -2.0), Vec2::new(-5.0, 2.0));
+    }
+
+    #[test]
+    fn test_vec3_cross() {
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        assert_eq!(x.cross(y), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(y.cross(x), Vec3::new(0.0, 0.0, -1.0));
+        let a = Vec3::new(1.0, 2.0, 3.0);
+        let b = Vec3::new(-2.0, 0.5, 4.0);
+        assert_eq!(a.cross(b).dot(a), 0.0);
+        assert_eq!(a.cross(b).dot(b), 0.0);
+    }
+
+    #[test]
+    fn test_vec3_length_normalize() {
+        let v = Vec3::new(3.0, 0.0, -4.0);
+        assert_eq!(v.length(), 5.0);
+        assert_eq!(v.normalize(), Vec3::new(0.6, 0.0, -0.8));
+        assert_eq!(Vec3::zero().normalize(), Vec3::zero());
+        assert_eq!(v / 2.0, Vec3::new(1.5, 0.0, -2.0));
+        assert_eq!(Vec3::zero().lerp(v, 0.5), Vec3::new(1.5, 0.0, -2.0));
+        assert_eq!(v.min(Vec3::new(1.0, 1.0, 1.0)), Vec3::new(1.0, 0.0, -4.0));
+        assert_eq!(v.max(Vec3::new(1.0, 1.0, 1.0)), Vec3::new(3.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_vec3_assign_and_index() {
+        let mut v = Vec3::new(1.0, 2.0, 3.0);
+        v += Vec3::new(1.0, 1.0, 1.0);
+        v -= Vec3::new(0.0, 0.0, 2.0);
+        v *= 2.0;
+        assert_eq!(v, Vec3::new(4.0, 6.0, 4.0));
+        assert_eq!((v[0], v[1], v[2]), (4.0, 6.0, 4.0));
+        v[1] = -1.0;
+        assert_eq!(v.y, -1.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_vec3_index_out_of_range() {
+        assert_eq!(Vec3::zero()[3], 0.0);
+    }
+
+    #[test]
+    fn test_mat3() {
+        let m = Mat3::new([[1.0, 2.0, 3.0], [0.0, 1.0, 4.0], [5.0, 6.0, 0.0]]);
+        assert_eq!(m * Mat3::identity(), m);
+        assert_eq!(m.transpose().row(0), Vec3::new(1.0, 0.0, 5.0));
+        assert_eq!(m * Vec3::new(1.0, 1.0, 1.0), Vec3::new(6.0, 5.0, 11.0));
+        assert_eq!(m.determinant(), 1.0);
+        let (f, r, u) = angle_vectors(Vec3::new(30.0, 60.0, 10.0));
+        let axes = Mat3::from_rows(f, r, u);
+        let product = axes * axes.transpose();
+        for i in 0..3 {
+            assert!(near(product.row(i), Mat3::identity().row(i)));
+        }
+    }
+
+    #[test]
+    fn test_rotation() {
+        // Counter-clockwise about +z turns +x into +y
+        let z = Mat3::rotation(Vec3::new(0.0, 0.0, 2.0), 90.0);
+        assert!(near(z * Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)));
+        // A third of a turn about the diagonal cycles the axes
+        let diagonal = Mat3::rotation(Vec3::new(1.0, 1.0, 1.0), 120.0);
+        assert!(near(diagonal * Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)));
+        assert!(near(diagonal * Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)));
+        // Points on the axis stay
+        assert!(near(diagonal * Vec3::new(2.0, 2.0, 2.0), Vec3::new(2.0, 2.0, 2.0)));
+        assert!((diagonal.determinant() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_mat4() {
+        let p = Vec3::new(1.0, 2.0, 3.0);
+        let offset = Vec3::new(10.0, 0.0, -5.0);
+        assert_eq!(Mat4::identity().transform_point(p), p);
+        assert_eq!(Mat4::translation(offset).transform_point(p), Vec3::new(11.0, 2.0, -2.0));
+        assert_eq!(Mat4::translation(offset).transform_vector(p), p);
+        let scaled = Mat4::scale(Vec3::new(2.0, 2.0, 2.0));
+        assert_eq!(scaled.transform_point(p), p * 2.0);
+
+        // Rotated about the origin first, then moved
+        let m = Mat4::translation(offset) * Mat4::rotation(Vec3::new(0.0, 0.0, 1.0), 90.0);
+        assert!(near(m.transform_point(p), Vec3::new(8.0, 1.0, -2.0)));
+        assert!(near(m.transform_vector(p), Vec3::new(-2.0, 1.0, 3.0)));
+        assert_eq!(m.transpose().transpose(), m);
+        assert_eq!(m.transpose().rows[3][0], 10.0);
+    }
+
+    #[test]
+    fn test_plane() {
+        let plane = ClipPlane::new(Vec3::new(0.0, -1.0, 0.0), 8.0);
+        assert_eq!(plane.signbits, 2);
+        assert_eq!(plane.distance(Vec3::new(5.0, -10.0, 3.0)), 2.0);
+        assert_eq!(plane.distance(Vec3::new(5.0, 0.0, 3.0)), -8.0);
+
+        let floor = ClipPlane::from_points(Vec3::new(0.0, 0.0, 4.0),
+                                           Vec3::new(1.0, 0.0, 4.0),
+                                           Vec3::new(0.0, 1.0, 4.0));
+        assert_eq!((floor.normal, floor.dist), (Vec3::new(0.0, 0.0, 1.0), 4.0));
+        assert_eq!(ClipPlane::new(Vec3::new(-1.0, 1.0, -1.0), 0.0).signbits, 5);
+    }
+
+    #[test]
+    fn test_box_on_plane_side() {
+        let diagonal = ClipPlane::new(Vec3::new(-1.0, 1.0, 0.0).normalize(), 0.0);
+        let side = |x: f32, y: f32| {
+            diagonal.box_on_plane_side(Vec3::new(x - 1.0, y - 1.0, -1.0),
+                                       Vec3::new(x + 1.0, y + 1.0, 1.0))
+        };
+        assert_eq!(side(0.0, 5.0), BoxSide::Front);
+        assert_eq!(side(5.0, 0.0), BoxSide::Back);
+        assert_eq!(side(0.0, 0.0), BoxSide::Both);
+        assert_eq!(side(3.0, 3.5), BoxSide::Both);
+        // Touching from the front
+        assert_eq!(side(-1.0, 1.0), BoxSide::Front);
+        let aabb = Aabb::new(Vec3::new(-1.0, 4.0, 0.0), Vec3::new(1.0, 6.0, 1.0));
+        assert_eq!(aabb.plane_side(&diagonal), BoxSide::Front);
+    }
+
+    #[test]
+    fn test_aabb() {
+        let mut aabb = Aabb::empty();
+        assert!(aabb.is_empty());
+        aabb.add_point(Vec3::new(1.0, 2.0, 3.0));
+        assert!(!aabb.is_empty());
+        assert_eq!(aabb.size(), Vec3::zero());
+
+        let aabb = Aabb::from_points(&[Vec3::new(1.0, -2.0, 0.0),
+                                       Vec3::new(-1.0, 2.0, 4.0),
+                                       Vec3::new(0.0, 0.0, 1.0)]);
+        assert_eq!(aabb, Aabb::new(Vec3::new(-1.0, -2.0, 0.0), Vec3::new(1.0, 2.0, 4.0)));
+        assert_eq!(aabb.center(), Vec3::new(0.0, 0.0, 2.0));
+        assert_eq!(aabb.size(), Vec3::new(2.0, 4.0, 4.0));
+        assert!(aabb.contains(Vec3::new(1.0, 0.0, 4.0)));
+        assert!(!aabb.contains(Vec3::new(1.5, 0.0, 0.0)));
+
+        let cube = Aabb::around(Vec3::new(3.0, 0.0, 0.0), 2.0);
+        assert!(aabb.intersects(&cube));
+        assert!(!aabb.intersects(&cube.translate(Vec3::new(0.1, 0.0, 0.0))));
+        assert_eq!(aabb.union(&cube).maxs, Vec3::new(5.0, 2.0, 4.0));
+        assert_eq!(Aabb::empty().union(&cube), cube);
+
+        let corners = cube.corners();
+        assert_eq!((corners[0], corners[7]), (cube.mins, cube.maxs));
+        assert_eq!(corners[1], Vec3::new(5.0, -2.0, -2.0));
+        assert_eq!(Aabb::from_points(&corners), cube);
+    }
 }